@@ -23,11 +23,16 @@ fn main() -> Result<()> {
     // 配置
     let rpc_url = "https://testnet-rpc.1024chain.com/rpc/";
     let program_id = Pubkey::from_str("E3ea5jEUvTojcKiJWayNVTJ16gU52zkfLJArgudAUCFw")?;
+    // 已存在的USDC托管账户，owner必须是Trading Vault PDA
     let usdc_account = Pubkey::from_str("GaYo5tic9mdV4sp6JmP2DXntWve5Sw6SDnQzcfMcvFxe")?;
+    let usdc_mint = Pubkey::from_str(
+        &std::env::var("USDC_MINT").map_err(|_| anyhow::anyhow!("USDC_MINT env var not set"))?,
+    )?;
     
     println!("📋 配置:");
     println!("  Program ID: {}", program_id);
     println!("  USDC Account: {}", usdc_account);
+    println!("  USDC Mint: {}", usdc_mint);
     println!();
     
     // 读取authority keypair
@@ -85,6 +90,7 @@ fn main() -> Result<()> {
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_sdk::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(usdc_mint, false),
     ];
     
     let instruction = Instruction {
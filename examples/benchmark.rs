@@ -145,6 +145,7 @@ async fn benchmark_calculations() -> Result<()> {
     println!("========================================");
     
     use trading_program::utils::*;
+    use trading_program::state::RiskParams;
     
     // 测试IM计算
     let iterations = 1_000_000;
@@ -179,7 +180,7 @@ async fn benchmark_calculations() -> Result<()> {
     let start = Instant::now();
     
     for i in 0..iterations {
-        let _ = is_liquidatable((i % 20000) as u32);
+        let _ = RiskParams::DEFAULT.liquidation_status((i % 20000) as u32);
     }
     
     let elapsed = start.elapsed();
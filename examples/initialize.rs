@@ -74,19 +74,24 @@ fn main() -> Result<()> {
     println!("  Trading Vault PDA: {}", vault_pda);
     println!();
     
-    // TODO: 创建Vault USDC Token Account
-    // 这里需要先创建Associated Token Account
-    println!("⚠️  Warning: You need to manually create Vault USDC Account");
-    println!("    Use: spl-token create-account <USDC_MINT>");
+    // Vault USDC托管账户由InitializeVault创建（PDA: [b"vault_usdc"]）
+    let (vault_usdc_account, _usdc_bump) = Pubkey::find_program_address(
+        &[b"vault_usdc"],
+        &program_id,
+    );
+    
+    let usdc_mint = std::env::var("USDC_MINT")
+        .map(|s| Pubkey::from_str(&s).expect("Invalid USDC_MINT"))
+        .expect("USDC_MINT env var not set");
+    
+    println!("  Vault USDC Account: {}", vault_usdc_account);
+    println!("  USDC Mint: {}", usdc_mint);
     println!();
     
     // 构造InitializeVault instruction
     let instruction_data = TradingInstruction::InitializeVault;
     let data = instruction_data.try_to_vec()?;
     
-    // Note: 这里需要实际的vault_usdc_account地址
-    let vault_usdc_account = Pubkey::new_unique(); // 临时placeholder
-    
     let accounts = vec![
         AccountMeta::new(vault_pda, false),
         AccountMeta::new(vault_usdc_account, false),
@@ -94,6 +99,7 @@ fn main() -> Result<()> {
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_sdk::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(usdc_mint, false),
     ];
     
     let instruction = Instruction {
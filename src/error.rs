@@ -88,6 +88,135 @@ pub enum TradingError {
     
     #[error("Insurance Fund Insufficient")]
     InsuranceFundInsufficient,
+    
+    #[error("Invalid Insurance Fund Account")]
+    InvalidInsuranceFundAccount,
+    
+    #[error("Unauthorized Relayer")]
+    UnauthorizedRelayer,
+    
+    #[error("Invalid Token Program")]
+    InvalidTokenProgram,
+    
+    #[error("Invalid System Program")]
+    InvalidSystemProgram,
+    
+    #[error("Invalid Token Account")]
+    InvalidTokenAccount,
+    
+    #[error("Invalid Token Account Owner")]
+    InvalidTokenAccountOwner,
+    
+    #[error("Invalid Token Mint")]
+    InvalidTokenMint,
+    
+    #[error("Invalid Vault Token Account")]
+    InvalidVaultTokenAccount,
+    
+    #[error("Invalid Fee Treasury Account")]
+    InvalidFeeTreasuryAccount,
+    
+    #[error("Invalid Market Config Account")]
+    InvalidMarketConfigAccount,
+    
+    #[error("Market Not Active")]
+    MarketNotActive,
+    
+    #[error("Market Delisted")]
+    MarketDelisted,
+    
+    #[error("Invalid Tick Size")]
+    InvalidTickSize,
+    
+    #[error("Invalid Lot Size")]
+    InvalidLotSize,
+    
+    #[error("Invalid Market Config")]
+    InvalidMarketConfig,
+    
+    #[error("Invalid Risk Bracket")]
+    InvalidRiskBracket,
+    
+    #[error("Position Notional Exceeds Max Bracket")]
+    PositionNotionalTooLarge,
+    
+    #[error("Leverage Exceeds Bracket Max")]
+    LeverageExceedsBracket,
+    
+    #[error("Invalid Oracle Account")]
+    InvalidOracleAccount,
+    
+    #[error("Oracle Not Configured")]
+    OracleNotConfigured,
+    
+    #[error("Oracle Price Stale")]
+    OraclePriceStale,
+    
+    #[error("Oracle Confidence Too Wide")]
+    OracleConfidenceTooWide,
+    
+    #[error("Price Deviates From Oracle")]
+    OraclePriceDeviation,
+    
+    #[error("Invalid Fee Rate")]
+    InvalidFeeRate,
+    
+    #[error("Invalid Funding State Account")]
+    InvalidFundingAccount,
+    
+    #[error("Unauthorized Keeper")]
+    UnauthorizedKeeper,
+    
+    #[error("Invalid Funding Rate")]
+    InvalidFundingRate,
+    
+    #[error("ADL Not Required")]
+    AdlNotRequired,
+    
+    #[error("Invalid ADL Target")]
+    InvalidAdlTarget,
+    
+    #[error("ADL Ranking Violation")]
+    AdlRankingViolation,
+    
+    #[error("Invalid Bankruptcy Price")]
+    InvalidBankruptcyPrice,
+    
+    #[error("Invalid Cross Margin Account")]
+    InvalidCrossMarginAccount,
+    
+    #[error("Too Many Cross Margin Positions")]
+    TooManyCrossPositions,
+    
+    #[error("Cross Margin Positions Mismatch")]
+    CrossPositionsMismatch,
+    
+    #[error("Margin Mode Mismatch")]
+    MarginModeMismatch,
+    
+    #[error("Leverage Mismatch")]
+    LeverageMismatch,
+    
+    #[error("Invalid Conditional Order")]
+    InvalidConditionalOrder,
+    
+    #[error("Conditional Order Not Triggered")]
+    ConditionalOrderNotTriggered,
+    
+    #[error("Trading Mode Is Reduce Only")]
+    TradingReduceOnly,
+    
+    #[error("Trading Halted")]
+    TradingHalted,
+    
+    #[error("Program Paused")]
+    ProgramPaused,
+    
+    #[error("Unauthorized Guardian")]
+    UnauthorizedGuardian,
+    
+    #[error("Trading Vault Resize Required")]
+    VaultResizeRequired,
 }
 
 impl From<TradingError> for ProgramError {
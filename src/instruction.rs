@@ -1,7 +1,7 @@
 //! Trading Program Instructions
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use crate::state::{Side, MarginMode};
+use crate::state::{Side, MarginMode, MarketStatus, RiskBracket, RiskParams, TradingMode, TriggerDirection};
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub enum TradingInstruction {
@@ -10,22 +10,50 @@ pub enum TradingInstruction {
     /// Accounts:
     /// 0. `[writable]` TradingVault PDA - 将被创建
     /// 1. `[writable]` Vault USDC Account - 托管账户
+    ///    （不存在时必须是 [b"vault_usdc"] PDA，将被创建；已存在时owner必须是Vault PDA）
     /// 2. `[signer, writable]` Authority (管理员) - 支付租金
     /// 3. `[]` System Program
     /// 4. `[]` Token Program
     /// 5. `[]` Rent Sysvar
+    /// 6. `[]` USDC Mint
+    /// 
+    /// 初始化后需调用SetTreasuryAccounts登记Insurance Fund和Fee Treasury账户
     InitializeVault,
     
     /// 开仓锁定保证金（Lock Margin）
     /// 
+    /// 与现有持仓反向时先对冲：对冲部分实现盈亏并释放保证金，
+    /// 剩余部分按新方向开仓，与新开仓IM净额结算
+    /// 
+    /// 全仓模式：IM从Cross Margin Account的可用抵押品中划转，抵押品扣除全部全仓持仓
+    /// 未实现亏损后不足占用IM的部分由用户补足。
+    /// 逐仓模式：账户已Deposit时IM和手续费优先从可用抵押品（扣除全仓持仓未实现亏损）划转，
+    /// 释放的保证金计入抵押品。
+    /// 保证金模式必须与现有持仓一致；同方向加仓时杠杆必须与持仓一致（先AdjustLeverage），
+    /// 反向开仓部分使用本次杠杆
+    /// 
+    /// 双向持仓（Hedge Mode）：账户0传入本方向的双向持仓PDA即可同时持有多空，
+    /// 反向下单不对冲，减仓使用UnlockMargin。同一市场的单向持仓和双向持仓相互独立
+    /// 
     /// Accounts:
-    /// 0. `[writable]` User Position PDA - 将被创建或更新
-    /// 1. `[signer]` User - 用户钱包
-    /// 2. `[writable]` User USDC Account - 用户USDC账户
-    /// 3. `[writable]` Vault USDC Account - 托管USDC账户
+    /// 0. `[writable]` User Position PDA - 将被创建、更新，全部对冲时关闭
+    ///     单向持仓Seeds: [b"position", wallet, account_id, market]
+    ///     双向持仓Seeds: [b"position", wallet, account_id, market, side]
+    /// 1. `[signer, writable]` User - 用户钱包（支付/收回持仓租金）
+    /// 2. `[writable]` User USDC Account - 用户USDC账户（owner = User）
+    /// 3. `[writable]` Vault USDC Account - 托管USDC账户（= TradingVault.vault_usdc_account）
     /// 4. `[writable]` Trading Vault - 全局状态
     /// 5. `[]` Token Program
     /// 6. `[]` System Program
+    /// 7. `[]` Market Config PDA - 状态必须为Active
+    /// 8. `[]` Price Feed Account - = MarketConfig.oracle，entry_price必须在band内
+    /// 9. `[writable]` Fee Treasury Account - 收取开仓手续费（owner = Vault PDA）
+    /// 10. `[writable]` Funding State PDA - 加仓前结算资金费，新仓记录当前index，登记ADL持仓数
+    /// 11. `[writable]` Cross Margin Account PDA - 账户抵押品，全仓模式不存在时创建
+    ///     Seeds: [b"cross_margin", wallet, account_id]
+    /// 12. `[signer]` Relayer（可选）- 联合签名表示Maker成交，否则按Taker费率收费
+    /// 13. `[]` 账户抵押品存在时：账户其他全仓持仓 - 与CrossMarginAccount.positions（不含本持仓）一致；
+    ///     每个持仓依次传入 [持仓PDA, 该持仓的Market Config PDA, 该市场的Price Feed Account]
     LockMargin {
         account_id: String,         // 账户ID（如 "test_isolated"）
         market: String,             // 市场（如 "BTC-PERP"）
@@ -39,12 +67,19 @@ pub enum TradingInstruction {
     /// 平仓返还保证金（Unlock Margin）
     /// 
     /// Accounts:
-    /// 0. `[writable]` User Position PDA
-    /// 1. `[signer]` User - 用户钱包
+    /// 0. `[writable]` User Position PDA - 全部平仓时关闭（双向持仓传入要平的方向的PDA）
+    /// 1. `[signer, writable]` User - 用户钱包（全部平仓时收回租金）
     /// 2. `[writable]` User USDC Account
-    /// 3. `[writable]` Vault USDC Account
+    /// 3. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
     /// 4. `[writable]` Trading Vault
     /// 5. `[]` Token Program
+    /// 6. `[writable]` Insurance Fund Account - 穿仓时补足亏损（owner = Vault PDA）
+    /// 7. `[]` Market Config PDA
+    /// 8. `[]` Price Feed Account - = MarketConfig.oracle，exit_price必须在band内
+    /// 9. `[writable]` Fee Treasury Account - 收取平仓手续费（owner = Vault PDA）
+    /// 10. `[writable]` Funding State PDA - 平仓前结算资金费，穿仓产生坏账时记录破产价格（供ADL使用）
+    /// 11. `[writable]` Cross Margin Account PDA - 账户抵押品，存在时返还金额计入抵押品（不转给用户）
+    /// 12. `[signer]` Relayer（可选）- 联合签名表示Maker成交，否则按Taker费率收费
     UnlockMargin {
         account_id: String,
         market: String,
@@ -55,16 +90,31 @@ pub enum TradingInstruction {
     /// 强平（Liquidation）
     /// 
     /// Accounts:
-    /// 0. `[writable]` User Position PDA - 被强平的持仓
+    /// 0. `[writable]` User Position PDA - 被强平的持仓（强平后关闭，双向持仓按方向分别强平）
     /// 1. `[]` Position Owner - 持仓所有者
-    /// 2. `[signer]` Liquidator - 清算人
-    /// 3. `[writable]` Liquidator USDC Account - 清算人收费账户
-    /// 4. `[writable]` User USDC Account - 用户账户（返还剩余）
-    /// 5. `[writable]` Vault USDC Account
-    /// 6. `[writable]` Insurance Fund Account
-    /// 7. `[writable]` Fee Treasury Account
+    /// 2. `[signer, writable]` Liquidator - 清算人（获得持仓租金作为奖励）
+    /// 3. `[writable]` Liquidator USDC Account - 清算人收费账户（owner = Liquidator）
+    /// 4. `[writable]` User USDC Account - 用户账户（返还剩余，owner = Position Owner）
+    /// 5. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 6. `[writable]` Insurance Fund Account（owner = Vault PDA，穿仓时补足亏损）
+    /// 7. `[writable]` Fee Treasury Account（owner = Vault PDA）
     /// 8. `[writable]` Trading Vault
     /// 9. `[]` Token Program
+    /// 10. `[]` Market Config PDA
+    /// 11. `[]` Price Feed Account - = MarketConfig.oracle，liquidation_price必须在band内
+    /// 12. `[writable]` Funding State PDA - 强平前结算资金费，穿仓产生坏账时记录破产价格（供ADL使用）
+    /// 13. `[writable]` Cross Margin Account PDA - 仅全仓持仓
+    /// 14. `[]` 账户其他全部全仓持仓 - 仅全仓持仓，与CrossMarginAccount.positions一致；
+    ///     每个持仓依次传入 [持仓PDA, 该持仓的Market Config PDA, 该市场的Price Feed Account]
+    /// 
+    /// 市场配置了partial_liquidation_target_bp时只平掉使保证金率恢复到目标值所需的数量，
+    /// 清算费只按被平部分收取，剩余持仓保留
+    /// 
+    /// 全仓持仓按账户判断：账户权益（抵押品 + 全部全仓持仓按预言机价格计算的未实现盈亏）低于全部持仓MM时才可强平，
+    /// 整个持仓被平掉，穿仓亏损先由账户可用抵押品承担
+    /// 
+    /// 未登记在CrossMarginAccount.positions中的旧全仓持仓（引入账户抵押品之前开立）按逐仓处理，
+    /// 此时无需传入账户14及之后的持仓
     Liquidate {
         account_id: String,
         market: String,
@@ -74,8 +124,12 @@ pub enum TradingInstruction {
     /// 更新持仓标记价格和未实现盈亏（链下定期调用）
     /// 
     /// Accounts:
-    /// 0. `[writable]` User Position PDA
-    /// 1. `[signer]` Authority (Relayer)
+    /// 0. `[writable]` User Position PDA - 双向持仓传入对应方向的PDA
+    /// 1. `[signer]` Relayer - 必须是TradingVault.relayer
+    /// 2. `[writable]` Trading Vault - 无法收取的资金费计入坏账
+    /// 3. `[writable]` Funding State PDA - 结算资金费，旧持仓登记到ADL持仓数
+    /// 4. `[]` Market Config PDA - 检查市场交易模式
+    /// 5. `[writable]` Cross Margin Account PDA - 仅全仓持仓，资金费同步计入抵押品
     UpdatePosition {
         account_id: String,
         market: String,
@@ -86,13 +140,449 @@ pub enum TradingInstruction {
     /// 紧急提现Insurance Fund（仅管理员）
     /// 
     /// Accounts:
-    /// 0. `[writable]` Insurance Fund Account
-    /// 1. `[writable]` Admin USDC Account
+    /// 0. `[writable]` Insurance Fund Account（owner = Vault PDA）
+    /// 1. `[writable]` Admin USDC Account（owner = Admin）
     /// 2. `[signer]` Admin
     /// 3. `[writable]` Trading Vault
     /// 4. `[]` Token Program
     WithdrawInsuranceFund {
         amount_e6: i64,
     },
+    
+    /// 轮换Relayer公钥（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` Admin - 必须是TradingVault.authority
+    SetRelayer {
+        new_relayer: solana_program::pubkey::Pubkey,
+    },
+    
+    /// 创建市场配置（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA - 将被创建，Seeds: [b"market", market]
+    /// 1. `[signer, writable]` Admin - 支付租金
+    /// 2. `[]` Trading Vault
+    /// 3. `[]` System Program
+    CreateMarket {
+        market: String,
+        max_leverage: u32,
+        maintenance_margin_ratio_bp: u32,
+        liquidation_fee_bp: u32,
+        tick_size_e6: i64,
+        lot_size_e6: i64,
+    },
+    
+    /// 设置市场状态（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    SetMarketStatus {
+        market: String,
+        status: MarketStatus,
+    },
+    
+    /// 更新市场风控参数（仅管理员）
+    /// 
+    /// 只影响新开仓，已有持仓保留开仓时的参数
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    UpdateMarketRiskParams {
+        market: String,
+        max_leverage: u32,
+        risk_params: RiskParams,
+    },
+    
+    /// 将持仓风控参数更新为市场当前参数并重新计算MM（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` User Position PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    /// 3. `[]` Market Config PDA
+    RemarginPosition {
+        wallet: solana_program::pubkey::Pubkey,
+        account_id: String,
+        market: String,
+    },
+    
+    /// 设置市场风险档位（仅管理员）
+    /// 
+    /// 按名义价值升序，最多MarketConfig::MAX_RISK_BRACKETS档；空列表表示不分档
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA
+    /// 1. `[signer, writable]` Admin - 账户扩容时补足租金
+    /// 2. `[]` Trading Vault
+    /// 3. `[]` System Program
+    SetRiskBrackets {
+        market: String,
+        brackets: Vec<RiskBracket>,
+    },
+    
+    /// 设置市场预言机（仅管理员）
+    /// 
+    /// LockMargin / UnlockMargin / Liquidate 提交的价格必须在预言机价格band内
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    /// 3. `[]` Pyth Price Account
+    SetMarketOracle {
+        market: String,
+        max_price_deviation_bp: u32,
+        max_oracle_staleness_secs: u32,
+        max_oracle_confidence_bp: u32,
+    },
+    
+    /// 设置交易手续费率（仅管理员）
+    /// 
+    /// 开仓/平仓按成交名义价值收取，转入Fee Treasury
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` Admin
+    SetTradingFees {
+        maker_fee_bp: u32,
+        taker_fee_bp: u32,
+    },
+    
+    /// 初始化市场资金费状态（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Funding State PDA - 将被创建
+    /// 1. `[signer, writable]` Admin - 支付租金
+    /// 2. `[]` Trading Vault
+    /// 3. `[]` Market Config PDA
+    /// 4. `[]` System Program
+    InitializeFunding {
+        market: String,
+        keeper: solana_program::pubkey::Pubkey,
+        funding_interval_secs: u32,     // 资金费周期（如 3600）
+        max_funding_rate_e9: i64,       // 单周期资金费率上限
+    },
+    
+    /// 按当前资金费率累计funding index（仅Keeper）
+    /// 
+    /// 正费率多头支付空头，负费率空头支付多头
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Funding State PDA
+    /// 1. `[signer]` Keeper - 必须是FundingState.keeper
+    /// 2. `[]` Market Config PDA
+    /// 3. `[]` Price Feed Account - = MarketConfig.oracle
+    /// 4. `[]` Trading Vault - 检查全局交易模式
+    UpdateFunding {
+        market: String,
+        funding_rate_e9: i64,           // 单周期资金费率（e9格式）
+    },
+    
+    /// 更换资金费Keeper（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Funding State PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    SetFundingKeeper {
+        market: String,
+        keeper: solana_program::pubkey::Pubkey,
+    },
+    
+    /// 设置部分强平目标保证金率（仅管理员）
+    /// 
+    /// 0 = 关闭（强平时全部平仓）；否则必须高于市场liquidation_threshold_bp
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    SetPartialLiquidation {
+        market: String,
+        target_margin_ratio_bp: u32,
+    },
+    
+    /// 自动减仓ADL（仅Relayer，该市场穿仓时Insurance Fund不足产生坏账时）
+    /// 
+    /// 穿仓方向、破产价格和未覆盖坏账读取自Funding State中Liquidate/UnlockMargin的记录（两个方向都有时先处理多头），
+    /// 按破产价格强制减仓与穿仓持仓反向的盈利持仓，每单位让渡 |预言机价格 − 破产价格| 的盈利用于抵消坏账。
+    /// 目标持仓由链下按 盈利率 × 杠杆 降序排列，链上按预言机价格验证方向、盈利和排序；
+    /// 之后须传入该市场其余全部反方向持仓，其排名都不能高于最后一个目标，
+    /// 传入的已登记持仓数必须等于Funding State中该方向的持仓数。
+    /// 登记之前开立的持仓在下一次LockMargin/UnlockMargin/UpdatePosition时登记
+    /// 
+    /// 全仓持仓的盈亏在账户抵押品内结算；逐仓持仓账户已Deposit时返还计入抵押品，否则转给持仓所有者
+    /// 
+    /// Accounts:
+    /// 0. `[signer]` Relayer - 必须是TradingVault.relayer
+    /// 1. `[writable]` Trading Vault
+    /// 2. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 3. `[]` Token Program
+    /// 4. `[]` Market Config PDA
+    /// 5. `[]` Price Feed Account - = MarketConfig.oracle
+    /// 6. `[writable]` Funding State PDA - 读取并减少该市场待覆盖的坏账
+    /// 7. 之后每个目标持仓4个账户（按ADL排序，共target_count个）：
+    ///    `[writable]` User Position PDA（全部减仓时关闭）、
+    ///    `[writable]` Position Owner（收回持仓租金）、
+    ///    `[writable]` Owner USDC Account（账户抵押品不存在时接收释放的保证金和盈利）、
+    ///    `[writable]` Cross Margin Account PDA
+    /// 8. `[]` 之后每个其余反方向持仓1个账户：User Position PDA
+    AutoDeleverage {
+        market: String,
+        target_count: u8,
+    },
+    
+    /// 存入账户抵押品（Cross Margin Account不存在时创建）
+    /// 
+    /// 抵押品可用于全仓持仓，也可在逐仓开仓时代替钱包转账
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Cross Margin Account PDA - Seeds: [b"cross_margin", wallet, account_id]
+    /// 1. `[signer, writable]` User - 用户钱包（创建时支付租金）
+    /// 2. `[writable]` User USDC Account
+    /// 3. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 4. `[writable]` Trading Vault
+    /// 5. `[]` Token Program
+    /// 6. `[]` System Program
+    Deposit {
+        account_id: String,
+        amount_e6: i64,
+    },
+    
+    /// 提取账户抵押品（不超过未被持仓占用的可用抵押品扣除全仓持仓未实现亏损）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Cross Margin Account PDA
+    /// 1. `[signer]` User
+    /// 2. `[writable]` User USDC Account
+    /// 3. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 4. `[writable]` Trading Vault
+    /// 5. `[]` Token Program
+    /// 6. `[]` 账户全部全仓持仓 - 与CrossMarginAccount.positions一致；
+    ///    每个持仓依次传入 [持仓PDA, 该持仓的Market Config PDA, 该市场的Price Feed Account]
+    Withdraw {
+        account_id: String,
+        amount_e6: i64,
+    },
+    
+    /// 逐仓持仓追加保证金
+    /// 
+    /// 按预言机价格重新计算保证金率，并按新的锁定保证金重新计算强平价格
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` User Position PDA - 必须是逐仓持仓
+    /// 1. `[signer]` User - 持仓所有者
+    /// 2. `[writable]` User USDC Account
+    /// 3. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 4. `[writable]` Trading Vault
+    /// 5. `[]` Token Program
+    /// 6. `[]` Market Config PDA
+    /// 7. `[]` Price Feed Account - = MarketConfig.oracle
+    /// 8. `[]` Funding State PDA - 调整前结算资金费
+    AddMargin {
+        account_id: String,
+        market: String,
+        amount_e6: i64,
+    },
+    
+    /// 逐仓持仓减少保证金
+    /// 
+    /// 减少后 locked + 未实现亏损 不得低于按预言机价格计算的IM（未实现盈利不计入）
+    /// 
+    /// Accounts: 同AddMargin
+    RemoveMargin {
+        account_id: String,
+        market: String,
+        amount_e6: i64,
+    },
+    
+    /// 调整持仓杠杆
+    /// 
+    /// 按 当前数量 × 开仓均价 计算新旧杠杆下IM的差额，锁定保证金只增减该差额
+    /// （AddMargin追加的部分保留），并重新计算MM和强平价格。
+    /// 差额经账户抵押品划转：全仓持仓在抵押品内划转，扣除未实现亏损后不足的部分由用户补足；
+    /// 逐仓持仓账户已Deposit时优先从可用抵押品划转，释放计入抵押品；账户抵押品不存在时与钱包之间划转。
+    /// 释放保证金时按RemoveMargin规则检查：调整后的保证金 + 未实现亏损不得低于按预言机价格计算的新IM
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` User Position PDA
+    /// 1. `[signer]` User - 持仓所有者
+    /// 2. `[writable]` User USDC Account
+    /// 3. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 4. `[writable]` Trading Vault
+    /// 5. `[]` Token Program
+    /// 6. `[]` Market Config PDA - 新杠杆不得超过市场和风险档位上限
+    /// 7. `[]` Price Feed Account - = MarketConfig.oracle
+    /// 8. `[]` Funding State PDA - 调整前结算资金费
+    /// 9. `[writable]` Cross Margin Account PDA - 账户抵押品，可不存在
+    /// 10. `[]` 账户抵押品存在时：账户其他全仓持仓 - 同LockMargin
+    AdjustLeverage {
+        account_id: String,
+        market: String,
+        leverage: u32,
+    },
+    
+    /// 创建止盈/止损条件单
+    /// 
+    /// 标记价格越过trigger_price后由Keeper执行ExecuteConditionalOrder平仓。
+    /// 持仓关闭后重新开仓时，之前创建的条件单失效（只能取消）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Conditional Order PDA - 将被创建
+    ///     Seeds: [b"conditional_order", position, order_id.to_le_bytes()]
+    /// 1. `[signer, writable]` User - 持仓所有者（支付租金）
+    /// 2. `[]` User Position PDA - 条件单关联的持仓（双向持仓传入对应方向的PDA）
+    /// 3. `[]` System Program
+    CreateConditionalOrder {
+        account_id: String,
+        market: String,
+        order_id: u64,                          // 条件单编号，同一持仓内唯一
+        trigger_price_e6: i64,                  // 触发价格（e6格式）
+        trigger_direction: TriggerDirection,    // Above/Below
+        size_e6: i64,                           // 平仓数量（执行时不超过持仓数量）
+    },
+    
+    /// 取消条件单（关闭账户，租金返还给用户）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Conditional Order PDA
+    /// 1. `[signer, writable]` User - 条件单所有者
+    CancelConditionalOrder {
+        order_id: u64,
+    },
+    
+    /// 执行条件单（任何Keeper）
+    /// 
+    /// 预言机价格越过触发价格时按预言机价格平仓，结算同UnlockMargin；
+    /// Keeper按TradingVault.keeper_fee_bp获得奖励（从返还金额中扣除）。
+    /// 执行后关闭条件单账户，租金返还给持仓所有者
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Conditional Order PDA
+    /// 1. `[signer]` Keeper
+    /// 2. `[writable]` Keeper USDC Account - 收取奖励（owner = Keeper）
+    /// 3. `[writable]` User Position PDA - = ConditionalOrder.position
+    /// 4. `[writable]` Position Owner - 无需签名（收回条件单和持仓租金）
+    /// 5. `[writable]` User USDC Account（owner = Position Owner）
+    /// 6. `[writable]` Vault USDC Account（= TradingVault.vault_usdc_account）
+    /// 7. `[writable]` Trading Vault
+    /// 8. `[]` Token Program
+    /// 9. `[writable]` Insurance Fund Account（owner = Vault PDA）
+    /// 10. `[]` Market Config PDA
+    /// 11. `[]` Price Feed Account - = MarketConfig.oracle，触发判断和平仓价格
+    /// 12. `[writable]` Fee Treasury Account（owner = Vault PDA）
+    /// 13. `[writable]` Funding State PDA - 同UnlockMargin
+    /// 14. `[writable]` Cross Margin Account PDA
+    ExecuteConditionalOrder,
+    
+    /// 设置条件单Keeper奖励费率（仅管理员，上限100bp）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` Admin
+    SetKeeperFee {
+        keeper_fee_bp: u32,
+    },
+    
+    /// 设置全局交易模式（仅管理员）
+    /// 
+    /// 与市场交易模式取较严格者生效：
+    /// - ReduceOnly：拒绝LockMargin、RemoveMargin和提高杠杆，允许UnlockMargin、条件单执行、
+    ///   Liquidate、AutoDeleverage、AddMargin、Deposit/Withdraw
+    /// - Halted：以上指令及UpdatePosition、UpdateFunding全部拒绝
+    /// 
+    /// 管理员指令和创建/取消条件单不受交易模式限制
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` Admin
+    SetTradingMode {
+        mode: TradingMode,
+    },
+    
+    /// 设置市场交易模式（仅管理员，规则同SetTradingMode）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Market Config PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[]` Trading Vault
+    SetMarketTradingMode {
+        market: String,
+        mode: TradingMode,
+    },
+    
+    /// 设置Guardian（仅管理员）
+    /// 
+    /// Guardian只能暂停，恢复需要管理员。旧版本（272字节）的Vault在此扩容到当前大小，
+    /// 管理员补足租金
+    /// 
+    /// 升级顺序：
+    /// 1. 部署新程序 - 旧Vault按新增字段为0读取，其他指令照常执行
+    /// 2. 调用SetGuardian扩容并设置Guardian - 扩容前没有Guardian，Pause返回VaultResizeRequired
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer, writable]` Admin
+    /// 2. `[]` System Program
+    SetGuardian {
+        guardian: solana_program::pubkey::Pubkey,
+    },
+    
+    /// 紧急暂停（仅Guardian）
+    /// 
+    /// 暂停期间拒绝所有资金相关指令：LockMargin、UnlockMargin、条件单执行、Liquidate、
+    /// AutoDeleverage、WithdrawInsuranceFund、Deposit/Withdraw、AddMargin/RemoveMargin、AdjustLeverage
+    /// 
+    /// 旧版本（272字节）Vault需先通过SetGuardian扩容，否则返回VaultResizeRequired
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` Guardian - 必须是TradingVault.guardian
+    Pause,
+    
+    /// 解除暂停（仅管理员）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` Admin
+    Unpause,
+    
+    /// 提议转移管理员（仅当前管理员，第一步）
+    /// 
+    /// 新管理员签名AcceptAuthority后生效；再次提议会覆盖，提议默认公钥表示取消。
+    /// 旧版本（272字节）Vault在此扩容到当前大小，管理员补足租金
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer, writable]` Admin
+    /// 2. `[]` System Program
+    ProposeAuthority {
+        new_authority: solana_program::pubkey::Pubkey,
+    },
+    
+    /// 接受管理员转移（第二步）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer]` New Authority - 必须是TradingVault.pending_authority
+    AcceptAuthority,
+    
+    /// 设置Insurance Fund和Fee Treasury账户（仅管理员）
+    /// 
+    /// UnlockMargin、Liquidate等只接受Vault中登记的账户，未设置时拒绝。
+    /// InitializeVault之后、以及升级旧Vault后必须先调用；旧版本Vault在此扩容，管理员补足租金
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Trading Vault
+    /// 1. `[signer, writable]` Admin
+    /// 2. `[]` System Program
+    /// 3. `[]` Insurance Fund Account - owner = Vault PDA，mint = USDC
+    /// 4. `[]` Fee Treasury Account - owner = Vault PDA，mint = USDC
+    SetTreasuryAccounts,
 }
-
@@ -22,9 +22,12 @@ use solana_program::{
 
 pub mod error;
 pub mod instruction;
+pub mod oracle;
 pub mod processor;
 pub mod state;
 pub mod utils;
+pub mod validation;
+pub mod waterfall;
 
 // Trading Program ID (已部署到1024Chain Testnet)
 // 部署日期: 2025-11-13
@@ -47,6 +50,7 @@ pub fn process_instruction(
 pub use error::TradingError;
 pub use instruction::TradingInstruction;
 pub use state::{
-    TradingVault, UserPosition, Side, MarginMode, LiquidationStatus,
+    TradingVault, UserPosition, MarketConfig, FundingState, CrossMarginAccount, ConditionalOrder, RiskBracket,
+    RiskParams, Side, MarginMode, LiquidationStatus, MarketStatus, TradingMode, TriggerDirection,
 };
 
@@ -0,0 +1,242 @@
+//! Oracle Price Validation
+//!
+//! 解析Pyth风格的价格账户（v2 price account布局），
+//! 校验发布时间、置信区间，并限制调用方提交的价格与预言机价格的偏离
+
+use solana_program::{account_info::AccountInfo, msg, program_error::ProgramError};
+
+use crate::{error::TradingError, state::MarketConfig};
+
+/// Pyth价格账户magic
+pub const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+
+/// Pyth价格账户版本
+pub const PYTH_VERSION_2: u32 = 2;
+
+/// Pyth账户类型：Price
+pub const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+
+/// Pyth价格状态：Trading
+pub const PYTH_STATUS_TRADING: u32 = 1;
+
+// === Pyth v2 price account 字段偏移 ===
+pub const PYTH_MAGIC_OFFSET: usize = 0;
+pub const PYTH_VERSION_OFFSET: usize = 4;
+pub const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+pub const PYTH_EXPO_OFFSET: usize = 20;
+pub const PYTH_TIMESTAMP_OFFSET: usize = 96;
+pub const PYTH_AGG_PRICE_OFFSET: usize = 208;
+pub const PYTH_AGG_CONF_OFFSET: usize = 216;
+pub const PYTH_AGG_STATUS_OFFSET: usize = 224;
+
+/// 解析所需的最小长度（到agg.pub_slot结束）
+pub const PYTH_MIN_ACCOUNT_LEN: usize = 240;
+
+/// 预言机价格（已转换为e6格式）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OraclePrice {
+    pub price_e6: i64,
+    pub conf_e6: i64,
+    pub publish_time: i64,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// 将 value × 10^expo 转换为e6格式
+fn scale_to_e6(value: i128, expo: i32) -> Result<i64, ProgramError> {
+    if !(-18..=12).contains(&expo) {
+        return Err(TradingError::InvalidOracleAccount.into());
+    }
+    
+    let shift = expo + 6;
+    let scaled = if shift >= 0 {
+        value
+            .checked_mul(10i128.pow(shift as u32))
+            .ok_or(TradingError::ArithmeticOverflow)?
+    } else {
+        value / 10i128.pow((-shift) as u32)
+    };
+    
+    i64::try_from(scaled).map_err(|_| TradingError::ArithmeticOverflow.into())
+}
+
+/// 解析Pyth价格账户数据
+pub fn parse_pyth_price(data: &[u8]) -> Result<OraclePrice, ProgramError> {
+    if data.len() < PYTH_MIN_ACCOUNT_LEN
+        || read_u32(data, PYTH_MAGIC_OFFSET) != PYTH_MAGIC
+        || read_u32(data, PYTH_VERSION_OFFSET) != PYTH_VERSION_2
+        || read_u32(data, PYTH_ACCOUNT_TYPE_OFFSET) != PYTH_ACCOUNT_TYPE_PRICE
+    {
+        msg!("Error: Not a Pyth price account");
+        return Err(TradingError::InvalidOracleAccount.into());
+    }
+    
+    if read_u32(data, PYTH_AGG_STATUS_OFFSET) != PYTH_STATUS_TRADING {
+        msg!("Error: Oracle price is not trading");
+        return Err(TradingError::OraclePriceStale.into());
+    }
+    
+    let expo = read_i32(data, PYTH_EXPO_OFFSET);
+    let price_e6 = scale_to_e6(read_i64(data, PYTH_AGG_PRICE_OFFSET) as i128, expo)?;
+    let conf_e6 = scale_to_e6(read_u64(data, PYTH_AGG_CONF_OFFSET) as i128, expo)?;
+    
+    if price_e6 <= 0 {
+        msg!("Error: Oracle price {} is not positive", price_e6);
+        return Err(TradingError::InvalidOracleAccount.into());
+    }
+    
+    Ok(OraclePrice {
+        price_e6,
+        conf_e6,
+        publish_time: read_i64(data, PYTH_TIMESTAMP_OFFSET),
+    })
+}
+
+/// 读取市场配置的预言机价格，并校验发布时间和置信区间
+pub fn load_oracle_price(
+    account: &AccountInfo,
+    config: &MarketConfig,
+    now: i64,
+) -> Result<OraclePrice, ProgramError> {
+    if !config.has_oracle() {
+        msg!("Error: Market {} has no oracle configured", config.market);
+        return Err(TradingError::OracleNotConfigured.into());
+    }
+    
+    if account.key != &config.oracle {
+        msg!("Error: Oracle account mismatch, expected {}", config.oracle);
+        return Err(TradingError::InvalidOracleAccount.into());
+    }
+    
+    let oracle_price = parse_pyth_price(&account.data.borrow())?;
+    
+    if now.saturating_sub(oracle_price.publish_time) > config.max_oracle_staleness_secs as i64 {
+        msg!("Error: Oracle price stale, published at {}, now {}", oracle_price.publish_time, now);
+        return Err(TradingError::OraclePriceStale.into());
+    }
+    
+    // conf / price <= max_oracle_confidence_bp
+    if oracle_price.conf_e6 as i128 * 10000
+        > oracle_price.price_e6 as i128 * config.max_oracle_confidence_bp as i128
+    {
+        msg!("Error: Oracle confidence {} too wide for price {}", oracle_price.conf_e6, oracle_price.price_e6);
+        return Err(TradingError::OracleConfidenceTooWide.into());
+    }
+    
+    Ok(oracle_price)
+}
+
+/// 验证价格与预言机价格的偏离不超过band（基点）
+pub fn validate_price_band(
+    price_e6: i64,
+    oracle_price: &OraclePrice,
+    max_deviation_bp: u32,
+) -> Result<(), ProgramError> {
+    let deviation = (price_e6 as i128 - oracle_price.price_e6 as i128).abs();
+    
+    if deviation * 10000 > oracle_price.price_e6 as i128 * max_deviation_bp as i128 {
+        msg!("Error: Price {} deviates from oracle {} by more than {}bp",
+            price_e6, oracle_price.price_e6, max_deviation_bp);
+        return Err(TradingError::OraclePriceDeviation.into());
+    }
+    Ok(())
+}
+
+/// 读取预言机价格并验证调用方提交的价格在市场band内
+pub fn validate_against_oracle(
+    account: &AccountInfo,
+    config: &MarketConfig,
+    price_e6: i64,
+    now: i64,
+) -> Result<OraclePrice, ProgramError> {
+    let oracle_price = load_oracle_price(account, config, now)?;
+    validate_price_band(price_e6, &oracle_price, config.max_price_deviation_bp)?;
+    
+    msg!("  Oracle Price: {} (conf {})", oracle_price.price_e6, oracle_price.conf_e6);
+    
+    Ok(oracle_price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn pyth_data(price: i64, conf: u64, expo: i32, timestamp: i64, status: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 3312];
+        data[PYTH_MAGIC_OFFSET..PYTH_MAGIC_OFFSET + 4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        data[PYTH_VERSION_OFFSET..PYTH_VERSION_OFFSET + 4].copy_from_slice(&PYTH_VERSION_2.to_le_bytes());
+        data[PYTH_ACCOUNT_TYPE_OFFSET..PYTH_ACCOUNT_TYPE_OFFSET + 4]
+            .copy_from_slice(&PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes());
+        data[PYTH_EXPO_OFFSET..PYTH_EXPO_OFFSET + 4].copy_from_slice(&expo.to_le_bytes());
+        data[PYTH_TIMESTAMP_OFFSET..PYTH_TIMESTAMP_OFFSET + 8].copy_from_slice(&timestamp.to_le_bytes());
+        data[PYTH_AGG_PRICE_OFFSET..PYTH_AGG_PRICE_OFFSET + 8].copy_from_slice(&price.to_le_bytes());
+        data[PYTH_AGG_CONF_OFFSET..PYTH_AGG_CONF_OFFSET + 8].copy_from_slice(&conf.to_le_bytes());
+        data[PYTH_AGG_STATUS_OFFSET..PYTH_AGG_STATUS_OFFSET + 4].copy_from_slice(&status.to_le_bytes());
+        data
+    }
+    
+    #[test]
+    fn test_parse_pyth_price() {
+        // $100,000.12345678, expo = -8
+        let data = pyth_data(10_000_012_345_678, 5_000_000_000, -8, 1_700_000_000, PYTH_STATUS_TRADING);
+        let price = parse_pyth_price(&data).unwrap();
+        
+        assert_eq!(price.price_e6, 100_000_123_456);
+        assert_eq!(price.conf_e6, 50_000_000);
+        assert_eq!(price.publish_time, 1_700_000_000);
+        
+        // 正指数
+        let data = pyth_data(5, 0, 2, 0, PYTH_STATUS_TRADING);
+        assert_eq!(parse_pyth_price(&data).unwrap().price_e6, 500_000_000);
+    }
+    
+    #[test]
+    fn test_parse_pyth_price_rejects_invalid() {
+        // 非Trading状态
+        let data = pyth_data(10_000_000_000_000, 0, -8, 0, 0);
+        assert!(parse_pyth_price(&data).is_err());
+        
+        // 长度不足
+        let data = pyth_data(10_000_000_000_000, 0, -8, 0, PYTH_STATUS_TRADING);
+        assert!(parse_pyth_price(&data[..PYTH_MIN_ACCOUNT_LEN - 1]).is_err());
+        
+        // magic错误
+        let mut data = pyth_data(10_000_000_000_000, 0, -8, 0, PYTH_STATUS_TRADING);
+        data[0] = 0;
+        assert!(parse_pyth_price(&data).is_err());
+        
+        // 非正价格
+        let data = pyth_data(-1, 0, -8, 0, PYTH_STATUS_TRADING);
+        assert!(parse_pyth_price(&data).is_err());
+    }
+    
+    #[test]
+    fn test_validate_price_band() {
+        let oracle = OraclePrice {
+            price_e6: 100_000_000_000,
+            conf_e6: 0,
+            publish_time: 0,
+        };
+        
+        // 1% band
+        assert!(validate_price_band(100_000_000_000, &oracle, 100).is_ok());
+        assert!(validate_price_band(101_000_000_000, &oracle, 100).is_ok());
+        assert!(validate_price_band(99_000_000_000, &oracle, 100).is_ok());
+        assert!(validate_price_band(101_000_000_001, &oracle, 100).is_err());
+        assert!(validate_price_band(98_999_999_999, &oracle, 100).is_err());
+    }
+}
@@ -1,28 +1,33 @@
 //! Trading Program Instruction Processing
 
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::BorshDeserialize;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
+    system_program,
     sysvar::Sysvar,
 };
 
 use crate::{
     error::TradingError,
     instruction::TradingInstruction,
-    state::{TradingVault, UserPosition, Side},
+    oracle::{load_oracle_price, parse_pyth_price, validate_against_oracle},
+    state::{
+        ConditionalOrder, CrossMarginAccount, FundingState, LiquidationStatus, MarginMode, MarketConfig,
+        MarketStatus, RiskBracket, RiskParams, TradingMode, TradingVault, TriggerDirection, UserPosition, Side,
+    },
     utils::*,
+    validation::*,
+    waterfall::calculate_liquidation_waterfall,
 };
 
-/// 授权的Relayer公钥（管理员）
-pub const AUTHORIZED_RELAYER: &str = "J1Szw8HZYL95NvYUsNhg3e6NzKQLUZ9UxQsKg4hsQnad";
-
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
@@ -108,6 +113,190 @@ pub fn process_instruction(
             msg!("Instruction: WithdrawInsuranceFund");
             process_withdraw_insurance_fund(program_id, accounts, amount_e6)
         }
+        TradingInstruction::SetRelayer { new_relayer } => {
+            msg!("Instruction: SetRelayer");
+            process_set_relayer(program_id, accounts, new_relayer)
+        }
+        TradingInstruction::CreateMarket {
+            market,
+            max_leverage,
+            maintenance_margin_ratio_bp,
+            liquidation_fee_bp,
+            tick_size_e6,
+            lot_size_e6,
+        } => {
+            msg!("Instruction: CreateMarket");
+            process_create_market(
+                program_id,
+                accounts,
+                market,
+                max_leverage,
+                maintenance_margin_ratio_bp,
+                liquidation_fee_bp,
+                tick_size_e6,
+                lot_size_e6,
+            )
+        }
+        TradingInstruction::SetMarketStatus { market, status } => {
+            msg!("Instruction: SetMarketStatus");
+            process_set_market_status(program_id, accounts, market, status)
+        }
+        TradingInstruction::UpdateMarketRiskParams {
+            market,
+            max_leverage,
+            risk_params,
+        } => {
+            msg!("Instruction: UpdateMarketRiskParams");
+            process_update_market_risk_params(program_id, accounts, market, max_leverage, risk_params)
+        }
+        TradingInstruction::RemarginPosition {
+            wallet,
+            account_id,
+            market,
+        } => {
+            msg!("Instruction: RemarginPosition");
+            process_remargin_position(program_id, accounts, wallet, account_id, market)
+        }
+        TradingInstruction::SetRiskBrackets { market, brackets } => {
+            msg!("Instruction: SetRiskBrackets");
+            process_set_risk_brackets(program_id, accounts, market, brackets)
+        }
+        TradingInstruction::InitializeFunding {
+            market,
+            keeper,
+            funding_interval_secs,
+            max_funding_rate_e9,
+        } => {
+            msg!("Instruction: InitializeFunding");
+            process_initialize_funding(
+                program_id,
+                accounts,
+                market,
+                keeper,
+                funding_interval_secs,
+                max_funding_rate_e9,
+            )
+        }
+        TradingInstruction::UpdateFunding { market, funding_rate_e9 } => {
+            msg!("Instruction: UpdateFunding");
+            process_update_funding(program_id, accounts, market, funding_rate_e9)
+        }
+        TradingInstruction::SetFundingKeeper { market, keeper } => {
+            msg!("Instruction: SetFundingKeeper");
+            process_set_funding_keeper(program_id, accounts, market, keeper)
+        }
+        TradingInstruction::SetPartialLiquidation { market, target_margin_ratio_bp } => {
+            msg!("Instruction: SetPartialLiquidation");
+            process_set_partial_liquidation(program_id, accounts, market, target_margin_ratio_bp)
+        }
+        TradingInstruction::AutoDeleverage { market, target_count } => {
+            msg!("Instruction: AutoDeleverage");
+            process_auto_deleverage(program_id, accounts, market, target_count)
+        }
+        TradingInstruction::Deposit { account_id, amount_e6 } => {
+            msg!("Instruction: Deposit");
+            process_deposit(program_id, accounts, account_id, amount_e6)
+        }
+        TradingInstruction::Withdraw { account_id, amount_e6 } => {
+            msg!("Instruction: Withdraw");
+            process_withdraw(program_id, accounts, account_id, amount_e6)
+        }
+        TradingInstruction::AddMargin { account_id, market, amount_e6 } => {
+            msg!("Instruction: AddMargin");
+            process_adjust_margin(program_id, accounts, account_id, market, amount_e6, true)
+        }
+        TradingInstruction::RemoveMargin { account_id, market, amount_e6 } => {
+            msg!("Instruction: RemoveMargin");
+            process_adjust_margin(program_id, accounts, account_id, market, amount_e6, false)
+        }
+        TradingInstruction::AdjustLeverage { account_id, market, leverage } => {
+            msg!("Instruction: AdjustLeverage");
+            process_adjust_leverage(program_id, accounts, account_id, market, leverage)
+        }
+        TradingInstruction::CreateConditionalOrder {
+            account_id,
+            market,
+            order_id,
+            trigger_price_e6,
+            trigger_direction,
+            size_e6,
+        } => {
+            msg!("Instruction: CreateConditionalOrder");
+            process_create_conditional_order(
+                program_id,
+                accounts,
+                account_id,
+                market,
+                order_id,
+                trigger_price_e6,
+                trigger_direction,
+                size_e6,
+            )
+        }
+        TradingInstruction::CancelConditionalOrder { order_id } => {
+            msg!("Instruction: CancelConditionalOrder");
+            process_cancel_conditional_order(program_id, accounts, order_id)
+        }
+        TradingInstruction::ExecuteConditionalOrder => {
+            msg!("Instruction: ExecuteConditionalOrder");
+            process_execute_conditional_order(program_id, accounts)
+        }
+        TradingInstruction::SetKeeperFee { keeper_fee_bp } => {
+            msg!("Instruction: SetKeeperFee");
+            process_set_keeper_fee(program_id, accounts, keeper_fee_bp)
+        }
+        TradingInstruction::SetTradingMode { mode } => {
+            msg!("Instruction: SetTradingMode");
+            process_set_trading_mode(program_id, accounts, mode)
+        }
+        TradingInstruction::SetMarketTradingMode { market, mode } => {
+            msg!("Instruction: SetMarketTradingMode");
+            process_set_market_trading_mode(program_id, accounts, market, mode)
+        }
+        TradingInstruction::SetGuardian { guardian } => {
+            msg!("Instruction: SetGuardian");
+            process_set_guardian(program_id, accounts, guardian)
+        }
+        TradingInstruction::Pause => {
+            msg!("Instruction: Pause");
+            process_pause(program_id, accounts)
+        }
+        TradingInstruction::Unpause => {
+            msg!("Instruction: Unpause");
+            process_unpause(program_id, accounts)
+        }
+        TradingInstruction::ProposeAuthority { new_authority } => {
+            msg!("Instruction: ProposeAuthority");
+            process_propose_authority(program_id, accounts, new_authority)
+        }
+        TradingInstruction::AcceptAuthority => {
+            msg!("Instruction: AcceptAuthority");
+            process_accept_authority(program_id, accounts)
+        }
+        TradingInstruction::SetTreasuryAccounts => {
+            msg!("Instruction: SetTreasuryAccounts");
+            process_set_treasury_accounts(program_id, accounts)
+        }
+        TradingInstruction::SetTradingFees { maker_fee_bp, taker_fee_bp } => {
+            msg!("Instruction: SetTradingFees");
+            process_set_trading_fees(program_id, accounts, maker_fee_bp, taker_fee_bp)
+        }
+        TradingInstruction::SetMarketOracle {
+            market,
+            max_price_deviation_bp,
+            max_oracle_staleness_secs,
+            max_oracle_confidence_bp,
+        } => {
+            msg!("Instruction: SetMarketOracle");
+            process_set_market_oracle(
+                program_id,
+                accounts,
+                market,
+                max_price_deviation_bp,
+                max_oracle_staleness_secs,
+                max_oracle_confidence_bp,
+            )
+        }
     }
 }
 
@@ -122,21 +311,20 @@ fn process_initialize_vault(
     let vault_usdc_account = next_account_info(account_iter)?;
     let authority = next_account_info(account_iter)?;
     let system_program = next_account_info(account_iter)?;
-    let _token_program = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
     let _rent = next_account_info(account_iter)?;
+    let usdc_mint = next_account_info(account_iter)?;
     
     msg!("Initializing Trading Vault...");
     
     // 验证authority签名
-    if !authority.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    validate_signer(authority)?;
+    validate_system_program(system_program)?;
+    validate_token_program(token_program)?;
+    validate_mint_account(usdc_mint)?;
     
     // 派生Trading Vault PDA
-    let (expected_vault_pda, bump) = Pubkey::find_program_address(
-        &[b"trading_vault"],
-        program_id,
-    );
+    let (expected_vault_pda, bump) = find_vault_pda(program_id);
     
     if trading_vault_account.key != &expected_vault_pda {
         msg!("Error: Vault PDA mismatch");
@@ -170,19 +358,68 @@ fn process_initialize_vault(
             trading_vault_account.clone(),
             system_program.clone(),
         ],
-        &[&[b"trading_vault", &[bump]]],
+        &[&[VAULT_SEED, &[bump]]],
     )?;
     
-    // 初始化Vault数据
-    let vault = TradingVault::new(*authority.key, bump);
-    let serialized = vault.try_to_vec()
-        .map_err(|_| TradingError::SerializationError)?;
+    // Vault USDC托管账户：不存在则创建PDA账户，已存在则验证owner和mint
+    if vault_usdc_account.lamports() == 0 {
+        let (expected_usdc_pda, usdc_bump) = find_vault_usdc_pda(program_id);
+        
+        if vault_usdc_account.key != &expected_usdc_pda {
+            msg!("Error: Vault USDC PDA mismatch");
+            return Err(TradingError::InvalidVaultTokenAccount.into());
+        }
+        
+        msg!("Creating Vault USDC account...");
+        
+        let token_space = spl_token::state::Account::LEN;
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                authority.key,
+                vault_usdc_account.key,
+                rent.minimum_balance(token_space),
+                token_space as u64,
+                token_program.key,
+            ),
+            &[
+                authority.clone(),
+                vault_usdc_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[VAULT_USDC_SEED, &[usdc_bump]]],
+        )?;
+        
+        invoke(
+            &spl_token::instruction::initialize_account3(
+                token_program.key,
+                vault_usdc_account.key,
+                usdc_mint.key,
+                trading_vault_account.key,
+            )?,
+            &[
+                vault_usdc_account.clone(),
+                usdc_mint.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    } else {
+        validate_token_account(vault_usdc_account, trading_vault_account.key, usdc_mint.key)
+            .map_err(|_| TradingError::InvalidVaultTokenAccount)?;
+    }
     
-    trading_vault_account.data.borrow_mut()[..serialized.len()]
-        .copy_from_slice(&serialized);
+    // 初始化Vault数据
+    let vault = TradingVault::new(
+        *authority.key,
+        bump,
+        *usdc_mint.key,
+        *vault_usdc_account.key,
+    );
+    store_trading_vault(trading_vault_account, &vault)?;
     
     msg!("✅ Trading Vault initialized!");
     msg!("  PDA: {}", trading_vault_account.key);
+    msg!("  USDC Mint: {}", usdc_mint.key);
     msg!("  Vault USDC Account: {}", vault_usdc_account.key);
     
     Ok(())
@@ -199,7 +436,7 @@ fn process_lock_margin(
     size_e6: i64,
     entry_price_e6: i64,
     leverage: u32,
-    margin_mode: crate::state::MarginMode,
+    margin_mode: MarginMode,
 ) -> ProgramResult {
     let account_iter = &mut accounts.iter();
     
@@ -210,6 +447,19 @@ fn process_lock_margin(
     let trading_vault_account = next_account_info(account_iter)?;
     let token_program = next_account_info(account_iter)?;
     let system_program = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let fee_treasury_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
+    let cross_margin_account = next_account_info(account_iter)?;
+    
+    // 其余账户：可选的Relayer，之后为账户其他全仓持仓（三个一组）
+    let remaining_accounts = account_iter.as_slice();
+    let (maker_attestation, cross_position_accounts) = if remaining_accounts.len() % 3 == 1 {
+        (remaining_accounts.first(), &remaining_accounts[1..])
+    } else {
+        (None, remaining_accounts)
+    };
     
     msg!("Lock Margin - Opening Position");
     msg!("  Account: {}", account_id);
@@ -226,69 +476,328 @@ fn process_lock_margin(
     validate_price(entry_price_e6)?;
     validate_leverage(leverage)?;
     
-    // 验证user签名
-    if !user.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    // 验证账户
+    validate_signer(user)?;
+    validate_token_program(token_program)?;
+    validate_system_program(system_program)?;
     
-    // 计算所需保证金
-    let im_e6 = calculate_initial_margin(size_e6, entry_price_e6, leverage)?;
-    let mm_e6 = calculate_maintenance_margin(im_e6);
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    validate_token_account(user_usdc_account, user.key, &vault.usdc_mint)?;
+    validate_fee_treasury_account(fee_treasury_account, &vault)?;
     
-    msg!("  IM: {} USDC", im_e6 as f64 / 1_000_000.0);
-    msg!("  MM: {} USDC", mm_e6 as f64 / 1_000_000.0);
+    // 验证市场配置
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
     
-    // 派生UserPosition PDA
-    let (expected_position_pda, position_bump) = Pubkey::find_program_address(
-        &[
-            b"position",
-            user.key.as_ref(),
-            account_id.as_bytes(),
-            market.as_bytes(),
-        ],
-        program_id,
-    );
+    // ReduceOnly下LockMargin整体拒绝（减仓使用UnlockMargin）
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, true)?;
+    
+    validate_market_leverage(leverage, &market_config)?;
+    validate_tick_size(entry_price_e6, &market_config)?;
+    validate_lot_size(size_e6, &market_config)?;
     
-    if user_position_account.key != &expected_position_pda {
+    // 开仓价格必须在预言机价格band内
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    validate_against_oracle(price_feed_account, &market_config, entry_price_e6, now)?;
+    
+    // 派生UserPosition PDA：传入单向持仓PDA为单向模式，传入本方向的双向持仓PDA为Hedge Mode
+    // Hedge Mode下多空各自独立持仓，反向下单不会对冲现有持仓
+    let (one_way_pda, one_way_bump) =
+        find_position_pda(program_id, user.key, &account_id, &market);
+    let (hedge_pda, hedge_bump) =
+        find_hedge_position_pda(program_id, user.key, &account_id, &market, side);
+    
+    let (hedge_mode, position_bump) = if user_position_account.key == &one_way_pda {
+        (false, one_way_bump)
+    } else if user_position_account.key == &hedge_pda {
+        (true, hedge_bump)
+    } else {
         msg!("Error: Position PDA mismatch");
         return Err(TradingError::InvalidPositionAccount.into());
+    };
+    
+    if hedge_mode {
+        msg!("  Position Mode: Hedge");
     }
     
-    // 检查持仓是否存在
-    let is_new_position = user_position_account.lamports() == 0;
+    // 按owner和数据长度判断持仓是否存在：他人预先转入lamports不影响创建
+    let is_new_position = user_position_account.owner != program_id || user_position_account.data_is_empty();
     
-    if is_new_position {
+    let mut existing_position = if is_new_position {
+        None
+    } else {
+        Some(load_user_position(
+            program_id,
+            user_position_account,
+            user.key,
+            &account_id,
+            &market,
+        )?)
+    };
+    
+    // 保证金模式必须与现有持仓一致；同方向加仓时杠杆也必须一致
+    if let Some(position) = &existing_position {
+        if position.margin_mode != margin_mode {
+            msg!("Error: Position is {:?}, order is {:?}", position.margin_mode, margin_mode);
+            return Err(TradingError::MarginModeMismatch.into());
+        }
+        
+        if position.side == side && position.leverage != leverage {
+            msg!("Error: Position leverage is {}x, order is {}x; use AdjustLeverage first",
+                position.leverage, leverage);
+            return Err(TradingError::LeverageMismatch.into());
+        }
+    }
+    
+    // 账户抵押品：全仓首次开仓时创建；逐仓仅在已Deposit（账户已存在）时使用
+    let is_cross = margin_mode == MarginMode::Cross;
+    let mut collateral = if is_cross {
+        Some(load_or_create_cross_margin(
+            program_id,
+            cross_margin_account,
+            user,
+            system_program,
+            &account_id,
+        )?)
+    } else if cross_margin_account.owner == program_id {
+        Some(load_cross_margin_account(program_id, cross_margin_account, user.key, &account_id)?)
+    } else {
+        None
+    };
+    
+    // 未登记在账户抵押品中的旧全仓持仓，加仓和反手仍按逐仓结算
+    let is_cross = match &existing_position {
+        Some(position) => is_registered_cross(user_position_account.key, position, collateral.as_ref()),
+        None => is_cross,
+    };
+    
+    // 账户其他全仓持仓的未实现亏损，开仓后需由抵押品覆盖（与Withdraw相同）
+    let other_unrealized_loss = match collateral.as_ref() {
+        Some(account) => {
+            let excluded = Some(user_position_account.key).filter(|key| account.positions.contains(key));
+            cross_unrealized_loss(program_id, account, excluded, cross_position_accounts, now)?
+        }
+        None => 0,
+    };
+    
+    // 加仓前先结算资金费
+    let mut funding = load_funding_state(program_id, funding_state_account, &market)?;
+    
+    if let Some(position) = existing_position.as_mut() {
+        let funding_received = settle_funding(position, &funding, &mut vault)?;
+        
+        if let Some(cross) = collateral.as_mut().filter(|_| is_cross) {
+            cross.adjust_position_margin(funding_received);
+        }
+    }
+    
+    // 反向下单：先与现有持仓对冲（减仓），剩余部分按新方向开仓
+    let reduce_size_e6 = match &existing_position {
+        Some(position) if position.side != side => size_e6.min(position.size_e6.abs()),
+        _ => 0,
+    };
+    let open_size_e6 = size_e6 - reduce_size_e6;
+    
+    if reduce_size_e6 > 0 {
+        msg!("  Reduce Size: {}", reduce_size_e6);
+        msg!("  Open Size: {}", open_size_e6);
+    }
+    
+    // 减仓在任何市场状态下都允许，开仓部分要求市场Active
+    if open_size_e6 > 0 && !market_config.can_open() {
+        msg!("Error: Market {} is {:?}, opening disabled", market, market_config.status);
+        return Err(TradingError::MarketNotActive.into());
+    }
+    
+    // 加仓沿用开仓时的风控参数，新开仓/反向开仓使用市场当前参数
+    let mut risk_params = match &existing_position {
+        Some(position) if reduce_size_e6 == 0 => position.effective_risk_params(),
+        _ => market_config.risk_params,
+    };
+    
+    // 按加仓后的持仓总名义价值选择风险档位
+    let existing_notional = match &existing_position {
+        Some(position) if reduce_size_e6 == 0 => {
+            calculate_notional(position.size_e6.abs(), position.entry_price_e6)?
+        }
+        _ => 0,
+    };
+    let total_notional = safe_add_i64(existing_notional, calculate_notional(open_size_e6, entry_price_e6)?)?;
+    
+    let bracket = if open_size_e6 > 0 {
+        market_config.risk_bracket(total_notional).inspect_err(|_| {
+            msg!("Error: Position notional {} exceeds max risk bracket", total_notional);
+        })?
+    } else {
+        None
+    };
+    
+    if let Some(bracket) = bracket {
+        msg!("  Risk Bracket: notional <= {}, max {}x, MM {}bp",
+            bracket.max_notional_e6, bracket.max_leverage, bracket.maintenance_margin_ratio_bp);
+        
+        if leverage > bracket.max_leverage {
+            msg!("Error: Leverage {}x exceeds bracket max {}x", leverage, bracket.max_leverage);
+            return Err(TradingError::LeverageExceedsBracket.into());
+        }
+        
+        risk_params.maintenance_margin_ratio_bp = bracket.maintenance_margin_ratio_bp;
+    }
+    
+    // 计算开仓部分所需保证金
+    let im_e6 = calculate_initial_margin(open_size_e6, entry_price_e6, leverage)?;
+    let mm_e6 = calculate_maintenance_margin(
+        open_size_e6,
+        entry_price_e6,
+        risk_params.maintenance_margin_ratio_bp,
+    )?;
+    
+    // 对冲部分：按成交价实现盈亏，并按比例释放保证金
+    let (realized_pnl, released_im, released_mm) = match &existing_position {
+        Some(position) if reduce_size_e6 > 0 => {
+            let is_long = matches!(position.side, Side::Buy);
+            let pnl = calculate_realized_pnl(
+                is_long,
+                position.entry_price_e6,
+                entry_price_e6,
+                reduce_size_e6,
+            )?;
+            let reduce_ratio = reduce_size_e6 as i128 * 1_000_000 / position.size_e6.abs() as i128;
+            (
+                pnl,
+                (position.locked_usdc_e6 as i128 * reduce_ratio / 1_000_000) as i64,
+                (position.mm_e6 as i128 * reduce_ratio / 1_000_000) as i64,
+            )
+        }
+        _ => (0, 0, 0),
+    };
+    
+    // 交易手续费（按成交名义价值，对冲部分计入平仓手续费）
+    let trade_notional = calculate_notional(size_e6, entry_price_e6)?;
+    let is_maker = is_maker_fill(maker_attestation, &vault);
+    let fee_bp = vault.trading_fee_bp(is_maker);
+    let fee_e6 = calculate_fee_bp(trade_notional, fee_bp);
+    let close_fee_e6 = (fee_e6 as i128 * reduce_size_e6 as i128 / size_e6 as i128) as i64;
+    let open_fee_e6 = fee_e6 - close_fee_e6;
+    
+    msg!("  IM: {} USDC", im_e6 as f64 / 1_000_000.0);
+    msg!("  MM: {} USDC", mm_e6 as f64 / 1_000_000.0);
+    msg!("  Fee: {} USDC ({}bp)", fee_e6 as f64 / 1_000_000.0, fee_bp);
+    
+    if reduce_size_e6 > 0 {
+        msg!("  Realized PnL: {} USDC", realized_pnl as f64 / 1_000_000.0);
+        msg!("  Released IM: {} USDC", released_im as f64 / 1_000_000.0);
+    }
+    
+    let mut is_fully_closed = false;
+    let mut position_pnl = 0;
+    
+    if let Some(mut position) = existing_position {
+        // 反手会改变方向，先取消ADL登记，未全部平仓时按新方向重新登记
+        funding.unindex_position(&mut position);
+        
+        if reduce_size_e6 > 0 {
+            // 反向下单：对冲现有持仓
+            msg!("Reducing existing position...");
+            
+            position.size_e6 = safe_sub_i64(position.size_e6, reduce_size_e6)?;
+            position.locked_usdc_e6 = safe_sub_i64(position.locked_usdc_e6, released_im)?;
+            position.mm_e6 = safe_sub_i64(position.mm_e6, released_mm)?;
+            position.realized_pnl_e6 = safe_add_i64(position.realized_pnl_e6, realized_pnl)?;
+            position.close_fees_paid_e6 = safe_add_i64(position.close_fees_paid_e6, close_fee_e6)?;
+            
+            if open_size_e6 > 0 {
+                // 剩余部分按新方向开仓
+                msg!("Flipping position to {:?}...", side);
+                
+                position.flip(
+                    side,
+                    open_size_e6,
+                    entry_price_e6,
+                    leverage,
+                    margin_mode,
+                    im_e6,
+                    mm_e6,
+                    risk_params,
+                );
+                position.open_fees_paid_e6 = safe_add_i64(position.open_fees_paid_e6, open_fee_e6)?;
+                
+                // 反手后原方向的条件单失效
+                vault.position_nonce = vault.position_nonce.saturating_add(1);
+                position.position_nonce = vault.position_nonce;
+            } else if position.size_e6 != 0 {
+                position.update_pnl(entry_price_e6);
+            }
+        } else {
+            // 更新现有持仓（加仓）
+            msg!("Adding to existing position...");
+            
+            // 更新持仓
+            position.size_e6 = safe_add_i64(position.size_e6, size_e6)?;
+            position.locked_usdc_e6 = safe_add_i64(position.locked_usdc_e6, im_e6)?;
+            position.mm_e6 = safe_add_i64(position.mm_e6, mm_e6)?;
+            
+            // 重新计算均价
+            let total_notional = (position.entry_price_e6 as i128 * (position.size_e6 - size_e6) as i128
+                + entry_price_e6 as i128 * size_e6 as i128) / 1_000_000;
+            position.entry_price_e6 = (total_notional * 1_000_000 / position.size_e6 as i128) as i64;
+            
+            // 分档模式：整个持仓按所在档位的维持保证金率重新计算
+            if bracket.is_some() {
+                position.risk_params = risk_params;
+                position.mm_e6 = calculate_maintenance_margin(
+                    position.size_e6.abs(),
+                    position.entry_price_e6,
+                    risk_params.maintenance_margin_ratio_bp,
+                )?;
+            }
+            
+            position.open_fees_paid_e6 = safe_add_i64(position.open_fees_paid_e6, open_fee_e6)?;
+        }
+        
+        position.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+        is_fully_closed = position.size_e6 == 0;
+        
+        if is_fully_closed {
+            msg!("Position fully closed, closing account...");
+            close_position_account(user_position_account, user)?;
+        } else {
+            position_pnl = position.calculate_unrealized_pnl(entry_price_e6);
+            funding.index_position(&mut position);
+            store_account(user_position_account, &position)?;
+        }
+    } else {
         // 创建新持仓
         msg!("Creating new position...");
         
-        let rent = Rent::get()?;
         let space = UserPosition::MAX_SIZE;
-        let required_lamports = rent.minimum_balance(space);
         
-        invoke_signed(
-            &system_instruction::create_account(
-                user.key,
-                user_position_account.key,
-                required_lamports,
-                space as u64,
-                program_id,
-            ),
-            &[
-                user.clone(),
-                user_position_account.clone(),
-                system_program.clone(),
-            ],
-            &[&[
-                b"position",
-                user.key.as_ref(),
-                account_id.as_bytes(),
-                market.as_bytes(),
-                &[position_bump],
-            ]],
+        // Hedge Mode的PDA seeds额外包含Side
+        let side_seed = [side as u8];
+        let bump_seed = [position_bump];
+        let mut position_seeds: Vec<&[u8]> = vec![
+            POSITION_SEED,
+            user.key.as_ref(),
+            account_id.as_bytes(),
+            market.as_bytes(),
+        ];
+        if hedge_mode {
+            position_seeds.push(&side_seed);
+        }
+        position_seeds.push(&bump_seed);
+        
+        create_pda_account(
+            program_id,
+            user,
+            user_position_account,
+            system_program,
+            space,
+            &position_seeds,
         )?;
         
         // 初始化Position数据
-        let position = UserPosition::new(
+        let mut position = UserPosition::new(
             *user.key,
             account_id.clone(),
             market.clone(),
@@ -299,88 +808,189 @@ fn process_lock_margin(
             margin_mode,
             im_e6,
             mm_e6,
+            risk_params,
             position_bump,
         );
+        position.hedge_mode = hedge_mode;
+        vault.position_nonce = vault.position_nonce.saturating_add(1);
+        position.position_nonce = vault.position_nonce;
+        position.open_fees_paid_e6 = fee_e6;
+        position.last_funding_index = funding.cumulative_funding_index;
+        funding.index_position(&mut position);
         
-        let serialized = position.try_to_vec()
-            .map_err(|_| TradingError::SerializationError)?;
-        
-        user_position_account.data.borrow_mut()[..serialized.len()]
-            .copy_from_slice(&serialized);
+        store_account(user_position_account, &position)?;
+    }
+    
+    // 净额结算：新开仓IM − 对冲释放的保证金和已实现盈亏
+    // 对冲亏损超出释放保证金的部分由用户在本次转账中补足
+    // 全仓：盈亏计入抵押品，只补足抵押品扣除全部未实现亏损后低于占用IM的部分
+    let collateral_before = collateral.as_ref().map_or(0, |account| account.collateral_e6);
+    let unrealized_loss = if is_cross {
+        safe_add_i64(other_unrealized_loss, position_pnl.min(0))?
     } else {
-        // 更新现有持仓（加仓）
-        msg!("Adding to existing position...");
-        
-        let mut position = UserPosition::try_from_slice(&user_position_account.data.borrow())
-            .map_err(|_| TradingError::DeserializationError)?;
-        
-        // 验证方向一致
-        if position.side != side {
-            msg!("Error: Cannot add to position with different side");
-            return Err(TradingError::InvalidSize.into());
+        other_unrealized_loss
+    };
+    
+    let net_deposit = match collateral.as_mut() {
+        Some(cross) if is_cross => {
+            cross.collateral_e6 = safe_add_i64(cross.collateral_e6, realized_pnl)?;
+            cross.realized_pnl_e6 = safe_add_i64(cross.realized_pnl_e6, realized_pnl)?;
+            cross.locked_im_e6 = safe_add_i64(cross.locked_im_e6, im_e6)?;
+            cross.locked_im_e6 = safe_sub_i64(cross.locked_im_e6, released_im)?;
+            
+            if is_fully_closed {
+                cross.remove_position(user_position_account.key);
+            } else {
+                cross.add_position(*user_position_account.key)?;
+            }
+            
+            let equity = safe_add_i64(cross.collateral_e6, unrealized_loss)?;
+            let deposit = safe_sub_i64(cross.locked_im_e6, equity)?.max(0);
+            cross.collateral_e6 = safe_add_i64(cross.collateral_e6, deposit)?;
+            deposit
         }
+        Some(account) => {
+            // 逐仓：IM优先从账户可用抵押品划转，返还计入抵押品
+            let released_amount = safe_add_i64(released_im, realized_pnl)?;
+            let amount = safe_sub_i64(im_e6, released_amount)?;
+            
+            if amount > 0 {
+                let available = safe_add_i64(account.free_collateral_e6(), unrealized_loss)?;
+                let from_collateral = amount.min(available.max(0));
+                account.collateral_e6 = safe_sub_i64(account.collateral_e6, from_collateral)?;
+                safe_sub_i64(amount, from_collateral)?
+            } else {
+                account.collateral_e6 = safe_sub_i64(account.collateral_e6, amount)?;
+                0
+            }
+        }
+        None => {
+            let released_amount = safe_add_i64(released_im, realized_pnl)?;
+            safe_sub_i64(im_e6, released_amount)?
+        }
+    };
+    
+    // 手续费同样优先从可用抵押品（扣除未实现亏损）扣除
+    let fee_from_collateral = match collateral.as_mut() {
+        Some(account) => {
+            let available = safe_add_i64(account.free_collateral_e6(), unrealized_loss)?;
+            let amount = fee_e6.min(available.max(0));
+            account.collateral_e6 = safe_sub_i64(account.collateral_e6, amount)?;
+            amount
+        }
+        None => 0,
+    };
+    let fee_from_wallet = fee_e6 - fee_from_collateral;
+    
+    if net_deposit > 0 {
+        // SPL Token Transfer: 用户 → Vault
+        msg!("Transferring {} USDC to vault...", net_deposit as f64 / 1_000_000.0);
         
-        // 更新持仓
-        position.size_e6 = safe_add_i64(position.size_e6, size_e6)?;
-        position.locked_usdc_e6 = safe_add_i64(position.locked_usdc_e6, im_e6)?;
-        position.mm_e6 = safe_add_i64(position.mm_e6, mm_e6)?;
-        
-        // 重新计算均价
-        let total_notional = (position.entry_price_e6 as i128 * (position.size_e6 - size_e6) as i128
-            + entry_price_e6 as i128 * size_e6 as i128) / 1_000_000;
-        position.entry_price_e6 = (total_notional * 1_000_000 / position.size_e6 as i128) as i64;
-        
-        position.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
-        
-        let serialized = position.try_to_vec()
-            .map_err(|_| TradingError::SerializationError)?;
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                user_usdc_account.key,
+                vault_usdc_account.key,
+                user.key,
+                &[],
+                net_deposit as u64,
+            )?,
+            &[
+                user_usdc_account.clone(),
+                vault_usdc_account.clone(),
+                user.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    } else if net_deposit < 0 {
+        // SPL Token Transfer: Vault → 用户
+        msg!("Returning {} USDC to user...", -net_deposit as f64 / 1_000_000.0);
         
-        user_position_account.data.borrow_mut()[..serialized.len()]
-            .copy_from_slice(&serialized);
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            user_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            -net_deposit,
+        )?;
     }
     
-    // SPL Token Transfer: 用户 → Vault
-    msg!("Transferring {} USDC to vault...", im_e6 as f64 / 1_000_000.0);
+    // SPL Token Transfer: 用户 → Fee Treasury
+    if fee_from_wallet > 0 {
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                user_usdc_account.key,
+                fee_treasury_account.key,
+                user.key,
+                &[],
+                fee_from_wallet as u64,
+            )?,
+            &[
+                user_usdc_account.clone(),
+                fee_treasury_account.clone(),
+                user.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    }
     
-    invoke_signed(
-        &spl_token::instruction::transfer(
-            token_program.key,
-            user_usdc_account.key,
-            vault_usdc_account.key,
-            user.key,
-            &[],
-            im_e6 as u64,
-        )?,
-        &[
-            user_usdc_account.clone(),
-            vault_usdc_account.clone(),
-            user.clone(),
-            token_program.clone(),
-        ],
-        &[],
-    )?;
+    // SPL Token Transfer: Vault → Fee Treasury（从抵押品扣除的手续费）
+    if fee_from_collateral > 0 {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            fee_treasury_account,
+            trading_vault_account,
+            vault_bump,
+            fee_from_collateral,
+        )?;
+    }
     
-    // 更新Trading Vault全局状态
-    let mut vault = TradingVault::try_from_slice(&trading_vault_account.data.borrow())
-        .map_err(|_| TradingError::DeserializationError)?;
+    // 更新Trading Vault全局状态（账户抵押品按变动计入，全仓IM已含在抵押品中）
+    if !is_cross {
+        vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, im_e6)?;
+        vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, released_im)?;
+    }
     
-    vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, im_e6)?;
+    if let Some(mut account) = collateral {
+        let collateral_change = safe_sub_i64(account.collateral_e6, collateral_before)?;
+        vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, collateral_change)?;
+        
+        account.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+        store_account(cross_margin_account, &account)?;
+        log_cross_margin(&account);
+    }
+    vault.cumulative_pnl_e6 = safe_add_i64(vault.cumulative_pnl_e6, realized_pnl)?;
     
     if is_new_position {
         vault.total_positions += 1;
     }
     
-    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    if is_fully_closed {
+        vault.total_positions = vault.total_positions.saturating_sub(1);
+    }
     
-    let serialized = vault.try_to_vec()
-        .map_err(|_| TradingError::SerializationError)?;
+    record_trade(&mut vault, trade_notional, fee_e6)?;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
     
-    trading_vault_account.data.borrow_mut()[..serialized.len()]
-        .copy_from_slice(&serialized);
+    store_trading_vault(trading_vault_account, &vault)?;
+    store_account(funding_state_account, &funding)?;
     
     // Emit事件
-    msg!("POSITION_OPENED|account:{}|market:{}|side:{:?}|size:{}|price:{}|leverage:{}|im:{}|mm:{}",
-        account_id, market, side, size_e6, entry_price_e6, leverage, im_e6, mm_e6);
+    if reduce_size_e6 > 0 {
+        msg!("POSITION_REDUCED|wallet:{}|account:{}|market:{}|side:{:?}|size:{}|price:{}|pnl:{}|released_im:{}|flipped:{}",
+            user.key, account_id, market, side, reduce_size_e6, entry_price_e6, realized_pnl, released_im,
+            open_size_e6 > 0);
+    }
+    
+    if open_size_e6 > 0 {
+        msg!("POSITION_OPENED|account:{}|market:{}|side:{:?}|size:{}|price:{}|leverage:{}|im:{}|mm:{}",
+            account_id, market, side, open_size_e6, entry_price_e6, leverage, im_e6, mm_e6);
+    }
+    msg!("TRADING_FEE_CHARGED|wallet:{}|account:{}|market:{}|action:open|maker:{}|notional:{}|fee_bp:{}|fee:{}",
+        user.key, account_id, market, is_maker, trade_notional, fee_bp, fee_e6);
     
     msg!("✅ Position opened successfully!");
     msg!("  Locked USDC: {} (e6)", im_e6);
@@ -398,6 +1008,23 @@ fn process_unlock_margin(
     close_size_e6: i64,
     exit_price_e6: i64,
 ) -> ProgramResult {
+    close_position(program_id, accounts, account_id, market, close_size_e6, exit_price_e6, None)?;
+    Ok(())
+}
+
+/// 平仓结算（UnlockMargin和条件单执行共用），返回支付给Keeper的奖励
+/// 
+/// `accounts`按UnlockMargin账户顺序传入；`keeper`为条件单执行时的
+/// (Keeper USDC账户, 奖励费率)，此时持仓所有者无需签名，奖励从返还金额中扣除
+fn close_position<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    account_id: String,
+    market: String,
+    close_size_e6: i64,
+    exit_price_e6: i64,
+    keeper: Option<(&AccountInfo<'a>, u32)>,
+) -> Result<i64, ProgramError> {
     let account_iter = &mut accounts.iter();
     
     let user_position_account = next_account_info(account_iter)?;
@@ -406,6 +1033,13 @@ fn process_unlock_margin(
     let vault_usdc_account = next_account_info(account_iter)?;
     let trading_vault_account = next_account_info(account_iter)?;
     let token_program = next_account_info(account_iter)?;
+    let insurance_fund_account = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let fee_treasury_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
+    let cross_margin_account = next_account_info(account_iter)?;
+    let maker_attestation = next_account_info(account_iter).ok();
     
     msg!("Unlock Margin - Closing Position");
     msg!("  Account: {}", account_id);
@@ -417,25 +1051,58 @@ fn process_unlock_margin(
     validate_size(close_size_e6)?;
     validate_price(exit_price_e6)?;
     
-    // 验证user签名
-    if !user.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+    // 验证账户（条件单由Keeper执行时持仓所有者无需签名）
+    if keeper.is_none() {
+        validate_signer(user)?;
     }
+    validate_token_program(token_program)?;
     
-    // 读取持仓
-    let mut position = UserPosition::try_from_slice(&user_position_account.data.borrow())
-        .map_err(|_| TradingError::DeserializationError)?;
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    validate_token_account(user_usdc_account, user.key, &vault.usdc_mint)?;
+    validate_insurance_fund_account(insurance_fund_account, &vault)?;
+    validate_fee_treasury_account(fee_treasury_account, &vault)?;
     
-    // 验证持仓归属
-    if position.wallet != *user.key {
-        return Err(TradingError::InvalidPositionAccount.into());
-    }
+    // 读取持仓（验证PDA和归属）
+    let mut position = load_user_position(
+        program_id,
+        user_position_account,
+        user.key,
+        &account_id,
+        &market,
+    )?;
     
     // 验证平仓数量
     if close_size_e6 > position.size_e6.abs() {
         return Err(TradingError::InvalidSize.into());
     }
     
+    // 全仓持仓：盈亏和手续费在账户抵押品内结算；逐仓已Deposit时返还计入抵押品
+    let mut collateral =
+        load_optional_cross_margin_account(program_id, cross_margin_account, user.key, &account_id)?;
+    let is_cross = is_registered_cross(user_position_account.key, &position, collateral.as_ref());
+    
+    // 平仓前先结算资金费
+    let mut funding = load_funding_state(program_id, funding_state_account, &market)?;
+    let funding_received = settle_funding(&mut position, &funding, &mut vault)?;
+    
+    if let Some(cross) = collateral.as_mut().filter(|_| is_cross) {
+        cross.adjust_position_margin(funding_received);
+    }
+    
+    // 验证市场配置（任何市场状态都允许平仓；全部平仓不受lot size限制）
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, false)?;
+    
+    if close_size_e6 != position.size_e6.abs() {
+        validate_lot_size(close_size_e6, &market_config)?;
+    }
+    
+    // 平仓价格必须在预言机价格band内
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    validate_against_oracle(price_feed_account, &market_config, exit_price_e6, now)?;
+    
     // 计算Realized PnL
     let is_long = matches!(position.side, Side::Buy);
     let realized_pnl = calculate_realized_pnl(
@@ -450,93 +1117,198 @@ fn process_unlock_margin(
     // 计算释放的保证金
     let close_ratio = close_size_e6 as i128 * 1_000_000 / position.size_e6.abs() as i128;
     let released_im = (position.locked_usdc_e6 as i128 * close_ratio / 1_000_000) as i64;
+    let released_mm = (position.mm_e6 as i128 * close_ratio / 1_000_000) as i64;
+    
+    // 交易手续费（按成交名义价值，从返还金额中扣除，不足时只扣到0）
+    let trade_notional = calculate_notional(close_size_e6, exit_price_e6)?;
+    let is_maker = is_maker_fill(maker_attestation, &vault);
+    let fee_bp = vault.trading_fee_bp(is_maker);
+    let return_before_fee = safe_add_i64(released_im, realized_pnl)?;
+    
+    // 全仓：盈亏计入抵押品，手续费从可用抵押品中扣除
+    let collateral_before = collateral.as_ref().map_or(0, |account| account.collateral_e6);
+    
+    if let Some(cross) = collateral.as_mut().filter(|_| is_cross) {
+        cross.collateral_e6 = safe_add_i64(cross.collateral_e6, realized_pnl)?;
+        cross.realized_pnl_e6 = safe_add_i64(cross.realized_pnl_e6, realized_pnl)?;
+        cross.locked_im_e6 = safe_sub_i64(cross.locked_im_e6, released_im)?;
+    }
+    
+    let fee_available = match &collateral {
+        Some(cross) if is_cross => cross.free_collateral_e6(),
+        _ => return_before_fee,
+    };
+    let fee_e6 = calculate_fee_bp(trade_notional, fee_bp).min(fee_available.max(0));
+    
+    msg!("  Fee: {} USDC ({}bp)", fee_e6 as f64 / 1_000_000.0, fee_bp);
+    
+    // Keeper奖励在手续费之后扣除，同样不足时只扣到0
+    let keeper_fee_e6 = match keeper {
+        Some((_, keeper_fee_bp)) => calculate_fee_bp(trade_notional, keeper_fee_bp)
+            .min(safe_sub_i64(fee_available, fee_e6)?.max(0)),
+        None => 0,
+    };
+    
+    if keeper_fee_e6 > 0 {
+        msg!("  Keeper Fee: {} USDC", keeper_fee_e6 as f64 / 1_000_000.0);
+    }
     
     // 计算返还金额
-    let return_amount = safe_add_i64(released_im, realized_pnl)?;
+    let return_amount = safe_sub_i64(safe_sub_i64(return_before_fee, fee_e6)?, keeper_fee_e6)?;
+    
+    // 穿仓：逐仓亏损超出释放的保证金；全仓亏损超出账户全部抵押品
+    let shortfall = match collateral.as_mut() {
+        Some(cross) if is_cross => {
+            cross.collateral_e6 = safe_sub_i64(cross.collateral_e6, fee_e6)?;
+            cross.collateral_e6 = safe_sub_i64(cross.collateral_e6, keeper_fee_e6)?;
+            let shortfall = (-cross.collateral_e6).max(0);
+            cross.collateral_e6 = cross.collateral_e6.max(0);
+            shortfall
+        }
+        _ => (-return_amount).max(0),
+    };
     
-    if return_amount < 0 {
-        msg!("Warning: Return amount is negative (loss), returning 0");
+    if shortfall > 0 {
+        msg!("Warning: Loss exceeds released margin by {} USDC", shortfall as f64 / 1_000_000.0);
     }
     
-    let actual_return = return_amount.max(0);
+    // 有账户抵押品时返还留在抵押品中（Withdraw提取），否则转给用户
+    let actual_return = match collateral.as_mut() {
+        Some(cross) if is_cross => {
+            if close_size_e6 == position.size_e6.abs() {
+                cross.remove_position(user_position_account.key);
+            }
+            0
+        }
+        Some(account) => {
+            account.collateral_e6 = safe_add_i64(account.collateral_e6, return_amount.max(0))?;
+            0
+        }
+        None => return_amount.max(0),
+    };
     
     msg!("  Released IM: {} USDC", released_im as f64 / 1_000_000.0);
     msg!("  Return Amount: {} USDC", actual_return as f64 / 1_000_000.0);
     
     // SPL Token Transfer: Vault → 用户
     if actual_return > 0 {
-        // 派生Vault PDA以签名
-        let (vault_pda, vault_bump) = Pubkey::find_program_address(
-            &[b"trading_vault"],
-            program_id,
-        );
-        
-        invoke_signed(
-            &spl_token::instruction::transfer(
-                token_program.key,
-                vault_usdc_account.key,
-                user_usdc_account.key,
-                &vault_pda,
-                &[],
-                actual_return as u64,
-            )?,
-            &[
-                vault_usdc_account.clone(),
-                user_usdc_account.clone(),
-                trading_vault_account.clone(),
-                token_program.clone(),
-            ],
-            &[&[b"trading_vault", &[vault_bump]]],
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            user_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            actual_return,
+        )?;
+    }
+    
+    // SPL Token Transfer: Vault → Fee Treasury
+    if fee_e6 > 0 {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            fee_treasury_account,
+            trading_vault_account,
+            vault_bump,
+            fee_e6,
+        )?;
+    }
+    
+    // SPL Token Transfer: Vault → Keeper
+    if let Some((keeper_usdc_account, _)) = keeper.filter(|_| keeper_fee_e6 > 0) {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            keeper_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            keeper_fee_e6,
         )?;
     }
     
     // 更新持仓
     position.size_e6 = safe_sub_i64(position.size_e6, close_size_e6)?;
     position.locked_usdc_e6 = safe_sub_i64(position.locked_usdc_e6, released_im)?;
-    position.mm_e6 = calculate_maintenance_margin(position.locked_usdc_e6);
+    position.mm_e6 = safe_sub_i64(position.mm_e6, released_mm)?;
     position.realized_pnl_e6 = safe_add_i64(position.realized_pnl_e6, realized_pnl)?;
+    position.close_fees_paid_e6 = safe_add_i64(position.close_fees_paid_e6, fee_e6)?;
     position.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
     
     let is_fully_closed = position.size_e6 == 0;
     
     if is_fully_closed {
-        msg!("Position fully closed, clearing account...");
-        // TODO: 关闭PDA账户并返还租金
+        msg!("Position fully closed, closing account...");
+        // 关闭PDA账户，租金返还给用户（开仓时由用户支付）
+        funding.unindex_position(&mut position);
+        close_position_account(user_position_account, user)?;
     } else {
-        let serialized = position.try_to_vec()
-            .map_err(|_| TradingError::SerializationError)?;
-        
-        user_position_account.data.borrow_mut()[..serialized.len()]
-            .copy_from_slice(&serialized);
+        funding.index_position(&mut position);
+        store_account(user_position_account, &position)?;
     }
     
-    // 更新Trading Vault
-    let mut vault = TradingVault::try_from_slice(&trading_vault_account.data.borrow())
-        .map_err(|_| TradingError::DeserializationError)?;
+    // 更新Trading Vault（账户抵押品按变动计入，全仓IM已含在抵押品中）
+    let margin_absorbed = if is_cross { collateral_before } else { released_im };
+    
+    if !is_cross {
+        vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, released_im)?;
+    }
     
-    vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, released_im)?;
+    if let Some(mut account) = collateral {
+        let collateral_change = safe_sub_i64(account.collateral_e6, collateral_before)?;
+        vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, collateral_change)?;
+        
+        account.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+        store_account(cross_margin_account, &account)?;
+        log_cross_margin(&account);
+    }
     
     if is_fully_closed {
         vault.total_positions = vault.total_positions.saturating_sub(1);
     }
     
     vault.cumulative_pnl_e6 = safe_add_i64(vault.cumulative_pnl_e6, realized_pnl)?;
-    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    record_trade(&mut vault, trade_notional, fee_e6)?;
     
-    let serialized = vault.try_to_vec()
-        .map_err(|_| TradingError::SerializationError)?;
+    // 穿仓亏损分摊：保证金 → Insurance Fund → 坏账
+    if shortfall > 0 {
+        msg!("LOSS_MARGIN_ABSORBED|wallet:{}|account:{}|market:{}|amount:{}",
+            user.key, account_id, market, margin_absorbed);
+        
+        let bad_debt = cover_shortfall(
+            &mut vault,
+            token_program,
+            insurance_fund_account,
+            vault_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            user.key,
+            &account_id,
+            &market,
+            shortfall,
+        )?;
+        
+        // 坏账记录到市场，由ADL按破产价格向反方向盈利持仓收回
+        if bad_debt > 0 {
+            let bankruptcy_price = calculate_bankruptcy_price(is_long, exit_price_e6, shortfall, close_size_e6);
+            record_bankruptcy(funding_state_account, &mut funding, position.side, bankruptcy_price, bad_debt)?;
+        }
+    }
+    
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
     
-    trading_vault_account.data.borrow_mut()[..serialized.len()]
-        .copy_from_slice(&serialized);
+    store_trading_vault(trading_vault_account, &vault)?;
+    store_account(funding_state_account, &funding)?;
     
     // Emit事件
     msg!("POSITION_CLOSED|account:{}|market:{}|close_size:{}|exit_price:{}|pnl:{}|returned:{}",
         account_id, market, close_size_e6, exit_price_e6, realized_pnl, actual_return);
+    msg!("TRADING_FEE_CHARGED|wallet:{}|account:{}|market:{}|action:close|maker:{}|notional:{}|fee_bp:{}|fee:{}",
+        user.key, account_id, market, is_maker, trade_notional, fee_bp, fee_e6);
     
     msg!("✅ Position closed successfully!");
     msg!("  Returned: {} USDC (e6)", actual_return);
     
-    Ok(())
+    Ok(keeper_fee_e6)
 }
 
 /// 强平
@@ -559,41 +1331,51 @@ fn process_liquidate(
     let fee_treasury_account = next_account_info(account_iter)?;
     let trading_vault_account = next_account_info(account_iter)?;
     let token_program = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
     
     msg!("Liquidate - Processing");
     msg!("  Account: {}", account_id);
     msg!("  Market: {}", market);
     msg!("  Liquidation Price: {}", liquidation_price_e6);
     
-    // 验证liquidator签名
-    if !liquidator.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    validate_price(liquidation_price_e6)?;
     
-    // 验证Position PDA
-    let (expected_pda, _) = Pubkey::find_program_address(
-        &[
-            b"position",
-            position_owner.key.as_ref(),
-            account_id.as_bytes(),
-            market.as_bytes(),
-        ],
-        program_id,
-    );
+    // 验证账户
+    validate_signer(liquidator)?;
+    validate_token_program(token_program)?;
     
-    if user_position_account.key != &expected_pda {
-        msg!("Error: Position PDA mismatch");
-        return Err(TradingError::InvalidPositionAccount.into());
-    }
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    let usdc_mint = vault.usdc_mint;
     
-    // 读取持仓
-    let mut position = UserPosition::try_from_slice(&user_position_account.data.borrow())
-        .map_err(|_| TradingError::DeserializationError)?;
+    // 剩余资金只能返还到持仓所有者的USDC账户
+    validate_token_account(user_usdc_account, position_owner.key, &usdc_mint)?;
+    validate_token_account(liquidator_usdc_account, liquidator.key, &usdc_mint)?;
+    validate_insurance_fund_account(insurance_fund_account, &vault)?;
+    validate_fee_treasury_account(fee_treasury_account, &vault)?;
     
-    // 验证持仓归属
-    if position.wallet != *position_owner.key {
-        return Err(TradingError::InvalidPositionAccount.into());
-    }
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, false)?;
+    
+    // 清算价格必须在预言机价格band内，防止用伪造价格强平健康持仓
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    validate_against_oracle(price_feed_account, &market_config, liquidation_price_e6, now)?;
+    
+    // 读取持仓（验证PDA和归属）
+    let mut position = load_user_position(
+        program_id,
+        user_position_account,
+        position_owner.key,
+        &account_id,
+        &market,
+    )?;
+    
+    // 结算资金费（计入锁定保证金后再判断是否可强平）
+    let mut funding = load_funding_state(program_id, funding_state_account, &market)?;
+    let funding_received = settle_funding(&mut position, &funding, &mut vault)?;
     
     // 更新PnL（使用清算价格）
     position.update_pnl(liquidation_price_e6);
@@ -601,234 +1383,2873 @@ fn process_liquidate(
     msg!("  Margin Ratio: {}bp", position.margin_ratio_bp);
     msg!("  Unrealized PnL: {}", position.unrealized_pnl_e6);
     
-    // 验证可强平（保证金率 < 100%）
-    if position.margin_ratio_bp >= 10000 {
-        msg!("Error: Position not liquidatable, margin_ratio = {}bp", position.margin_ratio_bp);
-        return Err(TradingError::PositionNotLiquidatable.into());
-    }
-    
-    // 计算清算相关金额
-    let equity = position.locked_usdc_e6 + position.unrealized_pnl_e6;
-    let locked_usdc = position.locked_usdc_e6;
-    
-    // 清算手续费：0.5% of locked_usdc
-    let liquidation_fee = calculate_liquidation_fee(locked_usdc);
+    // 风控参数使用开仓时的快照
+    let risk_params = position.effective_risk_params();
     
-    msg!("  Equity: {} USDC", equity as f64 / 1_000_000.0);
-    msg!("  Liquidation Fee: {} USDC", liquidation_fee as f64 / 1_000_000.0);
+    // 全仓持仓传入账户抵押品；未登记的旧全仓持仓按逐仓强平
+    let registered_cross = if position.margin_mode == MarginMode::Cross {
+        let cross_margin_account = next_account_info(account_iter)?;
+        load_optional_cross_margin_account(program_id, cross_margin_account, position_owner.key, &account_id)?
+            .filter(|cross| is_registered_cross(user_position_account.key, &position, Some(cross)))
+            .map(|cross| (cross_margin_account, cross))
+    } else {
+        None
+    };
     
-    // 派生Vault PDA以签名
-    let (vault_pda, vault_bump) = Pubkey::find_program_address(
-        &[b"trading_vault"],
-        program_id,
-    );
+    let cross_margin = if let Some((cross_margin_account, mut cross)) = registered_cross {
+        // 全仓：账户权益 < 全部全仓持仓MM 时才可强平
+        cross.adjust_position_margin(funding_received);
+        
+        let (account_equity, account_mm) = calculate_cross_margin_health(
+            program_id,
+            &cross,
+            user_position_account.key,
+            &position,
+            account_iter.as_slice(),
+            now,
+        )?;
+        
+        msg!("  Account Equity: {} USDC", account_equity as f64 / 1_000_000.0);
+        msg!("  Account MM: {} USDC", account_mm as f64 / 1_000_000.0);
+        
+        if account_equity >= account_mm {
+            msg!("Error: Cross margin account not liquidatable");
+            return Err(TradingError::PositionNotLiquidatable.into());
+        }
+        
+        Some((cross_margin_account, cross))
+    } else {
+        // 验证可强平（保证金率 < liquidation_threshold）
+        if position.liquidation_status != LiquidationStatus::Liquidatable {
+            msg!("Error: Position not liquidatable, margin_ratio = {}bp", position.margin_ratio_bp);
+            return Err(TradingError::PositionNotLiquidatable.into());
+        }
+        
+        None
+    };
     
-    // 分配资金
-    let mut total_transferred: i64 = 0;
+    // 部分强平：只平掉使保证金率回到目标值所需的数量（全仓持仓整体强平）
+    let position_size_e6 = position.size_e6.abs();
+    let liquidation_size_e6 = if cross_margin.is_some() {
+        position_size_e6
+    } else {
+        calculate_partial_liquidation_size(
+            position_size_e6,
+            position.locked_usdc_e6,
+            position.mm_e6,
+            position.unrealized_pnl_e6,
+            risk_params.liquidation_fee_bp,
+            market_config.partial_liquidation_target_bp,
+            market_config.lot_size_e6,
+        )
+    };
     
-    // 1. 清算手续费（50%给清算人，50%给Fee Treasury）
-    if liquidation_fee > 0 {
+    if liquidation_size_e6 < position_size_e6 {
+        msg!("  Partial Liquidation Size: {}", liquidation_size_e6);
+        
+        // 被平部分按清算价格实现盈亏，清算费只按被平部分的保证金收取
+        let realized_pnl = (position.unrealized_pnl_e6 as i128 * liquidation_size_e6 as i128
+            / position_size_e6 as i128) as i64;
+        let liquidated_locked = (position.locked_usdc_e6 as i128 * liquidation_size_e6 as i128
+            / position_size_e6 as i128) as i64;
+        let released_mm = (position.mm_e6 as i128 * liquidation_size_e6 as i128
+            / position_size_e6 as i128) as i64;
+        let liquidation_fee = calculate_fee_bp(liquidated_locked, risk_params.liquidation_fee_bp);
+        
+        // 清算手续费（50%给清算人，50%给Fee Treasury）
         let liquidator_fee = liquidation_fee / 2;
         let treasury_fee = liquidation_fee - liquidator_fee;
         
-        // 给清算人
         if liquidator_fee > 0 {
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    token_program.key,
-                    vault_usdc_account.key,
-                    liquidator_usdc_account.key,
-                    &vault_pda,
-                    &[],
-                    liquidator_fee as u64,
-                )?,
-                &[
-                    vault_usdc_account.clone(),
-                    liquidator_usdc_account.clone(),
-                    trading_vault_account.clone(),
-                    token_program.clone(),
-                ],
-                &[&[b"trading_vault", &[vault_bump]]],
+            transfer_from_vault(
+                token_program,
+                vault_usdc_account,
+                liquidator_usdc_account,
+                trading_vault_account,
+                vault_bump,
+                liquidator_fee,
             )?;
-            total_transferred = safe_add_i64(total_transferred, liquidator_fee)?;
-            msg!("  Liquidator Fee: {} USDC", liquidator_fee as f64 / 1_000_000.0);
         }
         
-        // 给Fee Treasury
         if treasury_fee > 0 {
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    token_program.key,
-                    vault_usdc_account.key,
-                    fee_treasury_account.key,
-                    &vault_pda,
-                    &[],
-                    treasury_fee as u64,
-                )?,
-                &[
-                    vault_usdc_account.clone(),
-                    fee_treasury_account.clone(),
-                    trading_vault_account.clone(),
-                    token_program.clone(),
-                ],
-                &[&[b"trading_vault", &[vault_bump]]],
+            transfer_from_vault(
+                token_program,
+                vault_usdc_account,
+                fee_treasury_account,
+                trading_vault_account,
+                vault_bump,
+                treasury_fee,
             )?;
-            total_transferred = safe_add_i64(total_transferred, treasury_fee)?;
-            msg!("  Fee Treasury: {} USDC", treasury_fee as f64 / 1_000_000.0);
         }
-    }
-    
-    // 2. 剩余资金处理
-    let remaining = safe_sub_i64(locked_usdc, total_transferred)?;
-    
-    if equity > 0 && remaining > 0 {
-        // 有剩余返还给用户
-        let user_return = remaining.min(equity);
         
-        if user_return > 0 {
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    token_program.key,
-                    vault_usdc_account.key,
-                    user_usdc_account.key,
-                    &vault_pda,
-                    &[],
-                    user_return as u64,
-                )?,
-                &[
-                    vault_usdc_account.clone(),
-                    user_usdc_account.clone(),
-                    trading_vault_account.clone(),
-                    token_program.clone(),
-                ],
-                &[&[b"trading_vault", &[vault_bump]]],
+        // 被平部分的亏损进Insurance Fund（与整体强平时保证金承担的亏损一致）
+        let margin_loss = (-realized_pnl).max(0);
+        
+        if margin_loss > 0 {
+            transfer_from_vault(
+                token_program,
+                vault_usdc_account,
+                insurance_fund_account,
+                trading_vault_account,
+                vault_bump,
+                margin_loss,
             )?;
-            total_transferred = safe_add_i64(total_transferred, user_return)?;
-            msg!("  User Returned: {} USDC", user_return as f64 / 1_000_000.0);
         }
+        
+        msg!("  Realized PnL: {} USDC", realized_pnl as f64 / 1_000_000.0);
+        msg!("  Liquidation Fee: {} USDC", liquidation_fee as f64 / 1_000_000.0);
+        msg!("  Insurance Fund: {} USDC", margin_loss as f64 / 1_000_000.0);
+        
+        // 剩余持仓：已实现盈亏和清算费从锁定保证金中结算
+        let locked_change = safe_sub_i64(realized_pnl, liquidation_fee)?;
+        
+        position.size_e6 = safe_sub_i64(position.size_e6, liquidation_size_e6)?;
+        position.locked_usdc_e6 = safe_add_i64(position.locked_usdc_e6, locked_change)?;
+        position.mm_e6 = safe_sub_i64(position.mm_e6, released_mm)?;
+        position.realized_pnl_e6 = safe_add_i64(position.realized_pnl_e6, realized_pnl)?;
+        position.update_pnl(liquidation_price_e6);
+        position.update_liquidation_price();
+        funding.index_position(&mut position);
+        
+        store_account(user_position_account, &position)?;
+        store_account(funding_state_account, &funding)?;
+        
+        // 更新Trading Vault
+        vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, locked_change)?;
+        vault.cumulative_pnl_e6 = safe_add_i64(vault.cumulative_pnl_e6, realized_pnl)?;
+        vault.total_liquidations += 1;
+        vault.insurance_fund_e6 = safe_add_i64(vault.insurance_fund_e6, margin_loss)?;
+        vault.fee_treasury_e6 = safe_add_i64(vault.fee_treasury_e6, treasury_fee)?;
+        vault.cumulative_fees_e6 = safe_add_i64(vault.cumulative_fees_e6, treasury_fee)?;
+        vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+        
+        store_trading_vault(trading_vault_account, &vault)?;
+        
+        // Emit事件
+        msg!("PARTIAL_LIQUIDATION|wallet:{}|account:{}|market:{}|liquidation_price:{}|size:{}|remaining_size:{}|pnl:{}|liquidation_fee:{}|margin_loss:{}|margin_ratio:{}|new_liquidation_price:{}",
+            position_owner.key, account_id, market, liquidation_price_e6, liquidation_size_e6,
+            position.size_e6, realized_pnl, liquidation_fee, margin_loss, position.margin_ratio_bp,
+            position.liquidation_price_e6);
+        
+        msg!("✅ Position partially liquidated!");
+        
+        return Ok(());
     }
     
-    // 3. 剩余进Insurance Fund（如果还有）
-    let final_remaining = safe_sub_i64(locked_usdc, total_transferred)?;
+    // 计算清算相关金额
+    let equity = position.locked_usdc_e6 + position.unrealized_pnl_e6;
+    let locked_usdc = position.locked_usdc_e6;
+    
+    // 清算手续费：liquidation_fee_bp of locked_usdc（equity不足时由waterfall截断）
+    let liquidation_fee = calculate_fee_bp(locked_usdc, risk_params.liquidation_fee_bp);
     
-    if final_remaining > 0 {
-        invoke_signed(
-            &spl_token::instruction::transfer(
-                token_program.key,
-                vault_usdc_account.key,
-                insurance_fund_account.key,
-                &vault_pda,
-                &[],
-                final_remaining as u64,
-            )?,
-            &[
-                vault_usdc_account.clone(),
-                insurance_fund_account.clone(),
-                trading_vault_account.clone(),
-                token_program.clone(),
-            ],
-            &[&[b"trading_vault", &[vault_bump]]],
+    // 多级扣款：持仓保证金 → 账户可用抵押品 → Insurance Fund → ADL
+    // 逐仓持仓没有可用于弥补穿仓的账户抵押品
+    let free_collateral = cross_margin
+        .as_ref()
+        .map_or(0, |(_, cross)| cross.free_collateral_e6().max(0));
+    let waterfall = calculate_liquidation_waterfall(
+        locked_usdc,
+        equity,
+        liquidation_fee,
+        free_collateral,
+        vault.insurance_fund_e6,
+    );
+    
+    msg!("  Equity: {} USDC", equity as f64 / 1_000_000.0);
+    msg!("  Liquidation Fee: {} USDC", waterfall.liquidation_fee_e6() as f64 / 1_000_000.0);
+    
+    // 1. 清算手续费（50%给清算人，50%给Fee Treasury）
+    if waterfall.liquidator_fee_e6 > 0 {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            liquidator_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            waterfall.liquidator_fee_e6,
         )?;
-        msg!("  Insurance Fund: {} USDC", final_remaining as f64 / 1_000_000.0);
+        msg!("  Liquidator Fee: {} USDC", waterfall.liquidator_fee_e6 as f64 / 1_000_000.0);
     }
     
-    // 更新Trading Vault
-    let mut vault = TradingVault::try_from_slice(&trading_vault_account.data.borrow())
-        .map_err(|_| TradingError::DeserializationError)?;
+    if waterfall.treasury_fee_e6 > 0 {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            fee_treasury_account,
+            trading_vault_account,
+            vault_bump,
+            waterfall.treasury_fee_e6,
+        )?;
+        msg!("  Fee Treasury: {} USDC", waterfall.treasury_fee_e6 as f64 / 1_000_000.0);
+    }
+    
+    // 2. 剩余权益返还给用户（全仓留在账户抵押品中）
+    if waterfall.user_return_e6 > 0 && cross_margin.is_none() {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            user_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            waterfall.user_return_e6,
+        )?;
+        msg!("  User Returned: {} USDC", waterfall.user_return_e6 as f64 / 1_000_000.0);
+    }
+    
+    // 3. 保证金承担的亏损进Insurance Fund
+    if waterfall.margin_loss_e6 > 0 {
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            insurance_fund_account,
+            trading_vault_account,
+            vault_bump,
+            waterfall.margin_loss_e6,
+        )?;
+        msg!("  Insurance Fund: {} USDC", waterfall.margin_loss_e6 as f64 / 1_000_000.0);
+    }
     
-    vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, locked_usdc)?;
+    // 更新Trading Vault（全仓按抵押品变动计入）
+    if let Some((cross_margin_account, mut cross)) = cross_margin {
+        let collateral_before = cross.collateral_e6;
+        
+        // 保证金中的亏损和清算费、可用抵押品承担的穿仓亏损从抵押品中扣除
+        let collateral_used = safe_sub_i64(locked_usdc, waterfall.user_return_e6)?;
+        cross.collateral_e6 = safe_sub_i64(cross.collateral_e6, collateral_used)?;
+        cross.collateral_e6 = safe_sub_i64(cross.collateral_e6, waterfall.free_collateral_e6)?;
+        
+        // 盈利持仓的权益超出保证金的部分由waterfall截断，按已实现盈利计入抵押品
+        let realized_profit = (equity - locked_usdc).max(0);
+        cross.collateral_e6 = safe_add_i64(cross.collateral_e6, realized_profit)?;
+        
+        cross.locked_im_e6 = safe_sub_i64(cross.locked_im_e6, locked_usdc)?;
+        cross.remove_position(user_position_account.key);
+        
+        let collateral_change = safe_sub_i64(cross.collateral_e6, collateral_before)?;
+        vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, collateral_change)?;
+        
+        cross.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+        store_account(cross_margin_account, &cross)?;
+        log_cross_margin(&cross);
+    } else {
+        vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, locked_usdc)?;
+    }
     vault.total_positions = vault.total_positions.saturating_sub(1);
     vault.total_liquidations += 1;
+    vault.insurance_fund_e6 = safe_add_i64(vault.insurance_fund_e6, waterfall.margin_loss_e6)?;
+    
+    // Fee Treasury只记录清算费中归属Treasury的部分
+    vault.fee_treasury_e6 = safe_add_i64(vault.fee_treasury_e6, waterfall.treasury_fee_e6)?;
+    vault.cumulative_fees_e6 = safe_add_i64(vault.cumulative_fees_e6, waterfall.treasury_fee_e6)?;
     
-    if final_remaining > 0 {
-        vault.insurance_fund_e6 = safe_add_i64(vault.insurance_fund_e6, final_remaining)?;
+    // 4. 穿仓亏损分摊：账户可用抵押品 → Insurance Fund → 坏账（ADL）
+    if waterfall.shortfall_e6 > 0 {
+        msg!("LOSS_MARGIN_ABSORBED|wallet:{}|account:{}|market:{}|amount:{}",
+            position_owner.key, account_id, market, locked_usdc);
+        
+        if waterfall.free_collateral_e6 > 0 {
+            msg!("LOSS_COLLATERAL_ABSORBED|wallet:{}|account:{}|market:{}|amount:{}",
+                position_owner.key, account_id, market, waterfall.free_collateral_e6);
+        }
+        
+        if waterfall.insurance_fund_e6 > 0 {
+            transfer_from_vault(
+                token_program,
+                insurance_fund_account,
+                vault_usdc_account,
+                trading_vault_account,
+                vault_bump,
+                waterfall.insurance_fund_e6,
+            )?;
+            
+            vault.insurance_fund_e6 = safe_sub_i64(vault.insurance_fund_e6, waterfall.insurance_fund_e6)?;
+            
+            msg!("LOSS_INSURANCE_COVERED|wallet:{}|account:{}|market:{}|amount:{}|insurance_remaining:{}",
+                position_owner.key, account_id, market, waterfall.insurance_fund_e6, vault.insurance_fund_e6);
+        }
+        
+        if waterfall.adl_e6 > 0 {
+            vault.bad_debt_e6 = safe_add_i64(vault.bad_debt_e6, waterfall.adl_e6)?;
+            
+            msg!("LOSS_BAD_DEBT_SOCIALIZED|wallet:{}|account:{}|market:{}|amount:{}|total_bad_debt:{}",
+                position_owner.key, account_id, market, waterfall.adl_e6, vault.bad_debt_e6);
+            
+            // 破产价格：保证金和账户抵押品恰好耗尽的价格
+            let bankruptcy_price = calculate_bankruptcy_price(
+                matches!(position.side, Side::Buy),
+                liquidation_price_e6,
+                safe_add_i64(waterfall.insurance_fund_e6, waterfall.adl_e6)?,
+                position_size_e6,
+            );
+            record_bankruptcy(funding_state_account, &mut funding, position.side, bankruptcy_price, waterfall.adl_e6)?;
+        }
     }
     
+    msg!("LIQUIDATION_WATERFALL|wallet:{}|account:{}|market:{}|locked:{}|margin_loss:{}|liquidator_fee:{}|treasury_fee:{}|user_return:{}|shortfall:{}|free_collateral:{}|insurance:{}|adl:{}",
+        position_owner.key, account_id, market, locked_usdc, waterfall.margin_loss_e6,
+        waterfall.liquidator_fee_e6, waterfall.treasury_fee_e6, waterfall.user_return_e6,
+        waterfall.shortfall_e6, waterfall.free_collateral_e6, waterfall.insurance_fund_e6, waterfall.adl_e6);
+    
     vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
     
-    let serialized = vault.try_to_vec()
-        .map_err(|_| TradingError::SerializationError)?;
+    store_trading_vault(trading_vault_account, &vault)?;
     
-    trading_vault_account.data.borrow_mut()[..serialized.len()]
-        .copy_from_slice(&serialized);
+    // 关闭Position PDA，租金作为清算奖励给清算人
+    funding.unindex_position(&mut position);
+    store_account(funding_state_account, &funding)?;
     
-    // 删除Position PDA（TODO: 实际应该close account并返还租金）
-    // 暂时清零数据
-    user_position_account.data.borrow_mut().fill(0);
+    let rent_refund = user_position_account.lamports();
+    close_position_account(user_position_account, liquidator)?;
+    msg!("  Rent Bonus: {} lamports", rent_refund);
     
     // Emit事件
     msg!("LIQUIDATION|account:{}|market:{}|liquidation_price:{}|loss:{}|liquidation_fee:{}|equity:{}",
-        account_id, market, liquidation_price_e6, 
-        locked_usdc - equity, liquidation_fee, equity);
+        account_id, market, liquidation_price_e6,
+        locked_usdc - equity, waterfall.liquidation_fee_e6(), equity);
     
     msg!("✅ Position liquidated successfully!");
     msg!("  Total locked: {} USDC", locked_usdc as f64 / 1_000_000.0);
-    msg!("  Liquidation fee: {} USDC", liquidation_fee as f64 / 1_000_000.0);
+    msg!("  Liquidation fee: {} USDC", waterfall.liquidation_fee_e6() as f64 / 1_000_000.0);
     msg!("  Loss: {} USDC", (locked_usdc - equity) as f64 / 1_000_000.0);
     
     Ok(())
 }
 
-/// 更新持仓（链下定期调用）
-fn process_update_position(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    account_id: String,
-    market: String,
-    wallet: Pubkey,
-    mark_price_e6: i64,
-) -> ProgramResult {
-    let account_iter = &mut accounts.iter();
+/// 结算资金费：按持仓记录的index与市场当前index之差计入locked_usdc
+/// 
+/// 应付资金费超出锁定保证金的部分无法收取，计入坏账。返回持仓收到的金额（负=支付）
+fn settle_funding(
+    position: &mut UserPosition,
+    funding: &FundingState,
+    vault: &mut TradingVault,
+) -> Result<i64, ProgramError> {
+    let index_delta = funding.cumulative_funding_index - position.last_funding_index;
+    position.last_funding_index = funding.cumulative_funding_index;
     
-    let user_position_account = next_account_info(account_iter)?;
-    let authority = next_account_info(account_iter)?;
+    if index_delta == 0 || position.size_e6 == 0 {
+        return Ok(0);
+    }
     
-    // 验证authority
-    if !authority.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+    let is_long = matches!(position.side, Side::Buy);
+    let payment = calculate_funding_payment(is_long, position.size_e6.abs(), index_delta)?;
+    
+    // 支付不超过锁定保证金
+    let collected = payment.min(position.locked_usdc_e6.max(0));
+    let uncollected = payment - collected;
+    
+    position.locked_usdc_e6 = safe_sub_i64(position.locked_usdc_e6, collected)?;
+    position.cumulative_funding_e6 = safe_sub_i64(position.cumulative_funding_e6, collected)?;
+    vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, collected)?;
+    
+    if uncollected > 0 {
+        vault.bad_debt_e6 = safe_add_i64(vault.bad_debt_e6, uncollected)?;
     }
     
-    // 验证PDA
-    let (expected_pda, _) = Pubkey::find_program_address(
+    msg!("FUNDING_SETTLED|wallet:{}|account:{}|market:{}|index:{}|payment:{}|uncollected:{}|locked:{}",
+        position.wallet, position.account_id, position.market, funding.cumulative_funding_index,
+        collected, uncollected, position.locked_usdc_e6);
+    
+    Ok(-collected)
+}
+
+/// Relayer联合签名证明该成交为Maker，否则按Taker收费
+fn is_maker_fill(maker_attestation: Option<&AccountInfo>, vault: &TradingVault) -> bool {
+    maker_attestation.is_some_and(|account| account.is_signer && *account.key == vault.relayer)
+}
+
+/// 更新Vault交易统计（成交次数、交易量、手续费）
+fn record_trade(vault: &mut TradingVault, notional_e6: i64, fee_e6: i64) -> ProgramResult {
+    vault.total_trades += 1;
+    vault.cumulative_volume_e6 = safe_add_i64(vault.cumulative_volume_e6, notional_e6)?;
+    vault.cumulative_fees_e6 = safe_add_i64(vault.cumulative_fees_e6, fee_e6)?;
+    vault.fee_treasury_e6 = safe_add_i64(vault.fee_treasury_e6, fee_e6)?;
+    Ok(())
+}
+
+/// 从Vault PDA持有的Token账户转出USDC（Vault PDA签名）
+fn transfer_from_vault<'a>(
+    token_program: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    trading_vault_account: &AccountInfo<'a>,
+    vault_bump: u8,
+    amount_e6: i64,
+) -> ProgramResult {
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            trading_vault_account.key,
+            &[],
+            amount_e6 as u64,
+        )?,
         &[
-            b"position",
-            wallet.as_ref(),
-            account_id.as_bytes(),
-            market.as_bytes(),
+            source.clone(),
+            destination.clone(),
+            trading_vault_account.clone(),
+            token_program.clone(),
         ],
-        program_id,
-    );
+        &[&[VAULT_SEED, &[vault_bump]]],
+    )
+}
+
+/// 穿仓亏损分摊（保证金已全部扣除之后）
+///
+/// 1. Insurance Fund补足：从Insurance Fund账户转回Vault托管账户
+/// 2. 仍不足的部分记入bad_debt_e6（由所有存款人社会化分摊），返回记入的坏账
+#[allow(clippy::too_many_arguments)]
+fn cover_shortfall<'a>(
+    vault: &mut TradingVault,
+    token_program: &AccountInfo<'a>,
+    insurance_fund_account: &AccountInfo<'a>,
+    vault_usdc_account: &AccountInfo<'a>,
+    trading_vault_account: &AccountInfo<'a>,
+    vault_bump: u8,
+    wallet: &Pubkey,
+    account_id: &str,
+    market: &str,
+    shortfall_e6: i64,
+) -> Result<i64, ProgramError> {
+    let (insurance_covered, bad_debt) = split_shortfall(shortfall_e6, vault.insurance_fund_e6);
     
-    if user_position_account.key != &expected_pda {
-        return Err(TradingError::InvalidPositionAccount.into());
+    if insurance_covered > 0 {
+        transfer_from_vault(
+            token_program,
+            insurance_fund_account,
+            vault_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            insurance_covered,
+        )?;
+        
+        vault.insurance_fund_e6 = safe_sub_i64(vault.insurance_fund_e6, insurance_covered)?;
+        
+        msg!("LOSS_INSURANCE_COVERED|wallet:{}|account:{}|market:{}|amount:{}|insurance_remaining:{}",
+            wallet, account_id, market, insurance_covered, vault.insurance_fund_e6);
     }
     
-    // 读取并更新持仓
-    let mut position = UserPosition::try_from_slice(&user_position_account.data.borrow())
-        .map_err(|_| TradingError::DeserializationError)?;
-    
-    position.update_pnl(mark_price_e6);
-    
-    let serialized = position.try_to_vec()
-        .map_err(|_| TradingError::SerializationError)?;
+    if bad_debt > 0 {
+        vault.bad_debt_e6 = safe_add_i64(vault.bad_debt_e6, bad_debt)?;
+        
+        msg!("LOSS_BAD_DEBT_SOCIALIZED|wallet:{}|account:{}|market:{}|amount:{}|total_bad_debt:{}",
+            wallet, account_id, market, bad_debt, vault.bad_debt_e6);
+    }
     
-    user_position_account.data.borrow_mut()[..serialized.len()]
-        .copy_from_slice(&serialized);
+    Ok(bad_debt)
+}
+
+/// 记录穿仓坏账到市场Funding State（AutoDeleverage读取破产方向、破产价格和未覆盖金额）
+fn record_bankruptcy(
+    funding_state_account: &AccountInfo,
+    funding: &mut FundingState,
+    bankrupt_side: Side,
+    bankruptcy_price_e6: i64,
+    bad_debt_e6: i64,
+) -> ProgramResult {
+    funding.record_bankruptcy(bankrupt_side, bankruptcy_price_e6, bad_debt_e6);
+    store_account(funding_state_account, funding)?;
     
-    msg!("Position updated: mark_price={}, unrealized_pnl={}, margin_ratio={}bp",
-        mark_price_e6, position.unrealized_pnl_e6, position.margin_ratio_bp);
+    msg!("ADL_PENDING|market:{}|bankrupt_side:{:?}|bankruptcy_price:{}|amount:{}|long_outstanding:{}|short_outstanding:{}",
+        funding.market, bankrupt_side, bankruptcy_price_e6, bad_debt_e6,
+        funding.long_adl_outstanding_e6, funding.short_adl_outstanding_e6);
     
     Ok(())
 }
 
-/// 提现Insurance Fund
-fn process_withdraw_insurance_fund(
-    _program_id: &Pubkey,
-    _accounts: &[AccountInfo],
-    _amount_e6: i64,
+/// 关闭UserPosition PDA：清零数据，lamports转给recipient，归还System Program
+///
+/// 同一交易结束后账户被回收，之后的LockMargin可以重新创建同一PDA。
+fn close_position_account(
+    position_account: &AccountInfo,
+    recipient: &AccountInfo,
 ) -> ProgramResult {
-    // TODO: 实现Insurance Fund提现逻辑
-    msg!("WithdrawInsuranceFund - To be implemented");
+    let refund = position_account.lamports();
+    
+    **recipient.lamports.borrow_mut() = recipient
+        .lamports()
+        .checked_add(refund)
+        .ok_or(TradingError::ArithmeticOverflow)?;
+    **position_account.lamports.borrow_mut() = 0;
+    
+    position_account.data.borrow_mut().fill(0);
+    position_account.realloc(0, false)?;
+    position_account.assign(&system_program::id());
+    
     Ok(())
 }
 
+/// 创建PDA账户（payer支付租金）
+///
+/// 已被他人预先转入lamports的地址无法create_account，改为补足租金后allocate + assign
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let required_lamports = Rent::get()?.minimum_balance(space);
+    
+    if account.lamports() == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                account.key,
+                required_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[payer.clone(), account.clone(), system_program.clone()],
+            &[seeds],
+        );
+    }
+    
+    let top_up = required_lamports.saturating_sub(account.lamports());
+    
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, top_up),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    
+    invoke_signed(
+        &system_instruction::allocate(account.key, space as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// 持仓是否按全仓结算：margin_mode为Cross且登记在账户抵押品中
+///
+/// 引入CrossMarginAccount之前开立的全仓持仓没有登记，IM仍锁定在持仓自身并计入total_locked_usdc_e6，
+/// 按逐仓结算
+fn is_registered_cross(
+    position_key: &Pubkey,
+    position: &UserPosition,
+    collateral: Option<&CrossMarginAccount>,
+) -> bool {
+    position.margin_mode == MarginMode::Cross
+        && collateral.is_some_and(|cross| cross.positions.contains(position_key))
+}
+
+/// 读取Cross Margin Account，首次使用时创建（用户支付租金）
+fn load_or_create_cross_margin<'a>(
+    program_id: &Pubkey,
+    cross_margin_account: &AccountInfo<'a>,
+    user: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    account_id: &str,
+) -> Result<CrossMarginAccount, ProgramError> {
+    // 按owner判断是否已创建：他人预先转入lamports不影响创建
+    if cross_margin_account.owner == program_id {
+        return load_cross_margin_account(program_id, cross_margin_account, user.key, account_id);
+    }
+    
+    let (expected_pda, bump) = find_cross_margin_pda(program_id, user.key, account_id);
+    
+    if cross_margin_account.key != &expected_pda {
+        msg!("Error: Cross margin account PDA mismatch");
+        return Err(TradingError::InvalidCrossMarginAccount.into());
+    }
+    
+    msg!("Creating cross margin account...");
+    
+    create_pda_account(
+        program_id,
+        user,
+        cross_margin_account,
+        system_program,
+        CrossMarginAccount::MAX_SIZE,
+        &[
+            CROSS_MARGIN_SEED,
+            user.key.as_ref(),
+            account_id.as_bytes(),
+            &[bump],
+        ],
+    )?;
+    
+    Ok(CrossMarginAccount::new(*user.key, account_id.to_string(), bump))
+}
+
+/// 计算全仓账户权益和维持保证金：返回 (抵押品 + 全部未实现盈亏, 全部MM)
+///
+/// 被检查的持仓使用传入的最新盈亏，其他持仓按各自市场的预言机价格重新计算
+fn calculate_cross_margin_health(
+    program_id: &Pubkey,
+    cross: &CrossMarginAccount,
+    position_key: &Pubkey,
+    position: &UserPosition,
+    other_position_accounts: &[AccountInfo],
+    now: i64,
+) -> Result<(i64, i64), ProgramError> {
+    if !cross.positions.contains(position_key) {
+        return Err(TradingError::CrossPositionsMismatch.into());
+    }
+    
+    let mut equity = safe_add_i64(cross.collateral_e6, position.unrealized_pnl_e6)?;
+    let mut maintenance_margin = position.mm_e6;
+    
+    for (unrealized_pnl, mm) in mark_cross_positions(program_id, cross, Some(position_key), other_position_accounts, now)? {
+        equity = safe_add_i64(equity, unrealized_pnl)?;
+        maintenance_margin = safe_add_i64(maintenance_margin, mm)?;
+    }
+    
+    Ok((equity, maintenance_margin))
+}
+
+/// 按各自市场的预言机价格计算全仓持仓盈亏：返回每个持仓的 (未实现盈亏, MM)
+///
+/// 持仓按 [持仓PDA, Market Config PDA, Price Feed] 三个一组传入（不使用UpdatePosition记录的可能过期的盈亏），
+/// 必须与CrossMarginAccount.positions中除excluded之外的持仓完全一致
+fn mark_cross_positions(
+    program_id: &Pubkey,
+    cross: &CrossMarginAccount,
+    excluded: Option<&Pubkey>,
+    position_accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Vec<(i64, i64)>, ProgramError> {
+    let expected = cross.positions.len() - excluded.iter().count();
+    
+    if position_accounts.len() != expected * 3 {
+        msg!("Error: Expected {} cross positions, got {} accounts", expected, position_accounts.len());
+        return Err(TradingError::CrossPositionsMismatch.into());
+    }
+    
+    let mut seen: Vec<&Pubkey> = excluded.into_iter().collect();
+    let mut marked = Vec::with_capacity(expected);
+    
+    for accounts in position_accounts.chunks_exact(3) {
+        let (account, market_config_account, price_feed_account) = (&accounts[0], &accounts[1], &accounts[2]);
+        
+        if seen.contains(&account.key) || !cross.positions.contains(account.key) {
+            return Err(TradingError::CrossPositionsMismatch.into());
+        }
+        seen.push(account.key);
+        
+        let position = load_position_account(program_id, account)?;
+        
+        if position.wallet != cross.wallet || position.account_id != cross.account_id {
+            return Err(TradingError::CrossPositionsMismatch.into());
+        }
+        
+        let market_config = load_market_config(program_id, market_config_account, &position.market)?;
+        let oracle_price = load_oracle_price(price_feed_account, &market_config, now)?;
+        
+        marked.push((position.calculate_unrealized_pnl(oracle_price.price_e6), position.mm_e6));
+    }
+    
+    Ok(marked)
+}
+
+/// 全仓持仓按预言机价格的未实现亏损合计（浮盈不计，不大于0）
+///
+/// Withdraw和LockMargin使用账户抵押品时，可用抵押品需扣除该亏损
+fn cross_unrealized_loss(
+    program_id: &Pubkey,
+    cross: &CrossMarginAccount,
+    excluded: Option<&Pubkey>,
+    position_accounts: &[AccountInfo],
+    now: i64,
+) -> Result<i64, ProgramError> {
+    mark_cross_positions(program_id, cross, excluded, position_accounts, now)?
+        .into_iter()
+        .map(|(unrealized_pnl, _)| unrealized_pnl.min(0))
+        .try_fold(0i64, safe_add_i64)
+}
+
+/// 全仓账户状态事件
+fn log_cross_margin(cross: &CrossMarginAccount) {
+    msg!("CROSS_MARGIN_UPDATED|wallet:{}|account:{}|collateral:{}|locked_im:{}|free:{}|positions:{}",
+        cross.wallet, cross.account_id, cross.collateral_e6, cross.locked_im_e6,
+        cross.free_collateral_e6(), cross.positions.len());
+}
+
+/// 更新持仓（链下定期调用）
+fn process_update_position(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    account_id: String,
+    market: String,
+    wallet: Pubkey,
+    mark_price_e6: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let user_position_account = next_account_info(account_iter)?;
+    let relayer = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    
+    validate_price(mark_price_e6)?;
+    
+    // 验证relayer签名
+    validate_signer(relayer)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    
+    // 验证relayer = vault.relayer
+    if vault.relayer != *relayer.key {
+        msg!("Error: Signer is not the authorized relayer");
+        return Err(TradingError::UnauthorizedRelayer.into());
+    }
+    
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, false)?;
+    
+    // 读取并更新持仓（验证PDA）
+    let mut position = load_user_position(
+        program_id,
+        user_position_account,
+        &wallet,
+        &account_id,
+        &market,
+    )?;
+    
+    // 结算资金费（全仓持仓同步计入账户抵押品）
+    let mut funding = load_funding_state(program_id, funding_state_account, &market)?;
+    let funding_received = settle_funding(&mut position, &funding, &mut vault)?;
+    
+    // 登记之前开立的持仓（供ADL验证排名完整性）
+    if !position.adl_indexed {
+        funding.index_position(&mut position);
+        store_account(funding_state_account, &funding)?;
+    }
+    
+    if position.margin_mode == MarginMode::Cross {
+        let cross_margin_account = next_account_info(account_iter)?;
+        let cross = load_optional_cross_margin_account(program_id, cross_margin_account, &wallet, &account_id)?;
+        
+        // 未登记的旧全仓持仓资金费只计入持仓自身
+        if let Some(mut cross) = cross
+            .filter(|cross| is_registered_cross(user_position_account.key, &position, Some(cross)))
+            .filter(|_| funding_received != 0)
+        {
+            cross.adjust_position_margin(funding_received);
+            store_account(cross_margin_account, &cross)?;
+        }
+    }
+    
+    position.update_pnl(mark_price_e6);
+    
+    store_account(user_position_account, &position)?;
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    msg!("Position updated: mark_price={}, unrealized_pnl={}, margin_ratio={}bp",
+        mark_price_e6, position.unrealized_pnl_e6, position.margin_ratio_bp);
+    
+    Ok(())
+}
+
+/// 提现Insurance Fund（仅管理员）
+fn process_withdraw_insurance_fund(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount_e6: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let insurance_fund_account = next_account_info(account_iter)?;
+    let admin_usdc_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+    
+    msg!("Withdraw Insurance Fund");
+    msg!("  Amount: {}", amount_e6);
+    
+    if amount_e6 <= 0 {
+        return Err(TradingError::InvalidTokenTransfer.into());
+    }
+    
+    validate_token_program(token_program)?;
+    
+    // 验证admin = vault.authority
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_admin(&vault, admin)?;
+    
+    // 验证Insurance Fund账户为Vault中登记的账户
+    validate_insurance_fund_account(insurance_fund_account, &vault)?;
+    validate_token_account(admin_usdc_account, admin.key, &vault.usdc_mint)?;
+    
+    // 验证余额
+    if amount_e6 > vault.insurance_fund_e6 {
+        msg!("Error: Insurance fund insufficient, balance = {}", vault.insurance_fund_e6);
+        return Err(TradingError::InsuranceFundInsufficient.into());
+    }
+    
+    // SPL Token Transfer: Insurance Fund → Admin
+    transfer_from_vault(
+        token_program,
+        insurance_fund_account,
+        admin_usdc_account,
+        trading_vault_account,
+        vault_bump,
+        amount_e6,
+    )?;
+    
+    // 更新Trading Vault
+    vault.insurance_fund_e6 = safe_sub_i64(vault.insurance_fund_e6, amount_e6)?;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("INSURANCE_WITHDRAWN|admin:{}|to:{}|amount:{}|remaining:{}",
+        admin.key, admin_usdc_account.key, amount_e6, vault.insurance_fund_e6);
+    
+    msg!("✅ Insurance fund withdrawn!");
+    msg!("  Remaining: {} USDC", vault.insurance_fund_e6 as f64 / 1_000_000.0);
+    
+    Ok(())
+}
+
+/// 轮换Relayer（仅管理员）
+fn process_set_relayer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_relayer: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    // 验证admin = vault.authority
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let old_relayer = vault.relayer;
+    vault.relayer = new_relayer;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("RELAYER_UPDATED|admin:{}|old:{}|new:{}", admin.key, old_relayer, new_relayer);
+    
+    msg!("✅ Relayer updated!");
+    
+    Ok(())
+}
+
+/// 创建市场配置（仅管理员）
+#[allow(clippy::too_many_arguments)]
+fn process_create_market(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    max_leverage: u32,
+    maintenance_margin_ratio_bp: u32,
+    liquidation_fee_bp: u32,
+    tick_size_e6: i64,
+    lot_size_e6: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Create Market");
+    msg!("  Market: {}", market);
+    msg!("  Max Leverage: {}x", max_leverage);
+    msg!("  MM Ratio: {}bp", maintenance_margin_ratio_bp);
+    msg!("  Liquidation Fee: {}bp", liquidation_fee_bp);
+    
+    // 验证输入
+    // 预警/对冲/清算阈值使用默认值，可通过UpdateMarketRiskParams调整
+    let risk_params = RiskParams {
+        maintenance_margin_ratio_bp,
+        liquidation_fee_bp,
+        ..RiskParams::DEFAULT
+    };
+    
+    validate_market(&market)?;
+    validate_market_params(max_leverage, &risk_params, tick_size_e6, lot_size_e6)?;
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    validate_system_program(system_program)?;
+    
+    // 派生Market Config PDA
+    let (expected_market_pda, market_bump) = find_market_pda(program_id, &market);
+    
+    if market_config_account.key != &expected_market_pda {
+        msg!("Error: Market config PDA mismatch");
+        return Err(TradingError::InvalidMarketConfigAccount.into());
+    }
+    
+    if market_config_account.lamports() > 0 {
+        return Err(TradingError::AccountAlreadyExists.into());
+    }
+    
+    let rent = Rent::get()?;
+    let space = MarketConfig::MAX_SIZE;
+    
+    invoke_signed(
+        &system_instruction::create_account(
+            admin.key,
+            market_config_account.key,
+            rent.minimum_balance(space),
+            space as u64,
+            program_id,
+        ),
+        &[
+            admin.clone(),
+            market_config_account.clone(),
+            system_program.clone(),
+        ],
+        &[&[MARKET_SEED, market.as_bytes(), &[market_bump]]],
+    )?;
+    
+    let config = MarketConfig::new(
+        market.clone(),
+        max_leverage,
+        risk_params,
+        tick_size_e6,
+        lot_size_e6,
+        market_bump,
+    );
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    msg!("MARKET_CREATED|market:{}|max_leverage:{}|mm_ratio_bp:{}|liquidation_fee_bp:{}|tick_size:{}|lot_size:{}",
+        market, max_leverage, maintenance_margin_ratio_bp, liquidation_fee_bp, tick_size_e6, lot_size_e6);
+    
+    msg!("✅ Market created!");
+    msg!("  PDA: {}", market_config_account.key);
+    
+    Ok(())
+}
+
+/// 设置市场状态（仅管理员）
+fn process_set_market_status(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    status: MarketStatus,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let mut config = load_market_config(program_id, market_config_account, &market)?;
+    
+    // Delisted为终态
+    if config.status == MarketStatus::Delisted {
+        msg!("Error: Market {} is delisted", market);
+        return Err(TradingError::MarketDelisted.into());
+    }
+    
+    let old_status = config.status;
+    config.status = status;
+    config.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    msg!("MARKET_STATUS_UPDATED|market:{}|old:{:?}|new:{:?}", market, old_status, status);
+    
+    msg!("✅ Market status updated!");
+    
+    Ok(())
+}
+
+/// 更新市场风控参数（仅管理员）
+/// 
+/// 只影响之后的新开仓；已有持仓保留开仓时的参数，需通过RemarginPosition显式更新
+fn process_update_market_risk_params(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    max_leverage: u32,
+    risk_params: RiskParams,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    
+    msg!("Update Market Risk Params");
+    msg!("  Market: {}", market);
+    msg!("  Max Leverage: {}x", max_leverage);
+    msg!("  Risk Params: {:?}", risk_params);
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let mut config = load_market_config(program_id, market_config_account, &market)?;
+    
+    validate_market_params(max_leverage, &risk_params, config.tick_size_e6, config.lot_size_e6)?;
+    validate_partial_liquidation_target(config.partial_liquidation_target_bp, &risk_params)?;
+    
+    let old_max_leverage = config.max_leverage;
+    let old_risk_params = config.risk_params;
+    
+    config.max_leverage = max_leverage;
+    config.risk_params = risk_params;
+    config.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    msg!("MARKET_RISK_PARAMS_UPDATED|market:{}|max_leverage:{}->{}|mm_ratio_bp:{}->{}|liquidation_fee_bp:{}->{}|warning_bp:{}->{}|smart_hedge_bp:{}->{}|liquidation_bp:{}->{}",
+        market,
+        old_max_leverage, max_leverage,
+        old_risk_params.maintenance_margin_ratio_bp, risk_params.maintenance_margin_ratio_bp,
+        old_risk_params.liquidation_fee_bp, risk_params.liquidation_fee_bp,
+        old_risk_params.warning_threshold_bp, risk_params.warning_threshold_bp,
+        old_risk_params.smart_hedge_threshold_bp, risk_params.smart_hedge_threshold_bp,
+        old_risk_params.liquidation_threshold_bp, risk_params.liquidation_threshold_bp);
+    
+    msg!("✅ Market risk params updated!");
+    
+    Ok(())
+}
+
+/// 按市场当前风控参数重新计算持仓维持保证金（仅管理员）
+fn process_remargin_position(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    wallet: Pubkey,
+    account_id: String,
+    market: String,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let user_position_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    
+    msg!("Remargin Position");
+    msg!("  Wallet: {}", wallet);
+    msg!("  Account: {}", account_id);
+    msg!("  Market: {}", market);
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let config = load_market_config(program_id, market_config_account, &market)?;
+    
+    let mut position = load_user_position(
+        program_id,
+        user_position_account,
+        &wallet,
+        &account_id,
+        &market,
+    )?;
+    
+    let old_mm = position.mm_e6;
+    
+    // 使用市场当前参数重新计算MM（配置了风险档位时按持仓名义价值取档位费率，超出最高档按最高档）
+    let notional = calculate_notional(position.size_e6.abs(), position.entry_price_e6)?;
+    let mut risk_params = config.risk_params;
+    
+    if let Some(bracket) = config.risk_bracket(notional).ok().flatten().or(config.risk_brackets.last().copied()) {
+        risk_params.maintenance_margin_ratio_bp = bracket.maintenance_margin_ratio_bp;
+    }
+    
+    position.risk_params = risk_params;
+    position.mm_e6 = calculate_maintenance_margin(
+        position.size_e6.abs(),
+        position.entry_price_e6,
+        risk_params.maintenance_margin_ratio_bp,
+    )?;
+    
+    // 按最新标记价格刷新保证金率和清算状态
+    let mark_price = if position.mark_price_e6 > 0 {
+        position.mark_price_e6
+    } else {
+        position.entry_price_e6
+    };
+    position.update_pnl(mark_price);
+    
+    store_account(user_position_account, &position)?;
+    
+    // Emit事件
+    msg!("POSITION_REMARGINED|wallet:{}|account:{}|market:{}|old_mm:{}|new_mm:{}|margin_ratio:{}|status:{:?}",
+        wallet, account_id, market, old_mm, position.mm_e6, position.margin_ratio_bp, position.liquidation_status);
+    
+    msg!("✅ Position remargined!");
+    
+    Ok(())
+}
+
+/// 设置市场风险档位（仅管理员）
+/// 
+/// 只影响之后的开仓/加仓；旧版本MarketConfig账户空间不足时扩容，由admin补足租金
+fn process_set_risk_brackets(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    brackets: Vec<RiskBracket>,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Set Risk Brackets");
+    msg!("  Market: {}", market);
+    msg!("  Brackets: {}", brackets.len());
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    validate_system_program(system_program)?;
+    
+    let mut config = load_market_config(program_id, market_config_account, &market)?;
+    
+    validate_risk_brackets(&brackets, config.max_leverage)?;
+    
+    // 扩容至MAX_SIZE
+    if market_config_account.data_len() < MarketConfig::MAX_SIZE {
+        let rent = Rent::get()?;
+        let required_lamports = rent.minimum_balance(MarketConfig::MAX_SIZE);
+        let top_up = required_lamports.saturating_sub(market_config_account.lamports());
+        
+        if top_up > 0 {
+            invoke(
+                &system_instruction::transfer(admin.key, market_config_account.key, top_up),
+                &[
+                    admin.clone(),
+                    market_config_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        
+        market_config_account.realloc(MarketConfig::MAX_SIZE, true)?;
+    }
+    
+    config.risk_brackets = brackets;
+    config.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    for (i, bracket) in config.risk_brackets.iter().enumerate() {
+        msg!("RISK_BRACKET_SET|market:{}|tier:{}|max_notional:{}|max_leverage:{}|mm_ratio_bp:{}",
+            market, i, bracket.max_notional_e6, bracket.max_leverage, bracket.maintenance_margin_ratio_bp);
+    }
+    msg!("RISK_BRACKETS_UPDATED|market:{}|count:{}", market, config.risk_brackets.len());
+    
+    msg!("✅ Risk brackets updated!");
+    
+    Ok(())
+}
+
+/// 设置市场预言机（仅管理员）
+fn process_set_market_oracle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    max_price_deviation_bp: u32,
+    max_oracle_staleness_secs: u32,
+    max_oracle_confidence_bp: u32,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    
+    msg!("Set Market Oracle");
+    msg!("  Market: {}", market);
+    msg!("  Oracle: {}", price_feed_account.key);
+    msg!("  Max Deviation: {}bp", max_price_deviation_bp);
+    msg!("  Max Staleness: {}s", max_oracle_staleness_secs);
+    msg!("  Max Confidence: {}bp", max_oracle_confidence_bp);
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    validate_oracle_params(max_price_deviation_bp, max_oracle_staleness_secs, max_oracle_confidence_bp)?;
+    
+    // 必须是有效的Pyth价格账户
+    let oracle_price = parse_pyth_price(&price_feed_account.data.borrow())?;
+    
+    let mut config = load_market_config(program_id, market_config_account, &market)?;
+    
+    config.oracle = *price_feed_account.key;
+    config.max_price_deviation_bp = max_price_deviation_bp;
+    config.max_oracle_staleness_secs = max_oracle_staleness_secs;
+    config.max_oracle_confidence_bp = max_oracle_confidence_bp;
+    config.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    msg!("MARKET_ORACLE_UPDATED|market:{}|oracle:{}|max_deviation_bp:{}|max_staleness_secs:{}|max_confidence_bp:{}|price:{}",
+        market, price_feed_account.key, max_price_deviation_bp, max_oracle_staleness_secs,
+        max_oracle_confidence_bp, oracle_price.price_e6);
+    
+    msg!("✅ Market oracle updated!");
+    
+    Ok(())
+}
+
+/// 设置交易手续费率（仅管理员）
+fn process_set_trading_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    maker_fee_bp: u32,
+    taker_fee_bp: u32,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    msg!("Set Trading Fees");
+    msg!("  Maker: {}bp", maker_fee_bp);
+    msg!("  Taker: {}bp", taker_fee_bp);
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    validate_fee_rates(maker_fee_bp, taker_fee_bp)?;
+    
+    let old_maker_fee_bp = vault.maker_fee_bp;
+    let old_taker_fee_bp = vault.taker_fee_bp;
+    
+    vault.maker_fee_bp = maker_fee_bp;
+    vault.taker_fee_bp = taker_fee_bp;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("TRADING_FEES_UPDATED|maker_bp:{}->{}|taker_bp:{}->{}",
+        old_maker_fee_bp, maker_fee_bp, old_taker_fee_bp, taker_fee_bp);
+    
+    msg!("✅ Trading fees updated!");
+    
+    Ok(())
+}
+
+/// 初始化市场Funding State（仅管理员）
+fn process_initialize_funding(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    keeper: Pubkey,
+    funding_interval_secs: u32,
+    max_funding_rate_e9: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let funding_state_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Initialize Funding");
+    msg!("  Market: {}", market);
+    msg!("  Keeper: {}", keeper);
+    msg!("  Interval: {}s", funding_interval_secs);
+    msg!("  Max Rate: {}e-9", max_funding_rate_e9);
+    
+    validate_funding_params(funding_interval_secs, max_funding_rate_e9)?;
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    validate_system_program(system_program)?;
+    
+    // 市场必须已创建
+    load_market_config(program_id, market_config_account, &market)?;
+    
+    // 派生Funding State PDA
+    let (expected_funding_pda, funding_bump) = find_funding_pda(program_id, &market);
+    
+    if funding_state_account.key != &expected_funding_pda {
+        msg!("Error: Funding state PDA mismatch");
+        return Err(TradingError::InvalidFundingAccount.into());
+    }
+    
+    if funding_state_account.lamports() > 0 {
+        return Err(TradingError::AccountAlreadyExists.into());
+    }
+    
+    let rent = Rent::get()?;
+    let space = FundingState::MAX_SIZE;
+    
+    invoke_signed(
+        &system_instruction::create_account(
+            admin.key,
+            funding_state_account.key,
+            rent.minimum_balance(space),
+            space as u64,
+            program_id,
+        ),
+        &[
+            admin.clone(),
+            funding_state_account.clone(),
+            system_program.clone(),
+        ],
+        &[&[FUNDING_SEED, market.as_bytes(), &[funding_bump]]],
+    )?;
+    
+    let state = FundingState::new(
+        market.clone(),
+        keeper,
+        funding_interval_secs,
+        max_funding_rate_e9,
+        funding_bump,
+    );
+    
+    store_account(funding_state_account, &state)?;
+    
+    // Emit事件
+    msg!("FUNDING_INITIALIZED|market:{}|keeper:{}|interval:{}|max_rate_e9:{}",
+        market, keeper, funding_interval_secs, max_funding_rate_e9);
+    
+    msg!("✅ Funding initialized!");
+    msg!("  PDA: {}", funding_state_account.key);
+    
+    Ok(())
+}
+
+/// 更新累计资金费index（仅Keeper）
+/// 
+/// index += 预言机价格 × funding_rate × 距上次更新时间 / 资金费周期
+fn process_update_funding(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    funding_rate_e9: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let funding_state_account = next_account_info(account_iter)?;
+    let keeper = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    
+    validate_signer(keeper)?;
+    
+    let mut state = load_funding_state(program_id, funding_state_account, &market)?;
+    
+    if state.keeper != *keeper.key {
+        msg!("Error: Signer is not the funding keeper");
+        return Err(TradingError::UnauthorizedKeeper.into());
+    }
+    
+    if funding_rate_e9.abs() > state.max_funding_rate_e9 {
+        msg!("Error: Funding rate {} exceeds max {}", funding_rate_e9, state.max_funding_rate_e9);
+        return Err(TradingError::InvalidFundingRate.into());
+    }
+    
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    
+    // 使用预言机价格作为标记价格
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, false)?;
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    let oracle_price = load_oracle_price(price_feed_account, &market_config, now)?;
+    
+    let elapsed = now.saturating_sub(state.last_updated_at);
+    let index_delta = calculate_funding_index_delta(
+        oracle_price.price_e6,
+        funding_rate_e9,
+        elapsed,
+        state.funding_interval_secs,
+    )?;
+    
+    state.cumulative_funding_index = state.cumulative_funding_index
+        .checked_add(index_delta)
+        .ok_or(TradingError::ArithmeticOverflow)?;
+    state.last_funding_rate_e9 = funding_rate_e9;
+    state.last_mark_price_e6 = oracle_price.price_e6;
+    state.last_updated_at = now;
+    
+    store_account(funding_state_account, &state)?;
+    
+    // Emit事件
+    msg!("FUNDING_UPDATED|market:{}|rate_e9:{}|mark_price:{}|elapsed:{}|index_delta:{}|index:{}",
+        market, funding_rate_e9, oracle_price.price_e6, elapsed, index_delta, state.cumulative_funding_index);
+    
+    Ok(())
+}
+
+/// 轮换Funding Keeper（仅管理员）
+fn process_set_funding_keeper(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    keeper: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let funding_state_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let mut state = load_funding_state(program_id, funding_state_account, &market)?;
+    
+    let old_keeper = state.keeper;
+    state.keeper = keeper;
+    
+    store_account(funding_state_account, &state)?;
+    
+    // Emit事件
+    msg!("FUNDING_KEEPER_UPDATED|market:{}|old:{}|new:{}", market, old_keeper, keeper);
+    
+    msg!("✅ Funding keeper updated!");
+    
+    Ok(())
+}
+
+/// 设置部分强平目标保证金率（仅管理员）
+fn process_set_partial_liquidation(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    target_margin_ratio_bp: u32,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    
+    msg!("Set Partial Liquidation");
+    msg!("  Market: {}", market);
+    msg!("  Target Margin Ratio: {}bp", target_margin_ratio_bp);
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let mut config = load_market_config(program_id, market_config_account, &market)?;
+    
+    validate_partial_liquidation_target(target_margin_ratio_bp, &config.risk_params)?;
+    
+    let old_target = config.partial_liquidation_target_bp;
+    config.partial_liquidation_target_bp = target_margin_ratio_bp;
+    config.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    msg!("PARTIAL_LIQUIDATION_UPDATED|market:{}|target_bp:{}->{}", market, old_target, target_margin_ratio_bp);
+    
+    msg!("✅ Partial liquidation updated!");
+    
+    Ok(())
+}
+
+/// 自动减仓ADL（仅Relayer）
+/// 
+/// 按破产价格对反方向盈利持仓逐个减仓，直到坏账被覆盖或目标持仓用完
+fn process_auto_deleverage(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    target_count: u8,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let relayer = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let vault_usdc_account = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
+    
+    msg!("Auto Deleverage");
+    msg!("  Market: {}", market);
+    
+    // 验证relayer签名
+    validate_signer(relayer)?;
+    validate_token_program(token_program)?;
+    
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    
+    // 验证relayer = vault.relayer
+    if vault.relayer != *relayer.key {
+        msg!("Error: Signer is not the authorized relayer");
+        return Err(TradingError::UnauthorizedRelayer.into());
+    }
+    
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, false)?;
+    
+    // 只有该市场穿仓时Insurance Fund不足产生的坏账才允许ADL，方向和破产价格取Liquidate/UnlockMargin的记录
+    let mut funding = load_funding_state(program_id, funding_state_account, &market)?;
+    
+    let Some((bankrupt_side, bankruptcy_price_e6, outstanding_e6)) = funding.pending_adl() else {
+        msg!("Error: No bad debt to deleverage");
+        return Err(TradingError::AdlNotRequired.into());
+    };
+    
+    // 坏账可能已由其他途径弥补，不超过全局坏账
+    let adl_debt_e6 = outstanding_e6.min(vault.bad_debt_e6);
+    
+    if adl_debt_e6 <= 0 {
+        msg!("Error: No bad debt to deleverage");
+        return Err(TradingError::AdlNotRequired.into());
+    }
+    
+    msg!("  Bankrupt Side: {:?}", bankrupt_side);
+    msg!("  Bankruptcy Price: {}", bankruptcy_price_e6);
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    let mark_price_e6 = load_oracle_price(price_feed_account, &market_config, now)?.price_e6;
+    
+    // 破产价格必须比预言机价格对反方向持仓更不利（多头穿仓：破产价 > 标记价）
+    let haircut_per_unit_e6 = match bankrupt_side {
+        Side::Buy => bankruptcy_price_e6 - mark_price_e6,
+        Side::Sell => mark_price_e6 - bankruptcy_price_e6,
+    };
+    
+    if haircut_per_unit_e6 <= 0 {
+        msg!("Error: Bankruptcy price {} not beyond mark price {}", bankruptcy_price_e6, mark_price_e6);
+        return Err(TradingError::InvalidBankruptcyPrice.into());
+    }
+    
+    msg!("  Mark Price: {}", mark_price_e6);
+    msg!("  Bad Debt: {} USDC", adl_debt_e6 as f64 / 1_000_000.0);
+    
+    // 目标持仓（每个4个账户）之后是其余全部反方向持仓（每个1个账户），用于证明没有跳过排名更高的持仓
+    let remaining_accounts = account_iter.as_slice();
+    let target_accounts_len = target_count as usize * 4;
+    
+    if target_count == 0 || remaining_accounts.len() < target_accounts_len {
+        msg!("Error: No ADL target provided");
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    
+    let (target_accounts, other_accounts) = remaining_accounts.split_at(target_accounts_len);
+    let opposing_side = match bankrupt_side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    };
+    
+    // 排名按标记价格计算：目标持仓 盈利率 × 杠杆 降序，其余持仓都不高于最后一个目标
+    let mut seen: Vec<&Pubkey> = Vec::with_capacity(target_count as usize + other_accounts.len());
+    let mut indexed_count: u32 = 0;
+    let mut previous_score = i128::MAX;
+    let position_accounts = target_accounts
+        .iter()
+        .step_by(4)
+        .chain(other_accounts.iter());
+    
+    for (i, account) in position_accounts.enumerate() {
+        let position = load_market_position(program_id, account, &market)?;
+        
+        if position.side != opposing_side || seen.contains(&account.key) {
+            msg!("Error: ADL position {} is not a distinct opposing position", account.key);
+            return Err(TradingError::InvalidAdlTarget.into());
+        }
+        seen.push(account.key);
+        
+        if position.adl_indexed {
+            indexed_count += 1;
+        }
+        
+        let score = calculate_adl_score(
+            position.calculate_unrealized_pnl(mark_price_e6),
+            position.locked_usdc_e6,
+            position.leverage,
+        );
+        
+        // 只减盈利持仓
+        if i < target_count as usize && score <= 0 {
+            msg!("Error: ADL target {} is not profitable", account.key);
+            return Err(TradingError::InvalidAdlTarget.into());
+        }
+        
+        if score > previous_score {
+            msg!("Error: ADL position {} ranked out of order ({} > {})", account.key, score, previous_score);
+            return Err(TradingError::AdlRankingViolation.into());
+        }
+        
+        // 其余持仓之间不要求顺序，只与最后一个目标比较
+        if i < target_count as usize {
+            previous_score = score;
+        }
+    }
+    
+    // 必须传入该市场全部已登记的反方向持仓
+    if indexed_count != funding.indexed_positions(opposing_side) {
+        msg!("Error: Expected {} opposing positions, got {}", funding.indexed_positions(opposing_side), indexed_count);
+        return Err(TradingError::AdlRankingViolation.into());
+    }
+    
+    let mut remaining_debt_e6 = adl_debt_e6;
+    let mut deleveraged_positions: u32 = 0;
+    let mut total_adl_size_e6: i64 = 0;
+    
+    for accounts in target_accounts.chunks_exact(4) {
+        if remaining_debt_e6 <= 0 {
+            break;
+        }
+        
+        let (user_position_account, position_owner, owner_usdc_account, cross_margin_account) =
+            (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+        
+        let mut position = load_market_position(program_id, user_position_account, &market)?;
+        
+        if position_owner.key != &position.wallet {
+            msg!("Error: ADL target {} owner mismatch", user_position_account.key);
+            return Err(TradingError::InvalidAdlTarget.into());
+        }
+        
+        validate_token_account(owner_usdc_account, &position.wallet, &vault.usdc_mint)?;
+        
+        // 全仓持仓在账户抵押品内结算；逐仓已Deposit时返还计入抵押品（同平仓）
+        let mut collateral = load_optional_cross_margin_account(
+            program_id,
+            cross_margin_account,
+            &position.wallet,
+            &position.account_id,
+        )?;
+        let is_cross = is_registered_cross(user_position_account.key, &position, collateral.as_ref());
+        
+        let funding_received = settle_funding(&mut position, &funding, &mut vault)?;
+        
+        if let Some(cross) = collateral.as_mut().filter(|_| is_cross) {
+            cross.adjust_position_margin(funding_received);
+        }
+        
+        position.update_pnl(mark_price_e6);
+        let score = calculate_adl_score(position.unrealized_pnl_e6, position.locked_usdc_e6, position.leverage);
+        
+        let position_size_e6 = position.size_e6.abs();
+        let adl_size_e6 = calculate_adl_size(
+            position_size_e6,
+            haircut_per_unit_e6,
+            remaining_debt_e6,
+            market_config.lot_size_e6,
+        );
+        
+        // 按破产价格平仓
+        let is_long = matches!(position.side, Side::Buy);
+        let realized_pnl = calculate_realized_pnl(
+            is_long,
+            position.entry_price_e6,
+            bankruptcy_price_e6,
+            adl_size_e6,
+        )?;
+        let released_im = (position.locked_usdc_e6 as i128 * adl_size_e6 as i128
+            / position_size_e6 as i128) as i64;
+        let released_mm = (position.mm_e6 as i128 * adl_size_e6 as i128
+            / position_size_e6 as i128) as i64;
+        let return_amount = safe_add_i64(released_im, realized_pnl)?;
+        
+        if return_amount < 0 {
+            msg!("Error: ADL target {} would go bankrupt at bankruptcy price", user_position_account.key);
+            return Err(TradingError::InvalidAdlTarget.into());
+        }
+        
+        // 让渡的盈利抵消坏账
+        let haircut = ((haircut_per_unit_e6 as i128 * adl_size_e6 as i128 / 1_000_000) as i64)
+            .min(remaining_debt_e6);
+        
+        position.size_e6 = safe_sub_i64(position.size_e6, adl_size_e6)?;
+        position.locked_usdc_e6 = safe_sub_i64(position.locked_usdc_e6, released_im)?;
+        position.mm_e6 = safe_sub_i64(position.mm_e6, released_mm)?;
+        position.realized_pnl_e6 = safe_add_i64(position.realized_pnl_e6, realized_pnl)?;
+        
+        let is_fully_closed = position.size_e6 == 0;
+        
+        // 返还：全仓盈亏计入抵押品；逐仓有账户抵押品时计入抵押品，否则转给持仓所有者
+        match collateral.as_mut() {
+            Some(cross) if is_cross => {
+                cross.collateral_e6 = safe_add_i64(cross.collateral_e6, realized_pnl)?;
+                cross.realized_pnl_e6 = safe_add_i64(cross.realized_pnl_e6, realized_pnl)?;
+                cross.locked_im_e6 = safe_sub_i64(cross.locked_im_e6, released_im)?;
+                
+                if is_fully_closed {
+                    cross.remove_position(user_position_account.key);
+                }
+                
+                vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, realized_pnl)?;
+            }
+            Some(account) => {
+                account.collateral_e6 = safe_add_i64(account.collateral_e6, return_amount)?;
+                vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, released_im)?;
+                vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, return_amount)?;
+            }
+            None => {
+                if return_amount > 0 {
+                    transfer_from_vault(
+                        token_program,
+                        vault_usdc_account,
+                        owner_usdc_account,
+                        trading_vault_account,
+                        vault_bump,
+                        return_amount,
+                    )?;
+                }
+                
+                vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, released_im)?;
+            }
+        }
+        
+        let to_collateral = collateral.is_some();
+        
+        if let Some(mut account) = collateral {
+            account.updated_at = now;
+            store_account(cross_margin_account, &account)?;
+            log_cross_margin(&account);
+        }
+        
+        if is_fully_closed {
+            funding.unindex_position(&mut position);
+            close_position_account(user_position_account, position_owner)?;
+            vault.total_positions = vault.total_positions.saturating_sub(1);
+        } else {
+            position.update_pnl(mark_price_e6);
+            funding.index_position(&mut position);
+            store_account(user_position_account, &position)?;
+        }
+        
+        vault.cumulative_pnl_e6 = safe_add_i64(vault.cumulative_pnl_e6, realized_pnl)?;
+        vault.bad_debt_e6 = safe_sub_i64(vault.bad_debt_e6, haircut)?;
+        remaining_debt_e6 = safe_sub_i64(remaining_debt_e6, haircut)?;
+        
+        deleveraged_positions += 1;
+        total_adl_size_e6 = safe_add_i64(total_adl_size_e6, adl_size_e6)?;
+        
+        // Emit事件
+        msg!("ADL_EXECUTED|wallet:{}|account:{}|market:{}|side:{:?}|size:{}|remaining_size:{}|bankruptcy_price:{}|mark_price:{}|pnl:{}|haircut:{}|returned:{}|to_collateral:{}|score:{}",
+            position.wallet, position.account_id, market, position.side, adl_size_e6, position.size_e6,
+            bankruptcy_price_e6, mark_price_e6, realized_pnl, haircut, return_amount, to_collateral, score);
+    }
+    
+    let covered_e6 = adl_debt_e6 - remaining_debt_e6;
+    
+    // 已被其他途径弥补的部分一并从市场记录中清除
+    funding.reduce_adl_outstanding(bankrupt_side, outstanding_e6 - remaining_debt_e6);
+    store_account(funding_state_account, &funding)?;
+    
+    vault.updated_at = now;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    msg!("ADL_COMPLETED|market:{}|bankrupt_side:{:?}|positions:{}|size:{}|bad_debt_covered:{}|market_outstanding:{}|bad_debt_remaining:{}",
+        market, bankrupt_side, deleveraged_positions, total_adl_size_e6, covered_e6,
+        remaining_debt_e6, vault.bad_debt_e6);
+    
+    msg!("✅ Auto deleverage completed!");
+    
+    Ok(())
+}
+
+/// 存入账户抵押品
+fn process_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    account_id: String,
+    amount_e6: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let cross_margin_account = next_account_info(account_iter)?;
+    let user = next_account_info(account_iter)?;
+    let user_usdc_account = next_account_info(account_iter)?;
+    let vault_usdc_account = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Deposit Collateral");
+    msg!("  Account: {}", account_id);
+    msg!("  Amount: {}", amount_e6);
+    
+    validate_account_id(&account_id)?;
+    
+    if amount_e6 <= 0 {
+        return Err(TradingError::InvalidTokenTransfer.into());
+    }
+    
+    validate_signer(user)?;
+    validate_token_program(token_program)?;
+    validate_system_program(system_program)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_trading_mode(vault.trading_mode, TradingMode::Active, false)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    validate_token_account(user_usdc_account, user.key, &vault.usdc_mint)?;
+    
+    let mut collateral = load_or_create_cross_margin(
+        program_id,
+        cross_margin_account,
+        user,
+        system_program,
+        &account_id,
+    )?;
+    
+    // SPL Token Transfer: 用户 → Vault
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            user_usdc_account.key,
+            vault_usdc_account.key,
+            user.key,
+            &[],
+            amount_e6 as u64,
+        )?,
+        &[
+            user_usdc_account.clone(),
+            vault_usdc_account.clone(),
+            user.clone(),
+            token_program.clone(),
+        ],
+    )?;
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    collateral.collateral_e6 = safe_add_i64(collateral.collateral_e6, amount_e6)?;
+    collateral.updated_at = now;
+    store_account(cross_margin_account, &collateral)?;
+    
+    vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, amount_e6)?;
+    vault.updated_at = now;
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("COLLATERAL_DEPOSITED|wallet:{}|account:{}|amount:{}|collateral:{}",
+        user.key, account_id, amount_e6, collateral.collateral_e6);
+    log_cross_margin(&collateral);
+    
+    msg!("✅ Collateral deposited!");
+    
+    Ok(())
+}
+
+/// 提取账户抵押品
+fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    account_id: String,
+    amount_e6: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let cross_margin_account = next_account_info(account_iter)?;
+    let user = next_account_info(account_iter)?;
+    let user_usdc_account = next_account_info(account_iter)?;
+    let vault_usdc_account = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+    
+    msg!("Withdraw Collateral");
+    msg!("  Account: {}", account_id);
+    msg!("  Amount: {}", amount_e6);
+    
+    if amount_e6 <= 0 {
+        return Err(TradingError::InvalidTokenTransfer.into());
+    }
+    
+    validate_signer(user)?;
+    validate_token_program(token_program)?;
+    
+    // 只能提取可用抵押品，ReduceOnly下也允许
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_trading_mode(vault.trading_mode, TradingMode::Active, false)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    validate_token_account(user_usdc_account, user.key, &vault.usdc_mint)?;
+    
+    let mut collateral = load_cross_margin_account(program_id, cross_margin_account, user.key, &account_id)?;
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    // 全仓持仓占用的IM不能提取，且提取后 抵押品 + 全部未实现亏损 >= 全部IM（浮盈不可提取）
+    let unrealized_loss = cross_unrealized_loss(program_id, &collateral, None, account_iter.as_slice(), now)?;
+    let withdrawable = safe_add_i64(collateral.free_collateral_e6(), unrealized_loss)?;
+    
+    if amount_e6 > withdrawable {
+        msg!("Error: Free collateral insufficient, available = {}, unrealized loss = {}",
+            collateral.free_collateral_e6(), unrealized_loss);
+        return Err(TradingError::InsufficientBalance.into());
+    }
+    
+    // SPL Token Transfer: Vault → 用户
+    transfer_from_vault(
+        token_program,
+        vault_usdc_account,
+        user_usdc_account,
+        trading_vault_account,
+        vault_bump,
+        amount_e6,
+    )?;
+    
+    collateral.collateral_e6 = safe_sub_i64(collateral.collateral_e6, amount_e6)?;
+    collateral.updated_at = now;
+    store_account(cross_margin_account, &collateral)?;
+    
+    vault.total_locked_usdc_e6 = safe_sub_i64(vault.total_locked_usdc_e6, amount_e6)?;
+    vault.updated_at = now;
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("COLLATERAL_WITHDRAWN|wallet:{}|account:{}|amount:{}|collateral:{}",
+        user.key, account_id, amount_e6, collateral.collateral_e6);
+    log_cross_margin(&collateral);
+    
+    msg!("✅ Collateral withdrawn!");
+    
+    Ok(())
+}
+
+/// 逐仓持仓追加/减少保证金
+fn process_adjust_margin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    account_id: String,
+    market: String,
+    amount_e6: i64,
+    is_add: bool,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let user_position_account = next_account_info(account_iter)?;
+    let user = next_account_info(account_iter)?;
+    let user_usdc_account = next_account_info(account_iter)?;
+    let vault_usdc_account = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
+    
+    msg!("{} Margin", if is_add { "Add" } else { "Remove" });
+    msg!("  Account: {}", account_id);
+    msg!("  Market: {}", market);
+    msg!("  Amount: {}", amount_e6);
+    
+    if amount_e6 <= 0 {
+        return Err(TradingError::InvalidTokenTransfer.into());
+    }
+    
+    validate_signer(user)?;
+    validate_token_program(token_program)?;
+    
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    validate_token_account(user_usdc_account, user.key, &vault.usdc_mint)?;
+    
+    let mut position = load_user_position(
+        program_id,
+        user_position_account,
+        user.key,
+        &account_id,
+        &market,
+    )?;
+    
+    // 全仓持仓的保证金由账户抵押品统一管理
+    if position.margin_mode != MarginMode::Isolated {
+        msg!("Error: Margin can only be adjusted on isolated positions");
+        return Err(TradingError::MarginModeMismatch.into());
+    }
+    
+    let funding = load_funding_state(program_id, funding_state_account, &market)?;
+    settle_funding(&mut position, &funding, &mut vault)?;
+    
+    // 减少保证金增加风险，ReduceOnly下只允许追加
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, !is_add)?;
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    let mark_price_e6 = load_oracle_price(price_feed_account, &market_config, now)?.price_e6;
+    
+    position.update_pnl(mark_price_e6);
+    
+    if is_add {
+        // SPL Token Transfer: 用户 → Vault
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                user_usdc_account.key,
+                vault_usdc_account.key,
+                user.key,
+                &[],
+                amount_e6 as u64,
+            )?,
+            &[
+                user_usdc_account.clone(),
+                vault_usdc_account.clone(),
+                user.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    } else {
+        // 减少后不得低于按标记价格计算的IM
+        let required_im = calculate_initial_margin(position.size_e6.abs(), mark_price_e6, position.leverage)?;
+        let max_removable = calculate_max_removable_margin(
+            position.locked_usdc_e6,
+            position.unrealized_pnl_e6,
+            required_im,
+        );
+        
+        if amount_e6 > max_removable {
+            msg!("Error: Removing {} would breach IM {}, max removable = {}",
+                amount_e6, required_im, max_removable);
+            return Err(TradingError::MarginRatioTooLow.into());
+        }
+        
+        // SPL Token Transfer: Vault → 用户
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            user_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            amount_e6,
+        )?;
+    }
+    
+    // 更新持仓：重新计算保证金率和强平价格
+    let delta_e6 = if is_add { amount_e6 } else { -amount_e6 };
+    position.locked_usdc_e6 = safe_add_i64(position.locked_usdc_e6, delta_e6)?;
+    position.update_pnl(mark_price_e6);
+    position.update_liquidation_price();
+    position.updated_at = now;
+    
+    store_account(user_position_account, &position)?;
+    
+    vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, delta_e6)?;
+    vault.updated_at = now;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("MARGIN_ADJUSTED|wallet:{}|account:{}|market:{}|amount:{}|locked:{}|mark_price:{}|margin_ratio:{}|liquidation_price:{}",
+        user.key, account_id, market, delta_e6, position.locked_usdc_e6, mark_price_e6,
+        position.margin_ratio_bp, position.liquidation_price_e6);
+    
+    msg!("✅ Position margin adjusted!");
+    
+    Ok(())
+}
+
+/// 调整持仓杠杆：按新杠杆重新计算IM、MM和强平价格
+fn process_adjust_leverage(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    account_id: String,
+    market: String,
+    leverage: u32,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let user_position_account = next_account_info(account_iter)?;
+    let user = next_account_info(account_iter)?;
+    let user_usdc_account = next_account_info(account_iter)?;
+    let vault_usdc_account = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+    let market_config_account = next_account_info(account_iter)?;
+    let price_feed_account = next_account_info(account_iter)?;
+    let funding_state_account = next_account_info(account_iter)?;
+    
+    msg!("Adjust Leverage");
+    msg!("  Account: {}", account_id);
+    msg!("  Market: {}", market);
+    msg!("  Leverage: {}x", leverage);
+    
+    validate_leverage(leverage)?;
+    validate_signer(user)?;
+    validate_token_program(token_program)?;
+    
+    let (mut vault, vault_bump) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_not_paused(&vault)?;
+    validate_vault_token_account(vault_usdc_account, &vault)?;
+    validate_token_account(user_usdc_account, user.key, &vault.usdc_mint)?;
+    
+    let mut position = load_user_position(
+        program_id,
+        user_position_account,
+        user.key,
+        &account_id,
+        &market,
+    )?;
+    
+    // 提高杠杆增加风险，ReduceOnly下只允许降低杠杆
+    let market_config = load_market_config(program_id, market_config_account, &market)?;
+    validate_trading_mode(vault.trading_mode, market_config.trading_mode, leverage > position.leverage)?;
+    validate_market_leverage(leverage, &market_config)?;
+    
+    // 账户抵押品：全仓持仓的IM在其中划转；逐仓持仓（含未登记的旧全仓持仓）账户已Deposit时差额同样经抵押品划转
+    let cross_margin_account = next_account_info(account_iter)?;
+    let cross_position_accounts = account_iter.as_slice();
+    let mut collateral = load_optional_cross_margin_account(program_id, cross_margin_account, user.key, &account_id)?;
+    let is_cross = is_registered_cross(user_position_account.key, &position, collateral.as_ref());
+    
+    let funding = load_funding_state(program_id, funding_state_account, &market)?;
+    let funding_received = settle_funding(&mut position, &funding, &mut vault)?;
+    
+    if let Some(cross) = collateral.as_mut().filter(|_| is_cross) {
+        cross.adjust_position_margin(funding_received);
+    }
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    let mark_price_e6 = load_oracle_price(price_feed_account, &market_config, now)?.price_e6;
+    
+    // 风险档位：新杠杆不得超过持仓名义价值所在档位的上限
+    let size_e6 = position.size_e6.abs();
+    let notional = calculate_notional(size_e6, position.entry_price_e6)?;
+    let mut risk_params = position.effective_risk_params();
+    
+    if let Some(bracket) = market_config.risk_bracket(notional)? {
+        if leverage > bracket.max_leverage {
+            msg!("Error: Leverage {}x exceeds bracket max {}x", leverage, bracket.max_leverage);
+            return Err(TradingError::LeverageExceedsBracket.into());
+        }
+        
+        risk_params.maintenance_margin_ratio_bp = bracket.maintenance_margin_ratio_bp;
+    }
+    
+    // 新旧杠杆下IM（按开仓均价）的差额；追加的保证金和已结算的资金费保留在持仓中
+    let old_im = calculate_initial_margin(size_e6, position.entry_price_e6, position.leverage)?;
+    let new_im = calculate_initial_margin(size_e6, position.entry_price_e6, leverage)?;
+    let margin_change = safe_sub_i64(new_im, old_im)?;
+    let new_locked = safe_add_i64(position.locked_usdc_e6, margin_change)?;
+    
+    msg!("  IM: {} -> {} USDC", old_im as f64 / 1_000_000.0, new_im as f64 / 1_000_000.0);
+    msg!("  Locked: {} -> {} USDC", position.locked_usdc_e6 as f64 / 1_000_000.0, new_locked as f64 / 1_000_000.0);
+    
+    position.update_pnl(mark_price_e6);
+    
+    if margin_change < 0 {
+        // 释放保证金：调整后的保证金 + 未实现亏损不得低于按标记价格计算的新IM
+        let required_im = calculate_initial_margin(size_e6, mark_price_e6, leverage)?;
+        let margin_after = safe_add_i64(new_locked, position.unrealized_pnl_e6.min(0))?;
+        
+        if margin_after < required_im {
+            msg!("Error: Releasing {} would breach IM {}, margin after = {}",
+                -margin_change, required_im, margin_after);
+            return Err(TradingError::MarginRatioTooLow.into());
+        }
+    }
+    
+    // 账户抵押品的可用部分扣除全仓持仓未实现亏损（同LockMargin）
+    let unrealized_loss = match collateral.as_ref() {
+        Some(account) => {
+            let excluded = is_cross.then_some(user_position_account.key);
+            let other_unrealized_loss = cross_unrealized_loss(program_id, account, excluded, cross_position_accounts, now)?;
+            
+            if is_cross {
+                safe_add_i64(other_unrealized_loss, position.unrealized_pnl_e6.min(0))?
+            } else {
+                other_unrealized_loss
+            }
+        }
+        None => 0,
+    };
+    
+    // 资金划转：全仓只补足抵押品（扣除未实现亏损）低于占用IM的部分，释放部分留在抵押品中；
+    // 逐仓优先从可用抵押品划转，释放计入抵押品；账户抵押品不存在时与钱包之间划转
+    let wallet_transfer = match collateral.as_mut() {
+        Some(cross) if is_cross => {
+            cross.locked_im_e6 = safe_add_i64(cross.locked_im_e6, margin_change)?;
+            
+            if margin_change > 0 {
+                let equity = safe_add_i64(cross.collateral_e6, unrealized_loss)?;
+                let deposit = safe_sub_i64(cross.locked_im_e6, equity)?.clamp(0, margin_change);
+                cross.collateral_e6 = safe_add_i64(cross.collateral_e6, deposit)?;
+                deposit
+            } else {
+                0
+            }
+        }
+        Some(account) => {
+            if margin_change > 0 {
+                let available = safe_add_i64(account.free_collateral_e6(), unrealized_loss)?;
+                let from_collateral = margin_change.min(available.max(0));
+                account.collateral_e6 = safe_sub_i64(account.collateral_e6, from_collateral)?;
+                safe_sub_i64(margin_change, from_collateral)?
+            } else {
+                account.collateral_e6 = safe_sub_i64(account.collateral_e6, margin_change)?;
+                0
+            }
+        }
+        None => margin_change,
+    };
+    
+    if wallet_transfer > 0 {
+        // SPL Token Transfer: 用户 → Vault
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                user_usdc_account.key,
+                vault_usdc_account.key,
+                user.key,
+                &[],
+                wallet_transfer as u64,
+            )?,
+            &[
+                user_usdc_account.clone(),
+                vault_usdc_account.clone(),
+                user.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    } else if wallet_transfer < 0 {
+        // SPL Token Transfer: Vault → 用户
+        transfer_from_vault(
+            token_program,
+            vault_usdc_account,
+            user_usdc_account,
+            trading_vault_account,
+            vault_bump,
+            -wallet_transfer,
+        )?;
+    }
+    
+    // 更新持仓
+    position.leverage = leverage;
+    position.locked_usdc_e6 = new_locked;
+    position.risk_params = risk_params;
+    position.mm_e6 = calculate_maintenance_margin(
+        size_e6,
+        position.entry_price_e6,
+        risk_params.maintenance_margin_ratio_bp,
+    )?;
+    position.update_pnl(mark_price_e6);
+    position.update_liquidation_price();
+    position.updated_at = now;
+    
+    store_account(user_position_account, &position)?;
+    
+    if let Some(mut account) = collateral {
+        account.updated_at = now;
+        store_account(cross_margin_account, &account)?;
+        log_cross_margin(&account);
+    }
+    
+    vault.total_locked_usdc_e6 = safe_add_i64(vault.total_locked_usdc_e6, wallet_transfer)?;
+    vault.updated_at = now;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("LEVERAGE_ADJUSTED|wallet:{}|account:{}|market:{}|leverage:{}|im:{}|locked:{}|margin_change:{}|mm:{}|liquidation_price:{}",
+        user.key, account_id, market, leverage, new_im, new_locked, margin_change, position.mm_e6,
+        position.liquidation_price_e6);
+    
+    msg!("✅ Leverage adjusted!");
+    
+    Ok(())
+}
+
+/// 创建止盈/止损条件单
+#[allow(clippy::too_many_arguments)]
+fn process_create_conditional_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    account_id: String,
+    market: String,
+    order_id: u64,
+    trigger_price_e6: i64,
+    trigger_direction: TriggerDirection,
+    size_e6: i64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let conditional_order_account = next_account_info(account_iter)?;
+    let user = next_account_info(account_iter)?;
+    let user_position_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Create Conditional Order");
+    msg!("  Account: {}", account_id);
+    msg!("  Market: {}", market);
+    msg!("  Order ID: {}", order_id);
+    msg!("  Trigger: {:?} {}", trigger_direction, trigger_price_e6);
+    msg!("  Size: {}", size_e6);
+    
+    validate_account_id(&account_id)?;
+    validate_market(&market)?;
+    validate_size(size_e6)?;
+    validate_price(trigger_price_e6)?;
+    
+    validate_signer(user)?;
+    validate_system_program(system_program)?;
+    
+    // 条件单只能挂在用户自己的持仓上
+    let position = load_user_position(
+        program_id,
+        user_position_account,
+        user.key,
+        &account_id,
+        &market,
+    )?;
+    
+    let (expected_pda, bump) =
+        find_conditional_order_pda(program_id, user_position_account.key, order_id);
+    
+    // 按owner和数据长度判断是否已存在：他人预先转入lamports不影响创建
+    let exists = conditional_order_account.owner == program_id || !conditional_order_account.data_is_empty();
+    
+    if conditional_order_account.key != &expected_pda || exists {
+        msg!("Error: Conditional order PDA mismatch or already exists");
+        return Err(TradingError::InvalidConditionalOrder.into());
+    }
+    
+    create_pda_account(
+        program_id,
+        user,
+        conditional_order_account,
+        system_program,
+        ConditionalOrder::SIZE,
+        &[
+            CONDITIONAL_ORDER_SEED,
+            user_position_account.key.as_ref(),
+            &order_id.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+    
+    let order = ConditionalOrder::new(
+        *user.key,
+        *user_position_account.key,
+        position.position_nonce,
+        order_id,
+        trigger_price_e6,
+        trigger_direction,
+        size_e6,
+        bump,
+    );
+    
+    store_account(conditional_order_account, &order)?;
+    
+    // Emit事件
+    msg!("CONDITIONAL_ORDER_CREATED|wallet:{}|account:{}|market:{}|position:{}|order_id:{}|direction:{:?}|trigger_price:{}|size:{}",
+        user.key, account_id, market, user_position_account.key, order_id, trigger_direction,
+        trigger_price_e6, size_e6);
+    
+    msg!("✅ Conditional order created!");
+    
+    Ok(())
+}
+
+/// 取消条件单
+fn process_cancel_conditional_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let conditional_order_account = next_account_info(account_iter)?;
+    let user = next_account_info(account_iter)?;
+    
+    msg!("Cancel Conditional Order");
+    msg!("  Order ID: {}", order_id);
+    
+    validate_signer(user)?;
+    
+    let order = load_conditional_order(program_id, conditional_order_account)?;
+    
+    if order.wallet != *user.key || order.order_id != order_id {
+        msg!("Error: Conditional order does not belong to signer");
+        return Err(TradingError::InvalidConditionalOrder.into());
+    }
+    
+    close_position_account(conditional_order_account, user)?;
+    
+    // Emit事件
+    msg!("CONDITIONAL_ORDER_CANCELLED|wallet:{}|position:{}|order_id:{}",
+        user.key, order.position, order_id);
+    
+    msg!("✅ Conditional order cancelled!");
+    
+    Ok(())
+}
+
+/// Keeper执行条件单：预言机价格越过触发价格后按UnlockMargin流程平仓
+fn process_execute_conditional_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let conditional_order_account = next_account_info(account_iter)?;
+    let keeper = next_account_info(account_iter)?;
+    let keeper_usdc_account = next_account_info(account_iter)?;
+    
+    // 其余账户按UnlockMargin顺序传入
+    let close_accounts = account_iter.as_slice();
+    let user_position_account = close_accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let position_owner = close_accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let trading_vault_account = close_accounts.get(4).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let market_config_account = close_accounts.get(7).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let price_feed_account = close_accounts.get(8).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    
+    msg!("Execute Conditional Order");
+    msg!("  Keeper: {}", keeper.key);
+    
+    validate_signer(keeper)?;
+    
+    let order = load_conditional_order(program_id, conditional_order_account)?;
+    
+    msg!("  Order ID: {}", order.order_id);
+    msg!("  Trigger: {:?} {}", order.trigger_direction, order.trigger_price_e6);
+    
+    // 条件单必须对应当前持仓（持仓反手或关闭后重开则失效）
+    let position = load_position_account(program_id, user_position_account)?;
+    
+    if user_position_account.key != &order.position
+        || position.wallet != order.wallet
+        || position.position_nonce != order.position_nonce
+    {
+        msg!("Error: Conditional order does not match current position");
+        return Err(TradingError::InvalidConditionalOrder.into());
+    }
+    
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_token_account(keeper_usdc_account, keeper.key, &vault.usdc_mint)?;
+    
+    // 按预言机价格判断是否触发，并作为平仓价格
+    let market_config = load_market_config(program_id, market_config_account, &position.market)?;
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    let oracle_price = load_oracle_price(price_feed_account, &market_config, now)?;
+    
+    if !order.is_triggered(oracle_price.price_e6) {
+        msg!("Error: Oracle price {} has not crossed trigger {:?} {}",
+            oracle_price.price_e6, order.trigger_direction, order.trigger_price_e6);
+        return Err(TradingError::ConditionalOrderNotTriggered.into());
+    }
+    
+    let close_size_e6 = order.size_e6.min(position.size_e6.abs());
+    
+    let keeper_fee_e6 = close_position(
+        program_id,
+        close_accounts,
+        position.account_id.clone(),
+        position.market.clone(),
+        close_size_e6,
+        oracle_price.price_e6,
+        Some((keeper_usdc_account, vault.keeper_fee_bp)),
+    )?;
+    
+    // 关闭条件单，租金返还给持仓所有者
+    close_position_account(conditional_order_account, position_owner)?;
+    
+    // Emit事件
+    msg!("CONDITIONAL_ORDER_EXECUTED|wallet:{}|account:{}|market:{}|position:{}|order_id:{}|keeper:{}|trigger_price:{}|price:{}|size:{}|keeper_fee:{}",
+        order.wallet, position.account_id, position.market, order.position, order.order_id, keeper.key,
+        order.trigger_price_e6, oracle_price.price_e6, close_size_e6, keeper_fee_e6);
+    
+    msg!("✅ Conditional order executed!");
+    
+    Ok(())
+}
+
+/// 设置条件单Keeper奖励费率（仅管理员）
+fn process_set_keeper_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    keeper_fee_bp: u32,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    msg!("Set Keeper Fee");
+    msg!("  Keeper Fee: {}bp", keeper_fee_bp);
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    validate_keeper_fee(keeper_fee_bp)?;
+    
+    let old_keeper_fee_bp = vault.keeper_fee_bp;
+    
+    vault.keeper_fee_bp = keeper_fee_bp;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("KEEPER_FEE_UPDATED|keeper_fee_bp:{}->{}", old_keeper_fee_bp, keeper_fee_bp);
+    
+    msg!("✅ Keeper fee updated!");
+    
+    Ok(())
+}
+
+/// 设置全局交易模式（仅管理员）
+fn process_set_trading_mode(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mode: TradingMode,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    msg!("Set Trading Mode");
+    msg!("  Mode: {:?}", mode);
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let old_mode = vault.trading_mode;
+    
+    vault.trading_mode = mode;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("TRADING_MODE_UPDATED|scope:global|old:{:?}|new:{:?}", old_mode, mode);
+    
+    msg!("✅ Trading mode updated!");
+    
+    Ok(())
+}
+
+/// 设置市场交易模式（仅管理员）
+fn process_set_market_trading_mode(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: String,
+    mode: TradingMode,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let market_config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let trading_vault_account = next_account_info(account_iter)?;
+    
+    msg!("Set Market Trading Mode");
+    msg!("  Market: {}", market);
+    msg!("  Mode: {:?}", mode);
+    
+    // 验证admin = vault.authority
+    let (vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    let mut config = load_market_config(program_id, market_config_account, &market)?;
+    
+    let old_mode = config.trading_mode;
+    
+    config.trading_mode = mode;
+    config.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_account(market_config_account, &config)?;
+    
+    // Emit事件
+    msg!("TRADING_MODE_UPDATED|scope:market|market:{}|old:{:?}|new:{:?}", market, old_mode, mode);
+    
+    msg!("✅ Market trading mode updated!");
+    
+    Ok(())
+}
+
+/// 设置Guardian（仅管理员），旧版本Vault同时扩容
+fn process_set_guardian(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    guardian: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Set Guardian");
+    msg!("  Guardian: {}", guardian);
+    
+    validate_system_program(system_program)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    // 旧版本Vault先扩容（新增字段补0）
+    resize_trading_vault(trading_vault_account, admin, system_program)?;
+    
+    let old_guardian = vault.guardian;
+    
+    vault.guardian = guardian;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("GUARDIAN_UPDATED|old:{}|new:{}", old_guardian, guardian);
+    
+    msg!("✅ Guardian updated!");
+    
+    Ok(())
+}
+
+/// 旧版本Vault（272字节）扩容到TradingVault::SIZE（新增字段补0），payer补足租金
+fn resize_trading_vault<'a>(
+    trading_vault_account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let old_len = trading_vault_account.data_len();
+    
+    if old_len >= TradingVault::SIZE {
+        return Ok(());
+    }
+    
+    msg!("Resizing Trading Vault: {} -> {} bytes", old_len, TradingVault::SIZE);
+    trading_vault_account.realloc(TradingVault::SIZE, true)?;
+    
+    let required_lamports = Rent::get()?.minimum_balance(TradingVault::SIZE);
+    let top_up = required_lamports.saturating_sub(trading_vault_account.lamports());
+    
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, trading_vault_account.key, top_up),
+            &[
+                payer.clone(),
+                trading_vault_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+    
+    Ok(())
+}
+
+/// 紧急暂停（仅Guardian）
+fn process_pause(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let guardian = next_account_info(account_iter)?;
+    
+    msg!("Pause");
+    
+    validate_signer(guardian)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    
+    // 旧版本Vault没有Guardian，需管理员先SetGuardian扩容
+    if trading_vault_account.data_len() < TradingVault::SIZE {
+        msg!("Error: Trading Vault must be resized by SetGuardian first");
+        return Err(TradingError::VaultResizeRequired.into());
+    }
+    
+    if vault.guardian != *guardian.key {
+        msg!("Error: Signer is not the guardian");
+        return Err(TradingError::UnauthorizedGuardian.into());
+    }
+    
+    vault.paused = true;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("PROGRAM_PAUSED|guardian:{}|timestamp:{}", guardian.key, vault.updated_at);
+    
+    msg!("✅ Program paused!");
+    
+    Ok(())
+}
+
+/// 解除暂停（仅管理员）
+fn process_unpause(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    msg!("Unpause");
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    vault.paused = false;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("PROGRAM_UNPAUSED|admin:{}|timestamp:{}", admin.key, vault.updated_at);
+    
+    msg!("✅ Program unpaused!");
+    
+    Ok(())
+}
+
+/// 提议转移管理员（仅当前管理员）
+fn process_propose_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_authority: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    msg!("Propose Authority");
+    msg!("  New Authority: {}", new_authority);
+    
+    validate_system_program(system_program)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    // pending_authority位于原272字节之后，旧版本Vault先扩容
+    resize_trading_vault(trading_vault_account, admin, system_program)?;
+    
+    vault.pending_authority = new_authority;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("AUTHORITY_PROPOSED|authority:{}|pending:{}", vault.authority, new_authority);
+    
+    msg!("✅ Authority transfer proposed!");
+    
+    Ok(())
+}
+
+/// 接受管理员转移（新管理员签名）
+fn process_accept_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let new_authority = next_account_info(account_iter)?;
+    
+    msg!("Accept Authority");
+    
+    validate_signer(new_authority)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    
+    if vault.pending_authority == Pubkey::default() || vault.pending_authority != *new_authority.key {
+        msg!("Error: Signer is not the pending authority");
+        return Err(TradingError::InvalidAuthority.into());
+    }
+    
+    let old_authority = vault.authority;
+    
+    vault.authority = *new_authority.key;
+    vault.pending_authority = Pubkey::default();
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("AUTHORITY_TRANSFERRED|old:{}|new:{}", old_authority, new_authority.key);
+    
+    msg!("✅ Authority transferred!");
+    
+    Ok(())
+}
+
+/// 设置Insurance Fund和Fee Treasury账户（仅管理员）
+fn process_set_treasury_accounts(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let trading_vault_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let insurance_fund_account = next_account_info(account_iter)?;
+    let fee_treasury_account = next_account_info(account_iter)?;
+    
+    msg!("Set Treasury Accounts");
+    msg!("  Insurance Fund: {}", insurance_fund_account.key);
+    msg!("  Fee Treasury: {}", fee_treasury_account.key);
+    
+    validate_system_program(system_program)?;
+    
+    let (mut vault, _) = load_trading_vault(program_id, trading_vault_account)?;
+    validate_admin(&vault, admin)?;
+    
+    // 两个账户都必须由Vault PDA持有，且不能与Vault USDC账户或彼此相同
+    validate_token_account(insurance_fund_account, trading_vault_account.key, &vault.usdc_mint)
+        .map_err(|_| TradingError::InvalidInsuranceFundAccount)?;
+    validate_token_account(fee_treasury_account, trading_vault_account.key, &vault.usdc_mint)
+        .map_err(|_| TradingError::InvalidFeeTreasuryAccount)?;
+    
+    if insurance_fund_account.key == &vault.vault_usdc_account
+        || insurance_fund_account.key == fee_treasury_account.key
+    {
+        msg!("Error: Insurance fund must be a dedicated account");
+        return Err(TradingError::InvalidInsuranceFundAccount.into());
+    }
+    
+    if fee_treasury_account.key == &vault.vault_usdc_account {
+        msg!("Error: Fee treasury must be a dedicated account");
+        return Err(TradingError::InvalidFeeTreasuryAccount.into());
+    }
+    
+    // 旧版本Vault先扩容（新增字段补0）
+    resize_trading_vault(trading_vault_account, admin, system_program)?;
+    
+    vault.insurance_fund_account = *insurance_fund_account.key;
+    vault.fee_treasury_account = *fee_treasury_account.key;
+    vault.updated_at = solana_program::clock::Clock::get()?.unix_timestamp;
+    
+    store_trading_vault(trading_vault_account, &vault)?;
+    
+    // Emit事件
+    msg!("TREASURY_ACCOUNTS_UPDATED|insurance_fund:{}|fee_treasury:{}",
+        insurance_fund_account.key, fee_treasury_account.key);
+    
+    msg!("✅ Treasury accounts updated!");
+    
+    Ok(())
+}
@@ -5,6 +5,8 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{pubkey::Pubkey, sysvar::Sysvar};
 
+use crate::error::TradingError;
+
 /// Side枚举：Buy或Sell
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
@@ -19,6 +21,23 @@ pub enum MarginMode {
     Isolated,   // 逐仓
 }
 
+/// 交易模式（全局和每个市场各一个，生效时取较严格者）
+/// 
+/// 与MarketStatus（市场生命周期）独立，用于事故期间临时限制交易
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TradingMode {
+    Active,     // 正常交易
+    ReduceOnly, // 禁止LockMargin等增加风险的操作，允许平仓、强平
+    Halted,     // 停止全部交易相关指令
+}
+
+/// 条件单触发方向
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerDirection {
+    Above,      // 标记价格 >= 触发价格（多头止盈/空头止损）
+    Below,      // 标记价格 <= 触发价格（多头止损/空头止盈）
+}
+
 /// 清算状态
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LiquidationStatus {
@@ -28,6 +47,70 @@ pub enum LiquidationStatus {
     Liquidatable,           // 可强平（100%）
 }
 
+/// 市场状态
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketStatus {
+    Active,     // 正常交易
+    CloseOnly,  // 只能平仓/强平，禁止开仓
+    Delisted,   // 已下架（终态），禁止开仓，仍可平仓/强平
+}
+
+/// 风控参数
+/// 
+/// MarketConfig保存当前值；UserPosition开仓时保存快照，之后不随市场配置变化
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RiskParams {
+    pub maintenance_margin_ratio_bp: u32,   // 维持保证金率（基点，占名义价值）
+    pub liquidation_fee_bp: u32,            // 清算手续费（基点，占锁定保证金）
+    pub warning_threshold_bp: u32,          // 保证金率低于此值 → Warning
+    pub smart_hedge_threshold_bp: u32,      // 保证金率低于此值 → SmartHedgeTriggered
+    pub liquidation_threshold_bp: u32,      // 保证金率低于此值 → Liquidatable
+}
+
+impl RiskParams {
+    /// 默认参数（MM=0.5%名义价值, 清算费0.5%, 150%/110%/100%）
+    pub const DEFAULT: RiskParams = RiskParams {
+        maintenance_margin_ratio_bp: 50,
+        liquidation_fee_bp: 50,
+        warning_threshold_bp: 15000,
+        smart_hedge_threshold_bp: 11000,
+        liquidation_threshold_bp: 10000,
+    };
+    
+    /// 旧版本持仓的预留字段为0，视为未设置
+    pub fn is_unset(&self) -> bool {
+        self.liquidation_threshold_bp == 0
+    }
+    
+    /// 根据保证金率判断清算状态
+    pub fn liquidation_status(&self, margin_ratio_bp: u32) -> LiquidationStatus {
+        if margin_ratio_bp >= self.warning_threshold_bp {
+            LiquidationStatus::Normal
+        } else if margin_ratio_bp >= self.smart_hedge_threshold_bp {
+            LiquidationStatus::Warning
+        } else if margin_ratio_bp >= self.liquidation_threshold_bp {
+            LiquidationStatus::SmartHedgeTriggered
+        } else {
+            LiquidationStatus::Liquidatable
+        }
+    }
+}
+
+/// 风险档位（按持仓名义价值分档）
+/// 
+/// 持仓总名义价值 <= max_notional_e6 时适用该档位
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RiskBracket {
+    pub max_notional_e6: i64,               // 档位名义价值上限（e6格式）
+    pub max_leverage: u32,                  // 档位最大杠杆
+    pub maintenance_margin_ratio_bp: u32,   // 档位维持保证金率（基点）
+}
+
+impl RiskBracket {
+    /// 8 + 4 + 4 = 16 bytes
+    pub const SIZE: usize = 16;
+}
+
 /// Trading Vault（全局状态，单例PDA）
 /// PDA Seeds: [b"trading_vault"]
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
@@ -75,18 +158,61 @@ pub struct TradingVault {
     pub cumulative_fees_e6: i64,        // 累计手续费
     pub cumulative_pnl_e6: i64,         // 累计已实现盈亏
     
+    /// 授权的Relayer（UpdatePosition签名者，可由authority轮换）
+    pub relayer: Pubkey,
+    
+    /// 托管的USDC Mint
+    pub usdc_mint: Pubkey,
+    
+    /// Vault USDC托管账户（owner = Vault PDA）
+    pub vault_usdc_account: Pubkey,
+    
+    /// 累计坏账（Insurance Fund不足以覆盖的穿仓亏损，e6格式）
+    pub bad_debt_e6: i64,
+    
+    /// 交易手续费率（基点，按成交名义价值）
+    pub maker_fee_bp: u32,
+    pub taker_fee_bp: u32,
+    
+    /// 条件单执行奖励（基点，按成交名义价值，从平仓返还中支付给Keeper）
+    pub keeper_fee_bp: u32,
+    
+    /// 全局交易模式
+    pub trading_mode: TradingMode,
+    
+    /// 最近分配的持仓nonce（开仓/反手时递增，持仓账户关闭后重开也不会重复）
+    pub position_nonce: u64,
+    
+    /// 紧急暂停：Guardian可暂停，只有管理员可恢复
+    pub paused: bool,
+    pub guardian: Pubkey,
+    
+    /// 待接受的新管理员（ProposeAuthority设置，AcceptAuthority生效），默认值表示无
+    pub pending_authority: Pubkey,
+    
+    /// Insurance Fund和Fee Treasury的USDC账户（owner = Vault PDA，由SetTreasuryAccounts设置），默认值表示未设置
+    pub insurance_fund_account: Pubkey,
+    pub fee_treasury_account: Pubkey,
+    
     /// 预留扩展字段
-    pub reserved: [u8; 128],
+    pub reserved: [u8; 2],
 }
 
 impl TradingVault {
     pub const DISCRIMINATOR: u64 = 0x54524144_4556414c;
     pub const VERSION: u8 = 1;
     
-    /// 8 + 1 + 1 + 6 + 32 + 8*7 + 8*3 + 128 = 255 bytes
-    pub const SIZE: usize = 255;
+    /// 8 + 1 + 1 + 6 + 32 + 8*6 + 8*3 + 8*3 + 32*3 + 8 + 4*3 + 1 + 8 + 1 + 32 + 32 + 32*2 + 2 = 400 bytes
+    /// （较小的旧Vault由SetGuardian/ProposeAuthority/SetTreasuryAccounts扩容，
+    /// 交易模式和持仓nonce位于原272字节预留的12字节内）
+    pub const SIZE: usize = 400;
     
-    pub fn new(authority: Pubkey, bump: u8) -> Self {
+    pub fn new(
+        authority: Pubkey,
+        bump: u8,
+        usdc_mint: Pubkey,
+        vault_usdc_account: Pubkey,
+    ) -> Self {
         let now = solana_program::clock::Clock::get()
             .map(|clock| clock.unix_timestamp)
             .unwrap_or(0);
@@ -109,7 +235,30 @@ impl TradingVault {
             cumulative_volume_e6: 0,
             cumulative_fees_e6: 0,
             cumulative_pnl_e6: 0,
-            reserved: [0; 128],
+            relayer: authority,
+            usdc_mint,
+            vault_usdc_account,
+            bad_debt_e6: 0,
+            maker_fee_bp: 0,
+            taker_fee_bp: 0,
+            keeper_fee_bp: 0,
+            trading_mode: TradingMode::Active,
+            position_nonce: 0,
+            paused: false,
+            guardian: authority,
+            pending_authority: Pubkey::default(),
+            insurance_fund_account: Pubkey::default(),
+            fee_treasury_account: Pubkey::default(),
+            reserved: [0; 2],
+        }
+    }
+    
+    /// 成交适用的手续费率
+    pub fn trading_fee_bp(&self, is_maker: bool) -> u32 {
+        if is_maker {
+            self.maker_fee_bp
+        } else {
+            self.taker_fee_bp
         }
     }
 }
@@ -160,8 +309,28 @@ pub struct UserPosition {
     pub opened_at: i64,                 // 开仓时间（秒）
     pub updated_at: i64,                // 更新时间（秒）
     
+    // === 风控参数快照（开仓时从MarketConfig复制）===
+    pub risk_params: RiskParams,
+    
+    // === 交易手续费 ===
+    pub open_fees_paid_e6: i64,         // 累计开仓/加仓手续费
+    pub close_fees_paid_e6: i64,        // 累计平仓手续费
+    
+    // === 资金费 ===
+    pub last_funding_index: i128,       // 上次结算时的市场累计资金费index
+    pub cumulative_funding_e6: i64,     // 累计资金费（正=收到，负=支付）
+    
+    // === 持仓模式 ===
+    pub hedge_mode: bool,               // 双向持仓（PDA seeds包含Side）
+    
+    // === 持仓nonce ===
+    pub position_nonce: u64,            // 开仓/反手时从TradingVault.position_nonce分配，用于识别失效的条件单
+    
+    // === ADL索引 ===
+    pub adl_indexed: bool,              // 已计入FundingState的开放持仓数（旧持仓在下次被操作时计入）
+    
     // === 预留扩展字段 ===
-    pub reserved: [u8; 96],  // 增加预留空间（移除了TP/SL和Smart Hedge字段）
+    pub reserved: [u8; 26],  // 增加预留空间（TP/SL由ConditionalOrder实现，移除了Smart Hedge字段）
 }
 
 impl UserPosition {
@@ -183,6 +352,7 @@ impl UserPosition {
         margin_mode: MarginMode,
         locked_usdc_e6: i64,
         mm_e6: i64,
+        risk_params: RiskParams,
         bump: u8,
     ) -> Self {
         let now = solana_program::clock::Clock::get()
@@ -219,10 +389,51 @@ impl UserPosition {
             liquidation_status: LiquidationStatus::Normal,
             opened_at: now,
             updated_at: now,
-            reserved: [0; 96],
+            risk_params,
+            open_fees_paid_e6: 0,
+            close_fees_paid_e6: 0,
+            last_funding_index: 0,
+            cumulative_funding_e6: 0,
+            hedge_mode: false,
+            position_nonce: 0,
+            adl_indexed: false,
+            reserved: [0; 26],
         }
     }
     
+    /// 反向开仓：原持仓已全部对冲后，按新方向重置持仓
+    /// 
+    /// 保留累计已实现盈亏、手续费和资金费记录
+    #[allow(clippy::too_many_arguments)]
+    pub fn flip(
+        &mut self,
+        side: Side,
+        size_e6: i64,
+        entry_price_e6: i64,
+        leverage: u32,
+        margin_mode: MarginMode,
+        locked_usdc_e6: i64,
+        mm_e6: i64,
+        risk_params: RiskParams,
+    ) {
+        self.side = side;
+        self.size_e6 = size_e6;
+        self.entry_price_e6 = entry_price_e6;
+        self.mark_price_e6 = entry_price_e6;
+        self.leverage = leverage;
+        self.margin_mode = margin_mode;
+        self.locked_usdc_e6 = locked_usdc_e6;
+        self.mm_e6 = mm_e6;
+        self.risk_params = risk_params;
+        self.unrealized_pnl_e6 = 0;
+        self.liquidation_price_e6 = Self::calculate_liquidation_price(entry_price_e6, side, leverage);
+        self.margin_ratio_bp = 20000;
+        self.liquidation_status = LiquidationStatus::Normal;
+        self.opened_at = solana_program::clock::Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+    }
+    
     /// 计算强平价格（简化版）
     fn calculate_liquidation_price(
         entry_price_e6: i64,
@@ -247,6 +458,26 @@ impl UserPosition {
         }
     }
     
+    /// 按当前锁定保证金重新计算强平价格（保证金率降至liquidation_threshold时的价格）
+    pub fn update_liquidation_price(&mut self) {
+        if self.size_e6 == 0 {
+            return;
+        }
+        
+        let threshold_margin = self.mm_e6 as i128
+            * self.effective_risk_params().liquidation_threshold_bp as i128
+            / 10000;
+        let buffer = (self.locked_usdc_e6 as i128 - threshold_margin) * 1_000_000
+            / self.size_e6.abs() as i128;
+        
+        let liquidation_price = match self.side {
+            Side::Buy => self.entry_price_e6 as i128 - buffer,
+            Side::Sell => self.entry_price_e6 as i128 + buffer,
+        };
+        
+        self.liquidation_price_e6 = liquidation_price.clamp(0, i64::MAX as i128) as i64;
+    }
+    
     /// 计算未实现盈亏
     pub fn calculate_unrealized_pnl(&self, current_mark_price_e6: i64) -> i64 {
         let price_diff = match self.side {
@@ -274,22 +505,24 @@ impl UserPosition {
         (equity as i128 * 10000 / self.mm_e6 as i128) as u32
     }
     
+    /// 持仓生效的风控参数（旧版本持仓未保存快照时使用默认值）
+    pub fn effective_risk_params(&self) -> RiskParams {
+        if self.risk_params.is_unset() {
+            RiskParams::DEFAULT
+        } else {
+            self.risk_params
+        }
+    }
+    
     /// 更新标记价格和未实现盈亏
     pub fn update_pnl(&mut self, mark_price_e6: i64) {
         self.mark_price_e6 = mark_price_e6;
         self.unrealized_pnl_e6 = self.calculate_unrealized_pnl(mark_price_e6);
         self.margin_ratio_bp = self.calculate_margin_ratio();
         
-        // 更新清算状态
-        self.liquidation_status = if self.margin_ratio_bp >= 15000 {
-            LiquidationStatus::Normal
-        } else if self.margin_ratio_bp >= 11000 {
-            LiquidationStatus::Warning
-        } else if self.margin_ratio_bp >= 10000 {
-            LiquidationStatus::SmartHedgeTriggered
-        } else {
-            LiquidationStatus::Liquidatable
-        };
+        // 更新清算状态（使用开仓时的风控参数）
+        self.liquidation_status = self.effective_risk_params()
+            .liquidation_status(self.margin_ratio_bp);
         
         self.updated_at = solana_program::clock::Clock::get()
             .map(|clock| clock.unix_timestamp)
@@ -297,6 +530,741 @@ impl UserPosition {
     }
 }
 
+/// Market Config（每个市场一个PDA，由管理员创建）
+/// PDA Seeds: [b"market", market.as_bytes()]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct MarketConfig {
+    /// 账户类型标识符 "MKTCONFG" = 0x4d4b5443_4f4e4647
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 市场交易模式，与全局模式取较严格者（占用原对齐字段的1字节，旧账户为0即Active）
+    pub trading_mode: TradingMode,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 5],
+    
+    /// 市场（如 "BTC-PERP"）最大32字节
+    pub market: String,
+    
+    /// 市场状态
+    pub status: MarketStatus,
+    
+    // === 风控参数 ===
+    pub max_leverage: u32,                  // 最大杠杆倍数
+    pub risk_params: RiskParams,            // 新开仓使用的风控参数
+    
+    // === 交易规则 ===
+    pub tick_size_e6: i64,                  // 最小价格变动（e6格式）
+    pub lot_size_e6: i64,                   // 最小数量变动（e6格式）
+    
+    // === 时间戳 ===
+    pub created_at: i64,
+    pub updated_at: i64,
+    
+    // === 风险档位（按名义价值升序，为空时使用max_leverage和risk_params）===
+    pub risk_brackets: Vec<RiskBracket>,
+    
+    // === 预言机（Pyth价格账户）===
+    pub oracle: Pubkey,                     // 价格账户，默认值表示未配置
+    pub max_price_deviation_bp: u32,        // 提交价格与预言机价格最大偏离（基点）
+    pub max_oracle_staleness_secs: u32,     // 预言机价格最大延迟（秒）
+    pub max_oracle_confidence_bp: u32,      // 置信区间占价格的最大比例（基点）
+    
+    // === 部分强平 ===
+    pub partial_liquidation_target_bp: u32, // 部分强平后恢复的保证金率（基点），0 = 全部强平
+}
+
+impl MarketConfig {
+    pub const DISCRIMINATOR: u64 = 0x4d4b5443_4f4e4647;
+    pub const VERSION: u8 = 1;
+    
+    /// 市场名称最大长度
+    pub const MAX_MARKET_LEN: usize = 32;
+    
+    /// 最大风险档位数
+    pub const MAX_RISK_BRACKETS: usize = 5;
+    
+    /// 不含风险档位：8 + 1 + 1 + 1 + 5 + (4+32) + 1 + 4 + 4*5 + 8*2 + 8*2 + 4 + 32 + 4*3 + 4 = 161 bytes
+    /// （部分强平目标占用原预留的4字节，交易模式占用原对齐字段的1字节）
+    pub const BASE_SIZE: usize = 161;
+    
+    /// 161 + 16*5 = 241 bytes
+    pub const MAX_SIZE: usize = Self::BASE_SIZE + RiskBracket::SIZE * Self::MAX_RISK_BRACKETS;
+    
+    pub fn new(
+        market: String,
+        max_leverage: u32,
+        risk_params: RiskParams,
+        tick_size_e6: i64,
+        lot_size_e6: i64,
+        bump: u8,
+    ) -> Self {
+        let now = solana_program::clock::Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+        
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            trading_mode: TradingMode::Active,
+            reserved_align: [0; 5],
+            market,
+            status: MarketStatus::Active,
+            max_leverage,
+            risk_params,
+            tick_size_e6,
+            lot_size_e6,
+            created_at: now,
+            updated_at: now,
+            risk_brackets: Vec::new(),
+            oracle: Pubkey::default(),
+            max_price_deviation_bp: 0,
+            max_oracle_staleness_secs: 0,
+            max_oracle_confidence_bp: 0,
+            partial_liquidation_target_bp: 0,
+        }
+    }
+    
+    /// 是否已配置预言机
+    pub fn has_oracle(&self) -> bool {
+        self.oracle != Pubkey::default()
+    }
+    
+    /// 是否允许开仓/加仓
+    pub fn can_open(&self) -> bool {
+        self.status == MarketStatus::Active
+    }
+    
+    /// 根据持仓总名义价值选择风险档位
+    /// 
+    /// 未配置档位时返回None；超出最高档位时返回Err
+    pub fn risk_bracket(&self, notional_e6: i64) -> Result<Option<RiskBracket>, TradingError> {
+        if self.risk_brackets.is_empty() {
+            return Ok(None);
+        }
+        
+        self.risk_brackets
+            .iter()
+            .find(|bracket| notional_e6 <= bracket.max_notional_e6)
+            .copied()
+            .map(Some)
+            .ok_or(TradingError::PositionNotionalTooLarge)
+    }
+}
+
+/// Funding State（每个市场一个PDA）
+/// PDA Seeds: [b"funding", market.as_bytes()]
+/// 
+/// cumulative_funding_index: 每1.0单位多头持仓累计支付的资金费（USDC e12，负数表示收到）
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct FundingState {
+    /// 账户类型标识符 "FUNDINGS" = 0x46554e44_494e4753
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 6],
+    
+    /// 市场（如 "BTC-PERP"）
+    pub market: String,
+    
+    /// 授权的Keeper（UpdateFunding签名者）
+    pub keeper: Pubkey,
+    
+    // === 资金费 ===
+    pub cumulative_funding_index: i128,     // 累计资金费index（USDC e12 / 1.0 size）
+    pub last_funding_rate_e9: i64,          // 最近一次资金费率（每个周期，1e9 = 100%）
+    pub last_mark_price_e6: i64,            // 最近一次计算使用的标记价格
+    pub funding_interval_secs: u32,         // 资金费周期（秒）
+    pub max_funding_rate_e9: i64,           // 单周期资金费率上限（绝对值）
+    
+    // === 时间戳 ===
+    pub created_at: i64,
+    pub last_updated_at: i64,
+    
+    // === ADL（占用原预留字段，旧账户为0即没有待覆盖的坏账） ===
+    pub long_bankruptcy_price_e6: i64,      // 最近一次多头穿仓的破产价格
+    pub long_adl_outstanding_e6: i64,       // 多头穿仓尚未由ADL覆盖的坏账
+    pub short_bankruptcy_price_e6: i64,     // 最近一次空头穿仓的破产价格
+    pub short_adl_outstanding_e6: i64,      // 空头穿仓尚未由ADL覆盖的坏账
+    pub long_positions: u32,                // 已登记的开放多头持仓数（ADL验证目标完整）
+    pub short_positions: u32,               // 已登记的开放空头持仓数
+    
+    // === 预留扩展字段 ===
+    pub reserved: [u8; 24],
+}
+
+impl FundingState {
+    pub const DISCRIMINATOR: u64 = 0x46554e44_494e4753;
+    pub const VERSION: u8 = 1;
+    
+    /// 8 + 1 + 1 + 6 + (4+32) + 32 + 16 + 8*2 + 4 + 8 + 8*2 + 8*4 + 4*2 + 24 = 208 bytes
+    pub const MAX_SIZE: usize = 208;
+    
+    pub fn new(
+        market: String,
+        keeper: Pubkey,
+        funding_interval_secs: u32,
+        max_funding_rate_e9: i64,
+        bump: u8,
+    ) -> Self {
+        let now = solana_program::clock::Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+        
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved_align: [0; 6],
+            market,
+            keeper,
+            cumulative_funding_index: 0,
+            last_funding_rate_e9: 0,
+            last_mark_price_e6: 0,
+            funding_interval_secs,
+            max_funding_rate_e9,
+            created_at: now,
+            last_updated_at: now,
+            long_bankruptcy_price_e6: 0,
+            long_adl_outstanding_e6: 0,
+            short_bankruptcy_price_e6: 0,
+            short_adl_outstanding_e6: 0,
+            long_positions: 0,
+            short_positions: 0,
+            reserved: [0; 24],
+        }
+    }
+    
+    /// 记录穿仓产生的坏账：同方向金额累计，破产价格取最近一次
+    pub fn record_bankruptcy(&mut self, bankrupt_side: Side, bankruptcy_price_e6: i64, bad_debt_e6: i64) {
+        match bankrupt_side {
+            Side::Buy => {
+                self.long_bankruptcy_price_e6 = bankruptcy_price_e6;
+                self.long_adl_outstanding_e6 += bad_debt_e6;
+            }
+            Side::Sell => {
+                self.short_bankruptcy_price_e6 = bankruptcy_price_e6;
+                self.short_adl_outstanding_e6 += bad_debt_e6;
+            }
+        }
+    }
+    
+    /// 待ADL覆盖的穿仓：返回 (穿仓方向, 破产价格, 未覆盖坏账)，两个方向都有时先处理多头
+    pub fn pending_adl(&self) -> Option<(Side, i64, i64)> {
+        if self.long_adl_outstanding_e6 > 0 {
+            Some((Side::Buy, self.long_bankruptcy_price_e6, self.long_adl_outstanding_e6))
+        } else if self.short_adl_outstanding_e6 > 0 {
+            Some((Side::Sell, self.short_bankruptcy_price_e6, self.short_adl_outstanding_e6))
+        } else {
+            None
+        }
+    }
+    
+    /// ADL覆盖坏账后减少对应方向的未覆盖金额
+    pub fn reduce_adl_outstanding(&mut self, bankrupt_side: Side, covered_e6: i64) {
+        match bankrupt_side {
+            Side::Buy => self.long_adl_outstanding_e6 -= covered_e6,
+            Side::Sell => self.short_adl_outstanding_e6 -= covered_e6,
+        }
+    }
+    
+    /// 登记开放持仓（每个持仓只计一次）
+    pub fn index_position(&mut self, position: &mut UserPosition) {
+        if position.adl_indexed {
+            return;
+        }
+        
+        match position.side {
+            Side::Buy => self.long_positions += 1,
+            Side::Sell => self.short_positions += 1,
+        }
+        position.adl_indexed = true;
+    }
+    
+    /// 持仓关闭或反手前取消登记
+    pub fn unindex_position(&mut self, position: &mut UserPosition) {
+        if !position.adl_indexed {
+            return;
+        }
+        
+        match position.side {
+            Side::Buy => self.long_positions = self.long_positions.saturating_sub(1),
+            Side::Sell => self.short_positions = self.short_positions.saturating_sub(1),
+        }
+        position.adl_indexed = false;
+    }
+    
+    /// 指定方向已登记的开放持仓数
+    pub fn indexed_positions(&self, side: Side) -> u32 {
+        match side {
+            Side::Buy => self.long_positions,
+            Side::Sell => self.short_positions,
+        }
+    }
+}
+
+/// Cross Margin Account（每个 wallet + account_id 一个PDA）
+/// PDA Seeds: [b"cross_margin", wallet, account_id.as_bytes()]
+/// 
+/// 账户抵押品：由Deposit/Withdraw存取，全仓持仓共享，逐仓开仓时也可从中划转IM。
+/// 全仓开仓IM从可用抵押品中划转，
+/// 账户权益 = 抵押品 + 全部全仓持仓未实现盈亏，低于全部持仓MM时才可强平
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct CrossMarginAccount {
+    /// 账户类型标识符 "XMARGIN_" = 0x584d4152_47494e5f
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 6],
+    
+    // === 账户信息 ===
+    pub wallet: Pubkey,                 // 用户钱包
+    pub account_id: String,             // 账户ID 最大64字节
+    
+    // === 抵押品 ===
+    pub collateral_e6: i64,             // 账户抵押品（存入余额 + 全仓持仓占用的IM）
+    pub locked_im_e6: i64,              // 全仓持仓占用的IM合计
+    pub realized_pnl_e6: i64,           // 全仓持仓已实现盈亏（累计）
+    
+    // === 全仓持仓 ===
+    pub positions: Vec<Pubkey>,         // 全仓持仓PDA，最多MAX_POSITIONS个
+    
+    // === 时间戳 ===
+    pub created_at: i64,
+    pub updated_at: i64,
+    
+    // === 预留扩展字段 ===
+    pub reserved: [u8; 64],
+}
+
+impl CrossMarginAccount {
+    pub const DISCRIMINATOR: u64 = 0x584d4152_47494e5f;
+    pub const VERSION: u8 = 1;
+    
+    /// 账户ID最大长度
+    pub const MAX_ACCOUNT_ID_LEN: usize = 64;
+    
+    /// 最大全仓持仓数
+    pub const MAX_POSITIONS: usize = 10;
+    
+    /// 8 + 1 + 1 + 6 + 32 + (4+64) + 8*3 + (4+32*10) + 8*2 + 64 = 544 bytes
+    pub const MAX_SIZE: usize = 544;
+    
+    pub fn new(wallet: Pubkey, account_id: String, bump: u8) -> Self {
+        let now = solana_program::clock::Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+        
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved_align: [0; 6],
+            wallet,
+            account_id,
+            collateral_e6: 0,
+            locked_im_e6: 0,
+            realized_pnl_e6: 0,
+            positions: Vec::new(),
+            created_at: now,
+            updated_at: now,
+            reserved: [0; 64],
+        }
+    }
+    
+    /// 可用抵押品（未被全仓持仓占用）
+    pub fn free_collateral_e6(&self) -> i64 {
+        self.collateral_e6 - self.locked_im_e6
+    }
+    
+    /// 全仓持仓保证金变动（资金费等）同步计入抵押品和占用IM，可用抵押品不变
+    pub fn adjust_position_margin(&mut self, delta_e6: i64) {
+        self.collateral_e6 += delta_e6;
+        self.locked_im_e6 += delta_e6;
+    }
+    
+    /// 登记全仓持仓
+    pub fn add_position(&mut self, position: Pubkey) -> Result<(), TradingError> {
+        if self.positions.contains(&position) {
+            return Ok(());
+        }
+        
+        if self.positions.len() >= Self::MAX_POSITIONS {
+            return Err(TradingError::TooManyCrossPositions);
+        }
+        
+        self.positions.push(position);
+        Ok(())
+    }
+    
+    /// 移除已关闭的全仓持仓
+    pub fn remove_position(&mut self, position: &Pubkey) {
+        self.positions.retain(|key| key != position);
+    }
+}
+
+/// Conditional Order（止盈/止损条件单，挂在持仓上）
+/// PDA Seeds: [b"conditional_order", position.key().as_ref(), order_id.to_le_bytes()]
+/// 
+/// 用户签名创建/取消；标记价格越过触发价格后任何Keeper都可执行，
+/// 按UnlockMargin流程以预言机价格平仓，Keeper获得TradingVault.keeper_fee_bp奖励
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct ConditionalOrder {
+    /// 账户类型标识符 "CONDORDR" = 0x434f4e44_4f524452
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 6],
+    
+    // === 关联持仓 ===
+    pub wallet: Pubkey,                 // 用户钱包
+    pub position: Pubkey,               // User Position PDA
+    pub position_nonce: u64,            // 创建时的持仓nonce，持仓反手或关闭后重开则条件单失效
+    pub order_id: u64,                  // 用户指定的条件单编号（同一持仓可挂多个）
+    
+    // === 触发条件 ===
+    pub trigger_price_e6: i64,          // 触发价格（e6格式）
+    pub trigger_direction: TriggerDirection,
+    pub size_e6: i64,                   // 平仓数量（执行时不超过持仓数量）
+    
+    // === 时间戳 ===
+    pub created_at: i64,
+    
+    // === 预留扩展字段 ===
+    pub reserved: [u8; 32],
+}
+
+impl ConditionalOrder {
+    pub const DISCRIMINATOR: u64 = 0x434f4e44_4f524452;
+    pub const VERSION: u8 = 1;
+    
+    /// 8 + 1 + 1 + 6 + 32*2 + 8*2 + 8 + 1 + 8 + 8 + 32 = 153 bytes
+    pub const SIZE: usize = 153;
+    
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        wallet: Pubkey,
+        position: Pubkey,
+        position_nonce: u64,
+        order_id: u64,
+        trigger_price_e6: i64,
+        trigger_direction: TriggerDirection,
+        size_e6: i64,
+        bump: u8,
+    ) -> Self {
+        let now = solana_program::clock::Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+        
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved_align: [0; 6],
+            wallet,
+            position,
+            position_nonce,
+            order_id,
+            trigger_price_e6,
+            trigger_direction,
+            size_e6,
+            created_at: now,
+            reserved: [0; 32],
+        }
+    }
+    
+    /// 标记价格是否已越过触发价格
+    pub fn is_triggered(&self, mark_price_e6: i64) -> bool {
+        match self.trigger_direction {
+            TriggerDirection::Above => mark_price_e6 >= self.trigger_price_e6,
+            TriggerDirection::Below => mark_price_e6 <= self.trigger_price_e6,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_trading_vault_size() {
+        let vault = TradingVault::new(
+            Pubkey::new_unique(),
+            255,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        assert_eq!(vault.try_to_vec().unwrap().len(), TradingVault::SIZE);
+        assert_eq!(vault.relayer, vault.authority);
+        assert_eq!(vault.guardian, vault.authority);
+    }
+    
+    #[test]
+    fn test_market_config_max_size() {
+        let config = MarketConfig::new(
+            "X".repeat(MarketConfig::MAX_MARKET_LEN),
+            100,
+            RiskParams::DEFAULT,
+            100_000,
+            1_000,
+            255,
+        );
+        assert_eq!(config.try_to_vec().unwrap().len(), MarketConfig::BASE_SIZE);
+        
+        let mut config = config;
+        config.risk_brackets = vec![
+            RiskBracket { max_notional_e6: i64::MAX, max_leverage: 1, maintenance_margin_ratio_bp: 1 };
+            MarketConfig::MAX_RISK_BRACKETS
+        ];
+        assert_eq!(config.try_to_vec().unwrap().len(), MarketConfig::MAX_SIZE);
+    }
+    
+    #[test]
+    fn test_funding_state_max_size() {
+        let state = FundingState::new(
+            "X".repeat(MarketConfig::MAX_MARKET_LEN),
+            Pubkey::new_unique(),
+            28_800,
+            750_000,
+            255,
+        );
+        assert_eq!(state.try_to_vec().unwrap().len(), FundingState::MAX_SIZE);
+    }
+    
+    #[test]
+    fn test_funding_state_adl_pending() {
+        let mut state = FundingState::new("BTC-PERP".to_string(), Pubkey::new_unique(), 3600, 1_000_000, 255);
+        assert!(state.pending_adl().is_none());
+        
+        state.record_bankruptcy(Side::Sell, 101_000_000_000, 200_000_000);
+        state.record_bankruptcy(Side::Buy, 99_000_000_000, 100_000_000);
+        state.record_bankruptcy(Side::Buy, 98_500_000_000, 50_000_000);
+        
+        // 多头优先，破产价格取最近一次
+        assert_eq!(state.pending_adl(), Some((Side::Buy, 98_500_000_000, 150_000_000)));
+        
+        state.reduce_adl_outstanding(Side::Buy, 150_000_000);
+        assert_eq!(state.pending_adl(), Some((Side::Sell, 101_000_000_000, 200_000_000)));
+        
+        state.reduce_adl_outstanding(Side::Sell, 200_000_000);
+        assert!(state.pending_adl().is_none());
+    }
+    
+    #[test]
+    fn test_cross_margin_account_max_size() {
+        let mut account = CrossMarginAccount::new(
+            Pubkey::new_unique(),
+            "X".repeat(CrossMarginAccount::MAX_ACCOUNT_ID_LEN),
+            255,
+        );
+        
+        for _ in 0..CrossMarginAccount::MAX_POSITIONS {
+            account.add_position(Pubkey::new_unique()).unwrap();
+        }
+        assert!(account.add_position(Pubkey::new_unique()).is_err());
+        assert_eq!(account.try_to_vec().unwrap().len(), CrossMarginAccount::MAX_SIZE);
+        
+        // 重复登记同一持仓不占用名额
+        let existing = account.positions[0];
+        assert!(account.add_position(existing).is_ok());
+        account.remove_position(&existing);
+        assert_eq!(account.positions.len(), CrossMarginAccount::MAX_POSITIONS - 1);
+    }
+    
+    #[test]
+    fn test_conditional_order_trigger() {
+        let order = ConditionalOrder::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            0,
+            u64::MAX,
+            95_000_000_000,
+            TriggerDirection::Below,
+            1_000_000,
+            255,
+        );
+        assert_eq!(order.try_to_vec().unwrap().len(), ConditionalOrder::SIZE);
+        
+        assert!(!order.is_triggered(95_000_000_001));
+        assert!(order.is_triggered(95_000_000_000));
+        assert!(order.is_triggered(90_000_000_000));
+        
+        let take_profit = ConditionalOrder {
+            trigger_direction: TriggerDirection::Above,
+            ..order
+        };
+        assert!(!take_profit.is_triggered(94_999_999_999));
+        assert!(take_profit.is_triggered(95_000_000_000));
+    }
+    
+    #[test]
+    fn test_market_config_risk_bracket() {
+        let mut config = MarketConfig::new(
+            "BTC-PERP".to_string(),
+            100,
+            RiskParams::DEFAULT,
+            100_000,
+            1_000,
+            255,
+        );
+        
+        // 未配置档位
+        assert!(matches!(config.risk_bracket(1_000_000_000_000), Ok(None)));
+        
+        config.risk_brackets = vec![
+            RiskBracket { max_notional_e6: 50_000_000_000, max_leverage: 100, maintenance_margin_ratio_bp: 50 },
+            RiskBracket { max_notional_e6: 250_000_000_000, max_leverage: 50, maintenance_margin_ratio_bp: 100 },
+            RiskBracket { max_notional_e6: 1_000_000_000_000, max_leverage: 20, maintenance_margin_ratio_bp: 250 },
+        ];
+        
+        assert_eq!(config.risk_bracket(10_000_000_000).unwrap().unwrap().max_leverage, 100);
+        assert_eq!(config.risk_bracket(50_000_000_000).unwrap().unwrap().max_leverage, 100);
+        assert_eq!(config.risk_bracket(50_000_000_001).unwrap().unwrap().max_leverage, 50);
+        assert_eq!(config.risk_bracket(1_000_000_000_000).unwrap().unwrap().maintenance_margin_ratio_bp, 250);
+        assert!(matches!(
+            config.risk_bracket(1_000_000_000_001),
+            Err(TradingError::PositionNotionalTooLarge)
+        ));
+    }
+    
+    #[test]
+    fn test_position_uses_snapshot_risk_params() {
+        let strict = RiskParams {
+            warning_threshold_bp: 30000,
+            smart_hedge_threshold_bp: 20000,
+            liquidation_threshold_bp: 15000,
+            ..RiskParams::DEFAULT
+        };
+        
+        let mut position = UserPosition::new(
+            Pubkey::new_unique(),
+            "test_isolated".to_string(),
+            "BTC-PERP".to_string(),
+            Side::Buy,
+            1_000_000,
+            100_000_000_000,
+            20,
+            MarginMode::Isolated,
+            5_000_000_000,
+            500_000_000,
+            strict,
+            255,
+        );
+        
+        // 价格下跌$4,000：equity = $1,000, MM = $500 → 200%
+        position.update_pnl(96_000_000_000);
+        assert_eq!(position.margin_ratio_bp, 20000);
+        assert_eq!(position.liquidation_status, LiquidationStatus::Warning);
+        
+        // 同样保证金率，默认参数下为Normal
+        position.risk_params = RiskParams::DEFAULT;
+        position.update_pnl(96_000_000_000);
+        assert_eq!(position.liquidation_status, LiquidationStatus::Normal);
+        
+        // 旧版本持仓（预留字段为0）回退到默认参数
+        position.risk_params = RiskParams {
+            maintenance_margin_ratio_bp: 0,
+            liquidation_fee_bp: 0,
+            warning_threshold_bp: 0,
+            smart_hedge_threshold_bp: 0,
+            liquidation_threshold_bp: 0,
+        };
+        assert_eq!(position.effective_risk_params(), RiskParams::DEFAULT);
+    }
+    
+    #[test]
+    fn test_update_liquidation_price() {
+        let mut position = UserPosition::new(
+            Pubkey::new_unique(),
+            "test_isolated".to_string(),
+            "BTC-PERP".to_string(),
+            Side::Buy,
+            1_000_000,
+            100_000_000_000,
+            20,
+            MarginMode::Isolated,
+            5_000_000_000,
+            500_000_000,
+            RiskParams::DEFAULT,
+            255,
+        );
+        
+        // equity降至MM（$500）时强平：$100,000 − ($5,000 − $500) = $95,500
+        position.update_liquidation_price();
+        assert_eq!(position.liquidation_price_e6, 95_500_000_000);
+        
+        // 空头
+        position.side = Side::Sell;
+        position.update_liquidation_price();
+        assert_eq!(position.liquidation_price_e6, 104_500_000_000);
+    }
+    
+    #[test]
+    fn test_position_flip() {
+        let mut position = UserPosition::new(
+            Pubkey::new_unique(),
+            "test_isolated".to_string(),
+            "BTC-PERP".to_string(),
+            Side::Buy,
+            1_000_000,
+            100_000_000_000,
+            20,
+            MarginMode::Isolated,
+            5_000_000_000,
+            500_000_000,
+            RiskParams::DEFAULT,
+            255,
+        );
+        position.realized_pnl_e6 = 500_000_000;
+        position.update_pnl(96_000_000_000);
+        
+        position.flip(
+            Side::Sell,
+            2_000_000,
+            100_500_000_000,
+            10,
+            MarginMode::Isolated,
+            20_100_000_000,
+            1_005_000_000,
+            RiskParams::DEFAULT,
+        );
+        
+        assert_eq!(position.side, Side::Sell);
+        assert_eq!(position.size_e6, 2_000_000);
+        assert_eq!(position.unrealized_pnl_e6, 0);
+        assert_eq!(position.liquidation_status, LiquidationStatus::Normal);
+        // 空头强平价 = 入场价 × (1 + 1/10)
+        assert_eq!(position.liquidation_price_e6, 110_550_000_000);
+        // 保留累计已实现盈亏
+        assert_eq!(position.realized_pnl_e6, 500_000_000);
+    }
+}
+
 // === ProtectionPool已移除 ===
 // Smart Hedge功能应该在1024-core/smart-hedge-engine中实现
 // trading-program只负责USDC的存入和取出！
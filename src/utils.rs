@@ -1,6 +1,6 @@
 //! Trading Program Utility Functions
 
-use crate::error::TradingError;
+use crate::{error::TradingError, state::{MarketConfig, RiskBracket, RiskParams, TradingMode}};
 use solana_program::program_error::ProgramError;
 
 /// 验证杠杆范围（1-100x）
@@ -27,16 +27,185 @@ pub fn validate_size(size_e6: i64) -> Result<(), ProgramError> {
     Ok(())
 }
 
-/// 验证市场名称
+/// 验证市场名称（是否已上架由MarketConfig PDA决定）
 pub fn validate_market(market: &str) -> Result<(), ProgramError> {
-    const VALID_MARKETS: &[&str] = &["BTC-PERP", "ETH-PERP", "SOL-PERP"];
-    
-    if !VALID_MARKETS.contains(&market) {
+    if market.is_empty() || market.len() > MarketConfig::MAX_MARKET_LEN {
         return Err(TradingError::InvalidMarket.into());
     }
     Ok(())
 }
 
+/// 验证杠杆不超过市场上限
+pub fn validate_market_leverage(leverage: u32, config: &MarketConfig) -> Result<(), ProgramError> {
+    validate_leverage(leverage)?;
+    
+    if leverage > config.max_leverage {
+        return Err(TradingError::InvalidLeverage.into());
+    }
+    Ok(())
+}
+
+/// 验证价格符合tick size
+pub fn validate_tick_size(price_e6: i64, config: &MarketConfig) -> Result<(), ProgramError> {
+    if config.tick_size_e6 > 0 && price_e6 % config.tick_size_e6 != 0 {
+        return Err(TradingError::InvalidTickSize.into());
+    }
+    Ok(())
+}
+
+/// 验证数量符合lot size
+pub fn validate_lot_size(size_e6: i64, config: &MarketConfig) -> Result<(), ProgramError> {
+    if config.lot_size_e6 > 0 && size_e6 % config.lot_size_e6 != 0 {
+        return Err(TradingError::InvalidLotSize.into());
+    }
+    Ok(())
+}
+
+/// 验证风控参数
+pub fn validate_risk_params(max_leverage: u32, risk_params: &RiskParams) -> Result<(), ProgramError> {
+    validate_leverage(max_leverage)?;
+    
+    // 维持保证金率必须低于初始保证金率（1/max_leverage）
+    if risk_params.maintenance_margin_ratio_bp == 0
+        || risk_params.maintenance_margin_ratio_bp as u64 * max_leverage as u64 >= 10000
+    {
+        return Err(TradingError::InvalidMarketConfig.into());
+    }
+    
+    if risk_params.liquidation_fee_bp > 1000 {
+        return Err(TradingError::InvalidMarketConfig.into());
+    }
+    
+    // 阈值必须满足 warning >= smart_hedge >= liquidation > 0
+    if risk_params.liquidation_threshold_bp == 0
+        || risk_params.smart_hedge_threshold_bp < risk_params.liquidation_threshold_bp
+        || risk_params.warning_threshold_bp < risk_params.smart_hedge_threshold_bp
+    {
+        return Err(TradingError::InvalidMarketConfig.into());
+    }
+    Ok(())
+}
+
+/// 验证市场配置参数
+pub fn validate_market_params(
+    max_leverage: u32,
+    risk_params: &RiskParams,
+    tick_size_e6: i64,
+    lot_size_e6: i64,
+) -> Result<(), ProgramError> {
+    validate_risk_params(max_leverage, risk_params)?;
+    
+    if tick_size_e6 <= 0 || lot_size_e6 <= 0 {
+        return Err(TradingError::InvalidMarketConfig.into());
+    }
+    Ok(())
+}
+
+/// 验证风险档位
+/// 
+/// 名义价值上限严格递增；杠杆上限不增且不超过市场上限；维持保证金率不减且低于初始保证金率
+pub fn validate_risk_brackets(brackets: &[RiskBracket], market_max_leverage: u32) -> Result<(), ProgramError> {
+    if brackets.len() > MarketConfig::MAX_RISK_BRACKETS {
+        return Err(TradingError::InvalidRiskBracket.into());
+    }
+    
+    let mut previous: Option<&RiskBracket> = None;
+    
+    for bracket in brackets {
+        if bracket.max_notional_e6 <= 0
+            || bracket.max_leverage == 0
+            || bracket.max_leverage > market_max_leverage
+            || bracket.maintenance_margin_ratio_bp == 0
+            || bracket.maintenance_margin_ratio_bp as u64 * bracket.max_leverage as u64 >= 10000
+        {
+            return Err(TradingError::InvalidRiskBracket.into());
+        }
+        
+        if let Some(prev) = previous {
+            if bracket.max_notional_e6 <= prev.max_notional_e6
+                || bracket.max_leverage > prev.max_leverage
+                || bracket.maintenance_margin_ratio_bp < prev.maintenance_margin_ratio_bp
+            {
+                return Err(TradingError::InvalidRiskBracket.into());
+            }
+        }
+        
+        previous = Some(bracket);
+    }
+    Ok(())
+}
+
+/// 验证预言机参数
+pub fn validate_oracle_params(
+    max_price_deviation_bp: u32,
+    max_oracle_staleness_secs: u32,
+    max_oracle_confidence_bp: u32,
+) -> Result<(), ProgramError> {
+    if max_price_deviation_bp == 0
+        || max_price_deviation_bp > 10000
+        || max_oracle_staleness_secs == 0
+        || max_oracle_confidence_bp == 0
+        || max_oracle_confidence_bp > 10000
+    {
+        return Err(TradingError::InvalidMarketConfig.into());
+    }
+    Ok(())
+}
+
+/// 验证部分强平目标保证金率（0 = 关闭；否则必须高于强平阈值，上限1000%）
+pub fn validate_partial_liquidation_target(
+    target_margin_ratio_bp: u32,
+    risk_params: &RiskParams,
+) -> Result<(), ProgramError> {
+    if target_margin_ratio_bp != 0
+        && (target_margin_ratio_bp <= risk_params.liquidation_threshold_bp || target_margin_ratio_bp > 100_000)
+    {
+        return Err(TradingError::InvalidMarketConfig.into());
+    }
+    Ok(())
+}
+
+/// 验证交易手续费率（上限10%，maker不高于taker）
+pub fn validate_fee_rates(maker_fee_bp: u32, taker_fee_bp: u32) -> Result<(), ProgramError> {
+    if taker_fee_bp > 1000 || maker_fee_bp > taker_fee_bp {
+        return Err(TradingError::InvalidFeeRate.into());
+    }
+    Ok(())
+}
+
+/// 验证条件单Keeper奖励费率（上限1%）
+pub fn validate_keeper_fee(keeper_fee_bp: u32) -> Result<(), ProgramError> {
+    if keeper_fee_bp > 100 {
+        return Err(TradingError::InvalidFeeRate.into());
+    }
+    Ok(())
+}
+
+/// 验证交易模式（全局和市场模式取较严格者）
+/// 
+/// 增加风险的操作只允许在Active下执行；降低风险的操作（平仓、强平、追加保证金）
+/// 在ReduceOnly下也允许；Halted时全部拒绝
+pub fn validate_trading_mode(
+    global_mode: TradingMode,
+    market_mode: TradingMode,
+    increases_risk: bool,
+) -> Result<(), ProgramError> {
+    match global_mode.max(market_mode) {
+        TradingMode::Halted => Err(TradingError::TradingHalted.into()),
+        TradingMode::ReduceOnly if increases_risk => Err(TradingError::TradingReduceOnly.into()),
+        _ => Ok(()),
+    }
+}
+
+/// 验证资金费参数
+pub fn validate_funding_params(funding_interval_secs: u32, max_funding_rate_e9: i64) -> Result<(), ProgramError> {
+    // 单周期上限不超过10%
+    if funding_interval_secs == 0 || max_funding_rate_e9 <= 0 || max_funding_rate_e9 > 100_000_000 {
+        return Err(TradingError::InvalidFundingRate.into());
+    }
+    Ok(())
+}
+
 /// 验证账户ID长度
 pub fn validate_account_id(account_id: &str) -> Result<(), ProgramError> {
     if account_id.is_empty() || account_id.len() > 64 {
@@ -45,6 +214,18 @@ pub fn validate_account_id(account_id: &str) -> Result<(), ProgramError> {
     Ok(())
 }
 
+/// 计算名义价值
+/// notional = size × price
+pub fn calculate_notional(size_e6: i64, price_e6: i64) -> Result<i64, ProgramError> {
+    // 防止溢出，使用i128
+    let notional = (size_e6 as i128)
+        .checked_mul(price_e6 as i128)
+        .ok_or(TradingError::ArithmeticOverflow)?
+        / 1_000_000;
+    
+    i64::try_from(notional).map_err(|_| TradingError::ArithmeticOverflow.into())
+}
+
 /// 计算初始保证金（IM）
 /// IM = notional / leverage
 /// notional = size × entry_price
@@ -53,11 +234,7 @@ pub fn calculate_initial_margin(
     entry_price_e6: i64,
     leverage: u32,
 ) -> Result<i64, ProgramError> {
-    // 防止溢出，使用i128
-    let notional = (size_e6 as i128)
-        .checked_mul(entry_price_e6 as i128)
-        .ok_or(TradingError::ArithmeticOverflow)?
-        / 1_000_000;
+    let notional = calculate_notional(size_e6, entry_price_e6)? as i128;
     
     let im = notional
         .checked_div(leverage as i128)
@@ -67,9 +244,15 @@ pub fn calculate_initial_margin(
 }
 
 /// 计算维持保证金（MM）
-/// MM = IM / 2
-pub fn calculate_maintenance_margin(im_e6: i64) -> i64 {
-    im_e6 / 2
+/// MM = notional × mm_ratio_bp / 10000
+pub fn calculate_maintenance_margin(
+    size_e6: i64,
+    price_e6: i64,
+    mm_ratio_bp: u32,
+) -> Result<i64, ProgramError> {
+    let notional = calculate_notional(size_e6, price_e6)? as i128;
+    
+    Ok((notional * mm_ratio_bp as i128 / 10000) as i64)
 }
 
 /// 计算已实现盈亏
@@ -99,10 +282,164 @@ pub fn calculate_realized_pnl(
 // === Smart Hedge费用计算已移除 ===
 // Smart Hedge应该在1024-core/smart-hedge-engine中实现
 
-/// 计算清算手续费（0.5%）
-pub fn calculate_liquidation_fee(locked_usdc_e6: i64) -> i64 {
-    // 0.5% = 0.005
-    (locked_usdc_e6 as i128 * 5 / 1000) as i64
+/// 按基点计算费用（10000 bp = 100%）
+pub fn calculate_fee_bp(amount_e6: i64, fee_bp: u32) -> i64 {
+    (amount_e6 as i128 * fee_bp as i128 / 10000) as i64
+}
+
+/// 穿仓亏损分摊：先由Insurance Fund承担，不足部分记为坏账（社会化损失）
+/// 返回 (insurance_covered, bad_debt)
+pub fn split_shortfall(shortfall_e6: i64, insurance_fund_e6: i64) -> (i64, i64) {
+    if shortfall_e6 <= 0 {
+        return (0, 0);
+    }
+    
+    let insurance_covered = shortfall_e6.min(insurance_fund_e6.max(0));
+    (insurance_covered, shortfall_e6 - insurance_covered)
+}
+
+/// ADL排序分数 = 盈利率（基点）× 杠杆，分数越高越先被减仓
+pub fn calculate_adl_score(unrealized_pnl_e6: i64, locked_usdc_e6: i64, leverage: u32) -> i128 {
+    if unrealized_pnl_e6 <= 0 || locked_usdc_e6 <= 0 {
+        return 0;
+    }
+    
+    unrealized_pnl_e6 as i128 * 10000 / locked_usdc_e6 as i128 * leverage as i128
+}
+
+/// 计算ADL减仓数量：按破产价格平仓，每单位让渡 |标记价 − 破产价| 的盈利，
+/// 减到刚好覆盖剩余坏账为止（按lot size向上取整，不超过持仓数量）
+pub fn calculate_adl_size(
+    size_e6: i64,
+    haircut_per_unit_e6: i64,
+    bad_debt_e6: i64,
+    lot_size_e6: i64,
+) -> i64 {
+    let size = size_e6.abs();
+    
+    if haircut_per_unit_e6 <= 0 || bad_debt_e6 <= 0 {
+        return 0;
+    }
+    
+    // haircut = haircut_per_unit × q / 1e6 >= bad_debt
+    let haircut_per_unit = haircut_per_unit_e6 as i128;
+    let mut adl_size = (bad_debt_e6 as i128 * 1_000_000 + haircut_per_unit - 1) / haircut_per_unit;
+    
+    if lot_size_e6 > 0 {
+        let lot = lot_size_e6 as i128;
+        adl_size = (adl_size + lot - 1) / lot * lot;
+    }
+    
+    adl_size.min(size as i128) as i64
+}
+
+/// 计算穿仓持仓的破产价格（权益恰好为0的价格）
+/// 
+/// 按price成交时亏损超出保证金shortfall：多头破产价 = price + shortfall / size，空头破产价 = price − shortfall / size
+pub fn calculate_bankruptcy_price(is_long: bool, price_e6: i64, shortfall_e6: i64, size_e6: i64) -> i64 {
+    let size = size_e6.abs() as i128;
+    
+    if size == 0 {
+        return price_e6;
+    }
+    
+    let offset = shortfall_e6.max(0) as i128 * 1_000_000 / size;
+    let bankruptcy_price = if is_long {
+        price_e6 as i128 + offset
+    } else {
+        price_e6 as i128 - offset
+    };
+    
+    bankruptcy_price.clamp(0, i64::MAX as i128) as i64
+}
+
+/// 逐仓持仓最多可减少的保证金
+/// 
+/// 减少后 locked + min(unrealized_pnl, 0) 不低于按标记价格计算的IM，未实现盈利不可提取
+pub fn calculate_max_removable_margin(
+    locked_usdc_e6: i64,
+    unrealized_pnl_e6: i64,
+    required_im_e6: i64,
+) -> i64 {
+    let available = locked_usdc_e6 as i128 + unrealized_pnl_e6.min(0) as i128 - required_im_e6 as i128;
+    available.clamp(0, locked_usdc_e6.max(0) as i128) as i64
+}
+
+/// 计算资金费index增量（USDC e12 / 1.0 size）
+/// delta = mark_price × rate × elapsed / interval
+pub fn calculate_funding_index_delta(
+    mark_price_e6: i64,
+    funding_rate_e9: i64,
+    elapsed_secs: i64,
+    funding_interval_secs: u32,
+) -> Result<i128, ProgramError> {
+    if funding_interval_secs == 0 {
+        return Err(TradingError::DivisionByZero.into());
+    }
+    
+    // price_e6 × rate_e9 / 1e3 = e12
+    let delta = (mark_price_e6 as i128)
+        .checked_mul(funding_rate_e9 as i128)
+        .and_then(|v| v.checked_mul(elapsed_secs.max(0) as i128))
+        .ok_or(TradingError::ArithmeticOverflow)?
+        / (1_000 * funding_interval_secs as i128);
+    
+    Ok(delta)
+}
+
+/// 计算持仓应付资金费（正=支付，负=收到）
+/// 多头支付index增量，空头收取
+pub fn calculate_funding_payment(
+    is_long: bool,
+    size_e6: i64,
+    index_delta: i128,
+) -> Result<i64, ProgramError> {
+    let payment = (size_e6 as i128)
+        .checked_mul(index_delta)
+        .ok_or(TradingError::ArithmeticOverflow)?
+        / 1_000_000_000_000;
+    
+    let payment = i64::try_from(payment).map_err(|_| TradingError::ArithmeticOverflow)?;
+    
+    Ok(if is_long { payment } else { -payment })
+}
+
+/// 计算部分强平数量：以清算价格平掉最少的仓位，使剩余持仓保证金率回到target以上
+/// 
+/// 平仓q后：equity' = equity − fee_bp × locked × q / S，MM' = MM × (S − q) / S
+/// 求 equity' × 10000 >= target × MM'，按lot size向上取整；无法恢复时返回全部数量
+pub fn calculate_partial_liquidation_size(
+    size_e6: i64,
+    locked_usdc_e6: i64,
+    mm_e6: i64,
+    unrealized_pnl_e6: i64,
+    liquidation_fee_bp: u32,
+    target_margin_ratio_bp: u32,
+    lot_size_e6: i64,
+) -> i64 {
+    let size = size_e6.abs() as i128;
+    let equity = locked_usdc_e6 as i128 + unrealized_pnl_e6 as i128;
+    
+    if target_margin_ratio_bp == 0 || equity <= 0 || mm_e6 <= 0 || size == 0 {
+        return size_e6.abs();
+    }
+    
+    let target_mm = target_margin_ratio_bp as i128 * mm_e6 as i128;
+    let numerator = (target_mm - equity * 10000) * size;
+    let denominator = target_mm - liquidation_fee_bp as i128 * locked_usdc_e6 as i128;
+    
+    if denominator <= 0 {
+        return size_e6.abs();
+    }
+    
+    let mut liquidation_size = (numerator.max(1) + denominator - 1) / denominator;
+    
+    if lot_size_e6 > 0 {
+        let lot = lot_size_e6 as i128;
+        liquidation_size = (liquidation_size + lot - 1) / lot * lot;
+    }
+    
+    liquidation_size.min(size) as i64
 }
 
 /// 安全的i64加法
@@ -115,11 +452,6 @@ pub fn safe_sub_i64(a: i64, b: i64) -> Result<i64, ProgramError> {
     a.checked_sub(b).ok_or(TradingError::ArithmeticUnderflow.into())
 }
 
-/// 验证保证金率是否可强平（<100%）
-pub fn is_liquidatable(margin_ratio_bp: u32) -> bool {
-    margin_ratio_bp < 10000 // 100% = 10000 bp
-}
-
 // === Smart Hedge触发判断已移除 ===
 // Smart Hedge应该在1024-core/smart-hedge-engine中实现
 
@@ -151,9 +483,217 @@ mod tests {
     }
     
     #[test]
-    fn test_calculate_liquidation_fee() {
-        let fee = calculate_liquidation_fee(5_000_000);
-        assert_eq!(fee, 25_000); // $0.025 (e6)
+    fn test_calculate_fee_bp() {
+        // 50bp = 0.5%，与默认清算手续费一致
+        assert_eq!(calculate_fee_bp(5_000_000, 50), 25_000); // $0.025 (e6)
+        assert_eq!(calculate_fee_bp(10_000_000, 100), 100_000); // 1%
+    }
+    
+    #[test]
+    fn test_validate_market_params() {
+        let params = |mm_bp: u32| RiskParams {
+            maintenance_margin_ratio_bp: mm_bp,
+            ..RiskParams::DEFAULT
+        };
+        
+        assert!(validate_market_params(100, &params(50), 100_000, 1_000).is_ok());
+        // MM率 >= IM率（1/100 = 100bp）
+        assert!(validate_market_params(100, &params(100), 100_000, 1_000).is_err());
+        assert!(validate_market_params(20, &params(0), 100_000, 1_000).is_err());
+        assert!(validate_market_params(20, &params(250), 0, 1_000).is_err());
+        assert!(validate_market_params(0, &params(50), 100_000, 1_000).is_err());
+        
+        // 清算费上限10%
+        let high_fee = RiskParams { liquidation_fee_bp: 1001, ..RiskParams::DEFAULT };
+        assert!(validate_risk_params(20, &high_fee).is_err());
+        
+        // 阈值顺序：warning >= smart_hedge >= liquidation > 0
+        let inverted = RiskParams { smart_hedge_threshold_bp: 16000, ..RiskParams::DEFAULT };
+        assert!(validate_risk_params(20, &inverted).is_err());
+        let zero = RiskParams {
+            warning_threshold_bp: 0,
+            smart_hedge_threshold_bp: 0,
+            liquidation_threshold_bp: 0,
+            ..RiskParams::DEFAULT
+        };
+        assert!(validate_risk_params(20, &zero).is_err());
+    }
+    
+    #[test]
+    fn test_validate_risk_brackets() {
+        let bracket = |max_notional_e6: i64, max_leverage: u32, mm_bp: u32| RiskBracket {
+            max_notional_e6,
+            max_leverage,
+            maintenance_margin_ratio_bp: mm_bp,
+        };
+        
+        let brackets = [
+            bracket(50_000_000_000, 100, 50),
+            bracket(250_000_000_000, 50, 100),
+            bracket(1_000_000_000_000, 20, 250),
+        ];
+        assert!(validate_risk_brackets(&brackets, 100).is_ok());
+        assert!(validate_risk_brackets(&[], 100).is_ok());
+        
+        // 档位杠杆超过市场上限
+        assert!(validate_risk_brackets(&brackets, 50).is_err());
+        // 名义价值未递增
+        assert!(validate_risk_brackets(&[brackets[1], brackets[0]], 100).is_err());
+        // 更高档位杠杆反而更大
+        assert!(validate_risk_brackets(&[bracket(50_000_000_000, 20, 50), bracket(250_000_000_000, 50, 100)], 100).is_err());
+        // 更高档位维持保证金率更低
+        assert!(validate_risk_brackets(&[bracket(50_000_000_000, 50, 100), bracket(250_000_000_000, 20, 50)], 100).is_err());
+        // MM率 >= IM率
+        assert!(validate_risk_brackets(&[bracket(50_000_000_000, 100, 100)], 100).is_err());
+        // 档位数超过上限
+        let too_many = [bracket(1, 1, 1); MarketConfig::MAX_RISK_BRACKETS + 1];
+        assert!(validate_risk_brackets(&too_many, 100).is_err());
+    }
+    
+    #[test]
+    fn test_partial_liquidation_size() {
+        // 1 BTC @ $100,000，IM $5,000，MM $500，价格跌至$95,400：equity $400 → 8000bp
+        let size = calculate_partial_liquidation_size(
+            1_000_000,
+            5_000_000_000,
+            500_000_000,
+            -4_600_000_000,
+            50,
+            15000,
+            1_000,
+        );
+        
+        // 平掉0.483 BTC：equity = $400 − $12.075，MM = $258.5 → 15006bp
+        assert_eq!(size, 483_000);
+        
+        // 未开启部分强平 / 已穿仓：全部平仓
+        assert_eq!(
+            calculate_partial_liquidation_size(1_000_000, 5_000_000_000, 500_000_000, -4_600_000_000, 50, 0, 1_000),
+            1_000_000
+        );
+        assert_eq!(
+            calculate_partial_liquidation_size(1_000_000, 5_000_000_000, 500_000_000, -5_100_000_000, 50, 15000, 1_000),
+            1_000_000
+        );
+        
+        // 参数校验
+        assert!(validate_partial_liquidation_target(0, &RiskParams::DEFAULT).is_ok());
+        assert!(validate_partial_liquidation_target(15000, &RiskParams::DEFAULT).is_ok());
+        assert!(validate_partial_liquidation_target(10000, &RiskParams::DEFAULT).is_err());
+    }
+    
+    #[test]
+    fn test_adl_ranking_and_size() {
+        // 盈利$1,000 / 保证金$5,000 = 2000bp × 20x
+        assert_eq!(calculate_adl_score(1_000_000_000, 5_000_000_000, 20), 40000);
+        // 同样盈利率，杠杆更高排在前面
+        assert!(calculate_adl_score(1_000_000_000, 5_000_000_000, 50) > calculate_adl_score(1_000_000_000, 5_000_000_000, 20));
+        // 亏损持仓不参与ADL
+        assert_eq!(calculate_adl_score(-1, 5_000_000_000, 20), 0);
+        
+        // 坏账$300，每BTC让渡$1,000 → 0.3 BTC
+        assert_eq!(calculate_adl_size(1_000_000, 1_000_000_000, 300_000_000, 1_000), 300_000);
+        // 持仓不足时全部减仓
+        assert_eq!(calculate_adl_size(100_000, 1_000_000_000, 300_000_000, 1_000), 100_000);
+        // 向上取整到lot size
+        assert_eq!(calculate_adl_size(1_000_000, 1_000_000_000, 300_000_001, 1_000), 301_000);
+        assert_eq!(calculate_adl_size(1_000_000, 1_000_000_000, 0, 1_000), 0);
+    }
+    
+    #[test]
+    fn test_calculate_bankruptcy_price() {
+        // 多头0.5 BTC在$95,000平仓，亏损超出保证金$300 → 破产价$95,600
+        assert_eq!(calculate_bankruptcy_price(true, 95_000_000_000, 300_000_000, 500_000), 95_600_000_000);
+        // 空头对称
+        assert_eq!(calculate_bankruptcy_price(false, 105_000_000_000, 300_000_000, -500_000), 104_400_000_000);
+        // 没有穿仓时即成交价
+        assert_eq!(calculate_bankruptcy_price(true, 95_000_000_000, 0, 500_000), 95_000_000_000);
+    }
+    
+    #[test]
+    fn test_max_removable_margin() {
+        // 保证金$6,000，按标记价格IM = $5,000
+        assert_eq!(calculate_max_removable_margin(6_000_000_000, 0, 5_000_000_000), 1_000_000_000);
+        // 未实现亏损$400从可提取部分中扣除
+        assert_eq!(calculate_max_removable_margin(6_000_000_000, -400_000_000, 5_000_000_000), 600_000_000);
+        // 未实现盈利不可提取
+        assert_eq!(calculate_max_removable_margin(6_000_000_000, 3_000_000_000, 5_000_000_000), 1_000_000_000);
+        // 已低于IM
+        assert_eq!(calculate_max_removable_margin(4_000_000_000, 0, 5_000_000_000), 0);
+    }
+    
+    #[test]
+    fn test_validate_fee_rates() {
+        assert!(validate_fee_rates(0, 0).is_ok());
+        assert!(validate_fee_rates(2, 5).is_ok());
+        assert!(validate_fee_rates(1000, 1000).is_ok());
+        // maker高于taker
+        assert!(validate_fee_rates(6, 5).is_err());
+        // 超过10%
+        assert!(validate_fee_rates(0, 1001).is_err());
+    }
+    
+    #[test]
+    fn test_validate_trading_mode() {
+        use TradingMode::*;
+        
+        assert!(validate_trading_mode(Active, Active, true).is_ok());
+        
+        // ReduceOnly：只允许降低风险的操作，全局和市场任一生效
+        assert!(validate_trading_mode(ReduceOnly, Active, false).is_ok());
+        assert_eq!(
+            validate_trading_mode(Active, ReduceOnly, true),
+            Err(TradingError::TradingReduceOnly.into())
+        );
+        
+        // Halted优先于ReduceOnly
+        assert_eq!(
+            validate_trading_mode(ReduceOnly, Halted, false),
+            Err(TradingError::TradingHalted.into())
+        );
+    }
+    
+    #[test]
+    fn test_funding_payment() {
+        // $100,000，费率0.01%，满一个周期 → 每1.0单位$10
+        let delta = calculate_funding_index_delta(100_000_000_000, 100_000, 28_800, 28_800).unwrap();
+        assert_eq!(delta, 10_000_000_000_000);
+        
+        // 多头1.0支付$10，空头收取$10
+        assert_eq!(calculate_funding_payment(true, 1_000_000, delta).unwrap(), 10_000_000);
+        assert_eq!(calculate_funding_payment(false, 1_000_000, delta).unwrap(), -10_000_000);
+        
+        // 半个周期按时间折算
+        let half = calculate_funding_index_delta(100_000_000_000, 100_000, 14_400, 28_800).unwrap();
+        assert_eq!(calculate_funding_payment(true, 1_000_000, half).unwrap(), 5_000_000);
+        
+        // 负费率：空头支付
+        let negative = calculate_funding_index_delta(100_000_000_000, -100_000, 28_800, 28_800).unwrap();
+        assert_eq!(calculate_funding_payment(false, 2_000_000, negative).unwrap(), 20_000_000);
+    }
+    
+    #[test]
+    fn test_calculate_maintenance_margin() {
+        // 1 BTC @ $100,000, MM率 0.5% → $500
+        let mm = calculate_maintenance_margin(1_000_000, 100_000_000_000, 50).unwrap();
+        assert_eq!(mm, 500_000_000);
+        
+        // MM率 2.5%（20x下等于IM/2）
+        let mm = calculate_maintenance_margin(1_000_000, 100_000_000_000, 250).unwrap();
+        let im = calculate_initial_margin(1_000_000, 100_000_000_000, 20).unwrap();
+        assert_eq!(mm, im / 2);
+    }
+    
+    #[test]
+    fn test_split_shortfall() {
+        // Insurance Fund充足
+        assert_eq!(split_shortfall(2_000_000, 10_000_000), (2_000_000, 0));
+        // Insurance Fund不足，剩余记为坏账
+        assert_eq!(split_shortfall(5_000_000, 3_000_000), (3_000_000, 2_000_000));
+        // Insurance Fund为0
+        assert_eq!(split_shortfall(5_000_000, 0), (0, 5_000_000));
+        // 无穿仓
+        assert_eq!(split_shortfall(0, 3_000_000), (0, 0));
     }
 }
 
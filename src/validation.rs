@@ -0,0 +1,515 @@
+//! Account Validation
+//!
+//! 所有指令共用的账户校验层：PDA派生、账户所属Program、
+//! Token Program / System Program、USDC Token账户的mint和owner
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    account_info::AccountInfo,
+    msg,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use crate::{
+    error::TradingError,
+    state::{
+        ConditionalOrder, CrossMarginAccount, FundingState, MarketConfig, Side, TradingVault, UserPosition,
+    },
+};
+
+/// Trading Vault PDA Seed
+pub const VAULT_SEED: &[u8] = b"trading_vault";
+
+/// Vault USDC托管账户PDA Seed（由InitializeVault创建时使用）
+pub const VAULT_USDC_SEED: &[u8] = b"vault_usdc";
+
+/// User Position PDA Seed
+pub const POSITION_SEED: &[u8] = b"position";
+
+/// Market Config PDA Seed
+pub const MARKET_SEED: &[u8] = b"market";
+
+/// Funding State PDA Seed
+pub const FUNDING_SEED: &[u8] = b"funding";
+
+/// Cross Margin Account PDA Seed
+pub const CROSS_MARGIN_SEED: &[u8] = b"cross_margin";
+
+/// Conditional Order PDA Seed
+pub const CONDITIONAL_ORDER_SEED: &[u8] = b"conditional_order";
+
+/// 派生Trading Vault PDA
+pub fn find_vault_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED], program_id)
+}
+
+/// 派生Vault USDC托管账户PDA
+pub fn find_vault_usdc_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_USDC_SEED], program_id)
+}
+
+/// 派生User Position PDA
+pub fn find_position_pda(
+    program_id: &Pubkey,
+    wallet: &Pubkey,
+    account_id: &str,
+    market: &str,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            POSITION_SEED,
+            wallet.as_ref(),
+            account_id.as_bytes(),
+            market.as_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// 派生双向持仓（Hedge Mode）User Position PDA
+/// 
+/// Seeds额外包含Side，同一市场可同时持有多空两个持仓
+pub fn find_hedge_position_pda(
+    program_id: &Pubkey,
+    wallet: &Pubkey,
+    account_id: &str,
+    market: &str,
+    side: Side,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            POSITION_SEED,
+            wallet.as_ref(),
+            account_id.as_bytes(),
+            market.as_bytes(),
+            &[side as u8],
+        ],
+        program_id,
+    )
+}
+
+/// 派生Market Config PDA
+pub fn find_market_pda(program_id: &Pubkey, market: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MARKET_SEED, market.as_bytes()], program_id)
+}
+
+/// 派生Funding State PDA
+pub fn find_funding_pda(program_id: &Pubkey, market: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FUNDING_SEED, market.as_bytes()], program_id)
+}
+
+/// 派生Cross Margin Account PDA
+pub fn find_cross_margin_pda(program_id: &Pubkey, wallet: &Pubkey, account_id: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[CROSS_MARGIN_SEED, wallet.as_ref(), account_id.as_bytes()],
+        program_id,
+    )
+}
+
+/// 派生Conditional Order PDA
+pub fn find_conditional_order_pda(program_id: &Pubkey, position: &Pubkey, order_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[CONDITIONAL_ORDER_SEED, position.as_ref(), &order_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// 验证签名
+pub fn validate_signer(account: &AccountInfo) -> Result<(), ProgramError> {
+    if !account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+/// 验证Token Program = spl-token
+pub fn validate_token_program(account: &AccountInfo) -> Result<(), ProgramError> {
+    if account.key != &spl_token::id() {
+        msg!("Error: Invalid token program {}", account.key);
+        return Err(TradingError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+/// 验证System Program
+pub fn validate_system_program(account: &AccountInfo) -> Result<(), ProgramError> {
+    if account.key != &system_program::id() {
+        msg!("Error: Invalid system program {}", account.key);
+        return Err(TradingError::InvalidSystemProgram.into());
+    }
+    Ok(())
+}
+
+/// 验证Trading Vault PDA并读取数据
+///
+/// 返回 (vault, bump)，bump用于PDA签名
+pub fn load_trading_vault(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+) -> Result<(TradingVault, u8), ProgramError> {
+    let (vault_pda, bump) = find_vault_pda(program_id);
+    
+    if account.key != &vault_pda || account.owner != program_id {
+        msg!("Error: Vault PDA mismatch");
+        return Err(TradingError::InvalidVaultAccount.into());
+    }
+    
+    // 旧版本Vault（扩容前）补0读取：paused = false，guardian/pending_authority/金库账户未设置
+    let data = account.data.borrow();
+    let vault = if data.len() < TradingVault::SIZE {
+        let mut padded = data.to_vec();
+        padded.resize(TradingVault::SIZE, 0);
+        TradingVault::deserialize(&mut &padded[..])
+    } else {
+        TradingVault::deserialize(&mut &data[..])
+    }
+    .map_err(|_| TradingError::DeserializationError)?;
+    
+    if vault.discriminator != TradingVault::DISCRIMINATOR {
+        return Err(TradingError::InvalidVaultAccount.into());
+    }
+    
+    Ok((vault, bump))
+}
+
+/// 将Trading Vault写回
+/// 
+/// 未扩容的旧Vault只写入已有长度，新增字段必须仍为0，否则返回VaultResizeRequired
+/// （Guardian等超出272字节的字段需先经SetGuardian扩容）
+pub fn store_trading_vault(account: &AccountInfo, vault: &TradingVault) -> Result<(), ProgramError> {
+    let serialized = vault.try_to_vec()
+        .map_err(|_| TradingError::SerializationError)?;
+    
+    let mut data = account.data.borrow_mut();
+    let len = serialized.len().min(data.len());
+    
+    if serialized[len..].iter().any(|b| *b != 0) {
+        msg!("Error: Trading Vault must be resized by SetGuardian first");
+        return Err(TradingError::VaultResizeRequired.into());
+    }
+    
+    data[..len].copy_from_slice(&serialized[..len]);
+    Ok(())
+}
+
+/// 验证签名者 = TradingVault.authority（管理员）
+pub fn validate_admin(vault: &TradingVault, admin: &AccountInfo) -> Result<(), ProgramError> {
+    validate_signer(admin)?;
+    
+    if vault.authority != *admin.key {
+        msg!("Error: Signer is not vault authority");
+        return Err(TradingError::InvalidAuthority.into());
+    }
+    Ok(())
+}
+
+/// 验证程序未被紧急暂停（所有资金相关指令）
+pub fn validate_not_paused(vault: &TradingVault) -> Result<(), ProgramError> {
+    if vault.paused {
+        msg!("Error: Program is paused");
+        return Err(TradingError::ProgramPaused.into());
+    }
+    Ok(())
+}
+
+/// 验证Market Config PDA并读取数据
+pub fn load_market_config(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    market: &str,
+) -> Result<MarketConfig, ProgramError> {
+    let (expected_pda, _) = find_market_pda(program_id, market);
+    
+    if account.key != &expected_pda || account.owner != program_id {
+        msg!("Error: Market config PDA mismatch for {}", market);
+        return Err(TradingError::InvalidMarketConfigAccount.into());
+    }
+    
+    let config = MarketConfig::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| TradingError::DeserializationError)?;
+    
+    if config.discriminator != MarketConfig::DISCRIMINATOR || config.market != market {
+        return Err(TradingError::InvalidMarketConfigAccount.into());
+    }
+    
+    Ok(config)
+}
+
+/// 验证Funding State PDA并读取数据
+pub fn load_funding_state(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    market: &str,
+) -> Result<FundingState, ProgramError> {
+    let (expected_pda, _) = find_funding_pda(program_id, market);
+    
+    if account.key != &expected_pda || account.owner != program_id {
+        msg!("Error: Funding state PDA mismatch for {}", market);
+        return Err(TradingError::InvalidFundingAccount.into());
+    }
+    
+    let state = FundingState::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| TradingError::DeserializationError)?;
+    
+    if state.discriminator != FundingState::DISCRIMINATOR || state.market != market {
+        return Err(TradingError::InvalidFundingAccount.into());
+    }
+    
+    Ok(state)
+}
+
+/// 验证Cross Margin Account PDA并读取数据
+pub fn load_cross_margin_account(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    wallet: &Pubkey,
+    account_id: &str,
+) -> Result<CrossMarginAccount, ProgramError> {
+    let (expected_pda, _) = find_cross_margin_pda(program_id, wallet, account_id);
+    
+    if account.key != &expected_pda || account.owner != program_id {
+        msg!("Error: Cross margin account PDA mismatch");
+        return Err(TradingError::InvalidCrossMarginAccount.into());
+    }
+    
+    let cross_account = CrossMarginAccount::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| TradingError::DeserializationError)?;
+    
+    if cross_account.discriminator != CrossMarginAccount::DISCRIMINATOR || cross_account.wallet != *wallet {
+        return Err(TradingError::InvalidCrossMarginAccount.into());
+    }
+    
+    Ok(cross_account)
+}
+
+/// 读取Cross Margin Account，尚未创建时返回None
+/// 
+/// 未创建时同样校验PDA，避免传入其他账户绕过账户抵押品结算
+pub fn load_optional_cross_margin_account(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    wallet: &Pubkey,
+    account_id: &str,
+) -> Result<Option<CrossMarginAccount>, ProgramError> {
+    if account.owner == program_id {
+        return load_cross_margin_account(program_id, account, wallet, account_id).map(Some);
+    }
+    
+    let (expected_pda, _) = find_cross_margin_pda(program_id, wallet, account_id);
+    
+    if account.key != &expected_pda {
+        msg!("Error: Cross margin account PDA mismatch");
+        return Err(TradingError::InvalidCrossMarginAccount.into());
+    }
+    
+    Ok(None)
+}
+
+/// 验证Conditional Order PDA并读取数据（PDA按存储的持仓和编号派生）
+pub fn load_conditional_order(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+) -> Result<ConditionalOrder, ProgramError> {
+    if account.owner != program_id {
+        msg!("Error: Conditional order account not owned by program");
+        return Err(TradingError::InvalidConditionalOrder.into());
+    }
+    
+    let order = ConditionalOrder::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| TradingError::DeserializationError)?;
+    
+    if order.discriminator != ConditionalOrder::DISCRIMINATOR {
+        return Err(TradingError::InvalidConditionalOrder.into());
+    }
+    
+    let (expected_pda, _) = find_conditional_order_pda(program_id, &order.position, order.order_id);
+    
+    if account.key != &expected_pda {
+        msg!("Error: Conditional order PDA mismatch");
+        return Err(TradingError::InvalidConditionalOrder.into());
+    }
+    
+    Ok(order)
+}
+
+/// 验证User Position PDA并读取数据
+pub fn load_user_position(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    wallet: &Pubkey,
+    account_id: &str,
+    market: &str,
+) -> Result<UserPosition, ProgramError> {
+    if account.owner != program_id {
+        msg!("Error: Position PDA mismatch");
+        return Err(TradingError::InvalidPositionAccount.into());
+    }
+    
+    let position = UserPosition::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| TradingError::DeserializationError)?;
+    
+    if position.discriminator != UserPosition::DISCRIMINATOR || position.wallet != *wallet {
+        return Err(TradingError::InvalidPositionAccount.into());
+    }
+    
+    // 双向持仓按持仓方向派生PDA，单向持仓不含Side
+    let (expected_pda, _) = if position.hedge_mode {
+        find_hedge_position_pda(program_id, wallet, account_id, market, position.side)
+    } else {
+        find_position_pda(program_id, wallet, account_id, market)
+    };
+    
+    if account.key != &expected_pda {
+        msg!("Error: Position PDA mismatch");
+        return Err(TradingError::InvalidPositionAccount.into());
+    }
+    
+    Ok(position)
+}
+
+/// 读取任意持仓（钱包、账户ID和市场取自持仓数据，再按PDA校验）
+pub fn load_position_account(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+) -> Result<UserPosition, ProgramError> {
+    if account.owner != program_id {
+        return Err(TradingError::InvalidPositionAccount.into());
+    }
+    
+    let position = UserPosition::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| TradingError::InvalidPositionAccount)?;
+    
+    load_user_position(program_id, account, &position.wallet, &position.account_id, &position.market)
+}
+
+/// 读取指定市场的持仓
+/// 
+/// 用于ADL等由第三方传入其他用户持仓的场景
+pub fn load_market_position(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    market: &str,
+) -> Result<UserPosition, ProgramError> {
+    let position = load_position_account(program_id, account)?;
+    
+    if position.market != market {
+        return Err(TradingError::InvalidPositionAccount.into());
+    }
+    
+    Ok(position)
+}
+
+/// 将账户数据写回
+pub fn store_account<T: BorshSerialize>(
+    account: &AccountInfo,
+    value: &T,
+) -> Result<(), ProgramError> {
+    let serialized = value.try_to_vec()
+        .map_err(|_| TradingError::SerializationError)?;
+    
+    let mut data = account.data.borrow_mut();
+    if serialized.len() > data.len() {
+        return Err(TradingError::SerializationError.into());
+    }
+    
+    data[..serialized.len()].copy_from_slice(&serialized);
+    Ok(())
+}
+
+/// 读取SPL Token账户（必须由spl-token持有且已初始化）
+pub fn unpack_token_account(account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+    if account.owner != &spl_token::id() {
+        msg!("Error: {} is not an SPL token account", account.key);
+        return Err(TradingError::InvalidTokenAccount.into());
+    }
+    
+    TokenAccount::unpack(&account.data.borrow())
+        .map_err(|_| TradingError::InvalidTokenAccount.into())
+}
+
+/// 验证Token账户的owner和mint
+pub fn validate_token_account(
+    account: &AccountInfo,
+    expected_owner: &Pubkey,
+    expected_mint: &Pubkey,
+) -> Result<TokenAccount, ProgramError> {
+    let token_account = unpack_token_account(account)?;
+    
+    if token_account.owner != *expected_owner {
+        msg!("Error: Token account {} not owned by {}", account.key, expected_owner);
+        return Err(TradingError::InvalidTokenAccountOwner.into());
+    }
+    
+    if token_account.mint != *expected_mint {
+        msg!("Error: Token account {} has wrong mint", account.key);
+        return Err(TradingError::InvalidTokenMint.into());
+    }
+    
+    Ok(token_account)
+}
+
+/// 验证Vault USDC托管账户 = TradingVault中记录的账户
+pub fn validate_vault_token_account(
+    account: &AccountInfo,
+    vault: &TradingVault,
+) -> Result<(), ProgramError> {
+    if account.key != &vault.vault_usdc_account {
+        msg!("Error: Vault USDC account mismatch, expected {}", vault.vault_usdc_account);
+        return Err(TradingError::InvalidVaultTokenAccount.into());
+    }
+    
+    Ok(())
+}
+
+/// 验证USDC Mint账户
+pub fn validate_mint_account(account: &AccountInfo) -> Result<(), ProgramError> {
+    if account.owner != &spl_token::id() {
+        msg!("Error: {} is not an SPL token mint", account.key);
+        return Err(TradingError::InvalidTokenMint.into());
+    }
+    
+    Mint::unpack(&account.data.borrow())
+        .map_err(|_| TradingError::InvalidTokenMint)?;
+    
+    Ok(())
+}
+
+/// 验证Insurance Fund账户 = TradingVault.insurance_fund_account
+pub fn validate_insurance_fund_account(
+    account: &AccountInfo,
+    vault: &TradingVault,
+) -> Result<(), ProgramError> {
+    if vault.insurance_fund_account == Pubkey::default() {
+        msg!("Error: Insurance fund account not configured, call SetTreasuryAccounts first");
+        return Err(TradingError::InvalidInsuranceFundAccount.into());
+    }
+    
+    if account.key != &vault.insurance_fund_account {
+        msg!("Error: Invalid insurance fund account {}, expected {}", account.key, vault.insurance_fund_account);
+        return Err(TradingError::InvalidInsuranceFundAccount.into());
+    }
+    
+    Ok(())
+}
+
+/// 验证Fee Treasury账户 = TradingVault.fee_treasury_account
+pub fn validate_fee_treasury_account(
+    account: &AccountInfo,
+    vault: &TradingVault,
+) -> Result<(), ProgramError> {
+    if vault.fee_treasury_account == Pubkey::default() {
+        msg!("Error: Fee treasury account not configured, call SetTreasuryAccounts first");
+        return Err(TradingError::InvalidFeeTreasuryAccount.into());
+    }
+    
+    if account.key != &vault.fee_treasury_account {
+        msg!("Error: Invalid fee treasury account {}, expected {}", account.key, vault.fee_treasury_account);
+        return Err(TradingError::InvalidFeeTreasuryAccount.into());
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Liquidation Loss Waterfall
+//!
+//! 强平资金分配的多级扣款：
+//! 持仓保证金 → 账户可用抵押品 → Insurance Fund → ADL（坏账）
+//!
+//! 持仓保证金按 亏损 → 清算费 → 返还用户 拆分，equity <= 0 时不收清算费；
+//! 超出保证金的穿仓亏损依次由后续各级承担
+
+/// 强平资金分配明细（金额均为e6格式，非负）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LiquidationWaterfall {
+    // === 持仓保证金拆分（合计 = locked_usdc_e6）===
+    pub margin_loss_e6: i64,            // 保证金承担的亏损（并入Insurance Fund）
+    pub liquidator_fee_e6: i64,         // 清算费：清算人部分
+    pub treasury_fee_e6: i64,           // 清算费：Fee Treasury部分
+    pub user_return_e6: i64,            // 返还用户的剩余保证金
+    
+    // === 穿仓亏损分摊（合计 = shortfall_e6）===
+    pub shortfall_e6: i64,              // 超出保证金的亏损
+    pub free_collateral_e6: i64,        // 账户可用抵押品承担
+    pub insurance_fund_e6: i64,         // Insurance Fund承担
+    pub adl_e6: i64,                    // 剩余计入坏账，由ADL覆盖
+}
+
+impl LiquidationWaterfall {
+    /// 清算费合计
+    pub fn liquidation_fee_e6(&self) -> i64 {
+        self.liquidator_fee_e6 + self.treasury_fee_e6
+    }
+    
+    /// 持仓保证金各项合计
+    pub fn margin_total_e6(&self) -> i64 {
+        self.margin_loss_e6 + self.liquidation_fee_e6() + self.user_return_e6
+    }
+}
+
+/// 计算强平资金分配
+///
+/// - `locked_usdc_e6`: 持仓锁定保证金
+/// - `equity_e6`: 按清算价格计算的权益（locked + unrealized_pnl）
+/// - `liquidation_fee_e6`: 按风控参数计算的清算费（50%清算人，50% Fee Treasury）
+/// - `free_collateral_e6`: 账户可用于弥补穿仓的抵押品
+/// - `insurance_fund_e6`: 当前Insurance Fund余额（保证金承担的亏损先并入后再分摊穿仓）
+pub fn calculate_liquidation_waterfall(
+    locked_usdc_e6: i64,
+    equity_e6: i64,
+    liquidation_fee_e6: i64,
+    free_collateral_e6: i64,
+    insurance_fund_e6: i64,
+) -> LiquidationWaterfall {
+    let locked = locked_usdc_e6.max(0);
+    let equity = equity_e6.max(0).min(locked);
+    
+    // 1. 持仓保证金：清算费只从正权益中收取
+    let fee = liquidation_fee_e6.max(0).min(equity);
+    let liquidator_fee_e6 = fee / 2;
+    let treasury_fee_e6 = fee - liquidator_fee_e6;
+    let user_return_e6 = equity - fee;
+    let margin_loss_e6 = locked - equity;
+    
+    // 2. 穿仓：可用抵押品 → Insurance Fund → ADL
+    let shortfall_e6 = (-(equity_e6 as i128)).max(0).min(i64::MAX as i128) as i64;
+    let free_collateral_e6 = shortfall_e6.min(free_collateral_e6.max(0));
+    let remaining = shortfall_e6 - free_collateral_e6;
+    let insurance_available = insurance_fund_e6.max(0).saturating_add(margin_loss_e6);
+    let insurance_covered = remaining.min(insurance_available);
+    let adl_e6 = remaining - insurance_covered;
+    
+    LiquidationWaterfall {
+        margin_loss_e6,
+        liquidator_fee_e6,
+        treasury_fee_e6,
+        user_return_e6,
+        shortfall_e6,
+        free_collateral_e6,
+        insurance_fund_e6: insurance_covered,
+        adl_e6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// xorshift64，生成可复现的随机输入
+    struct Rng(u64);
+    
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        
+        fn range(&mut self, min: i64, max: i64) -> i64 {
+            min + (self.next() % (max - min + 1) as u64) as i64
+        }
+    }
+    
+    #[test]
+    fn test_waterfall_healthy_equity() {
+        // 保证金$5,000，权益$400，清算费$25
+        let w = calculate_liquidation_waterfall(5_000_000_000, 400_000_000, 25_000_000, 0, 0);
+        
+        assert_eq!(w.margin_loss_e6, 4_600_000_000);
+        assert_eq!(w.liquidator_fee_e6, 12_500_000);
+        assert_eq!(w.treasury_fee_e6, 12_500_000);
+        assert_eq!(w.user_return_e6, 375_000_000);
+        assert_eq!(w.shortfall_e6, 0);
+    }
+    
+    #[test]
+    fn test_waterfall_fee_capped_by_equity() {
+        // 权益$10不足以支付$25清算费
+        let w = calculate_liquidation_waterfall(5_000_000_000, 10_000_000, 25_000_000, 0, 0);
+        assert_eq!(w.liquidation_fee_e6(), 10_000_000);
+        assert_eq!(w.user_return_e6, 0);
+        
+        // 穿仓时不收清算费
+        let w = calculate_liquidation_waterfall(5_000_000_000, -1_000_000_000, 25_000_000, 0, 0);
+        assert_eq!(w.liquidation_fee_e6(), 0);
+        assert_eq!(w.margin_loss_e6, 5_000_000_000);
+    }
+    
+    #[test]
+    fn test_waterfall_shortfall_levels() {
+        // 穿仓$1,000：可用抵押品$300 → Insurance Fund（$200 + 保证金$5,000）
+        let w = calculate_liquidation_waterfall(5_000_000_000, -1_000_000_000, 25_000_000, 300_000_000, 200_000_000);
+        assert_eq!(w.free_collateral_e6, 300_000_000);
+        assert_eq!(w.insurance_fund_e6, 700_000_000);
+        assert_eq!(w.adl_e6, 0);
+        
+        // 保证金为0时Insurance Fund不足，剩余进入ADL
+        let w = calculate_liquidation_waterfall(0, -1_000_000_000, 0, 300_000_000, 200_000_000);
+        assert_eq!(w.free_collateral_e6, 300_000_000);
+        assert_eq!(w.insurance_fund_e6, 200_000_000);
+        assert_eq!(w.adl_e6, 500_000_000);
+    }
+    
+    #[test]
+    fn test_waterfall_legs_sum_property() {
+        let mut rng = Rng(0x1024_5eed_cafe_f00d);
+        
+        for _ in 0..10_000 {
+            let locked = rng.range(0, 1_000_000_000_000);
+            let equity = rng.range(-2_000_000_000_000, 2_000_000_000_000);
+            let fee = rng.range(0, locked / 10);
+            let free_collateral = rng.range(-1_000_000_000, 1_000_000_000_000);
+            let insurance = rng.range(-1_000_000_000, 1_000_000_000_000);
+            
+            let w = calculate_liquidation_waterfall(locked, equity, fee, free_collateral, insurance);
+            
+            // 保证金各项合计 = locked_usdc_e6
+            assert_eq!(w.margin_total_e6(), locked, "{:?}", w);
+            // 穿仓各级合计 = shortfall
+            assert_eq!(w.free_collateral_e6 + w.insurance_fund_e6 + w.adl_e6, w.shortfall_e6, "{:?}", w);
+            assert_eq!(w.shortfall_e6, (-equity).max(0));
+            
+            // 各项非负，清算费不超过正权益
+            for leg in [
+                w.margin_loss_e6,
+                w.liquidator_fee_e6,
+                w.treasury_fee_e6,
+                w.user_return_e6,
+                w.free_collateral_e6,
+                w.insurance_fund_e6,
+                w.adl_e6,
+            ] {
+                assert!(leg >= 0, "{:?}", w);
+            }
+            assert!(w.liquidation_fee_e6() <= equity.max(0));
+            
+            // 各级不超过其可用余额
+            assert!(w.free_collateral_e6 <= free_collateral.max(0));
+            assert!(w.insurance_fund_e6 <= insurance.max(0) + w.margin_loss_e6);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! 账户校验测试：提交伪造账户，验证每一种都被拒绝
+
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+};
+use spl_token::state::{Account as TokenAccount, AccountState};
+use trading_program::{
+    error::TradingError,
+    state::{MarginMode, RiskParams, Side, TradingVault, UserPosition},
+    validation::*,
+};
+
+/// 辅助函数：构造SPL Token账户数据
+fn token_account_data(mint: Pubkey, owner: Pubkey, amount: u64) -> Vec<u8> {
+    let account = TokenAccount {
+        mint,
+        owner,
+        amount,
+        delegate: COption::None,
+        state: AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    };
+    let mut data = vec![0u8; TokenAccount::LEN];
+    TokenAccount::pack(account, &mut data).unwrap();
+    data
+}
+
+fn err(e: TradingError) -> ProgramError {
+    e.into()
+}
+
+#[test]
+fn test_rejects_spoofed_token_program() {
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![];
+    let fake = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, true, 0);
+    
+    assert_eq!(validate_token_program(&fake), Err(err(TradingError::InvalidTokenProgram)));
+    
+    let key = spl_token::id();
+    let mut lamports = 0;
+    let mut data = vec![];
+    let real = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, true, 0);
+    assert!(validate_token_program(&real).is_ok());
+}
+
+#[test]
+fn test_rejects_spoofed_system_program() {
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![];
+    let fake = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, true, 0);
+    
+    assert_eq!(validate_system_program(&fake), Err(err(TradingError::InvalidSystemProgram)));
+    
+    let key = system_program::id();
+    let mut lamports = 0;
+    let mut data = vec![];
+    let real = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, true, 0);
+    assert!(validate_system_program(&real).is_ok());
+}
+
+#[test]
+fn test_rejects_spoofed_trading_vault() {
+    let program_id = Pubkey::new_unique();
+    let (vault_pda, bump) = find_vault_pda(&program_id);
+    let vault_data = TradingVault::new(
+        Pubkey::new_unique(),
+        bump,
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    )
+    .try_to_vec()
+    .unwrap();
+    
+    // 1. 地址不是PDA
+    let fake_key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = vault_data.clone();
+    let fake = AccountInfo::new(&fake_key, false, true, &mut lamports, &mut data, &program_id, false, 0);
+    assert_eq!(
+        load_trading_vault(&program_id, &fake).unwrap_err(),
+        err(TradingError::InvalidVaultAccount)
+    );
+    
+    // 2. 地址正确但不由Program持有
+    let attacker_program = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = vault_data.clone();
+    let foreign = AccountInfo::new(&vault_pda, false, true, &mut lamports, &mut data, &attacker_program, false, 0);
+    assert_eq!(
+        load_trading_vault(&program_id, &foreign).unwrap_err(),
+        err(TradingError::InvalidVaultAccount)
+    );
+    
+    // 3. 正确的Vault
+    let mut lamports = 1;
+    let mut data = vault_data;
+    let real = AccountInfo::new(&vault_pda, false, true, &mut lamports, &mut data, &program_id, false, 0);
+    let (vault, loaded_bump) = load_trading_vault(&program_id, &real).unwrap();
+    assert_eq!(vault.discriminator, TradingVault::DISCRIMINATOR);
+    assert_eq!(loaded_bump, bump);
+}
+
+#[test]
+fn test_rejects_spoofed_user_position() {
+    let program_id = Pubkey::new_unique();
+    let wallet = Pubkey::new_unique();
+    let (position_pda, bump) = find_position_pda(&program_id, &wallet, "test_isolated", "BTC-PERP");
+    
+    let position = UserPosition::new(
+        wallet,
+        "test_isolated".to_string(),
+        "BTC-PERP".to_string(),
+        Side::Buy,
+        1_000_000,
+        100_000_000_000,
+        20,
+        MarginMode::Isolated,
+        5_000_000_000,
+        2_500_000_000,
+        RiskParams::DEFAULT,
+        bump,
+    );
+    let mut position_data = position.try_to_vec().unwrap();
+    position_data.resize(UserPosition::MAX_SIZE, 0);
+    
+    // 其他用户的钱包
+    let other_wallet = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = position_data.clone();
+    let account = AccountInfo::new(&position_pda, false, true, &mut lamports, &mut data, &program_id, false, 0);
+    assert_eq!(
+        load_user_position(&program_id, &account, &other_wallet, "test_isolated", "BTC-PERP").unwrap_err(),
+        err(TradingError::InvalidPositionAccount)
+    );
+    
+    // 不由Program持有
+    let attacker_program = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = position_data.clone();
+    let foreign = AccountInfo::new(&position_pda, false, true, &mut lamports, &mut data, &attacker_program, false, 0);
+    assert_eq!(
+        load_user_position(&program_id, &foreign, &wallet, "test_isolated", "BTC-PERP").unwrap_err(),
+        err(TradingError::InvalidPositionAccount)
+    );
+    
+    // 正确的持仓（带尾部预留字节也能读取）
+    let mut lamports = 1;
+    let mut data = position_data;
+    let real = AccountInfo::new(&position_pda, false, true, &mut lamports, &mut data, &program_id, false, 0);
+    let loaded = load_user_position(&program_id, &real, &wallet, "test_isolated", "BTC-PERP").unwrap();
+    assert_eq!(loaded.size_e6, 1_000_000);
+}
+
+#[test]
+fn test_rejects_spoofed_token_accounts() {
+    let program_id = Pubkey::new_unique();
+    let (vault_pda, _) = find_vault_pda(&program_id);
+    let usdc_mint = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let token_program_id = spl_token::id();
+    
+    let vault_usdc_key = Pubkey::new_unique();
+    let vault = TradingVault::new(Pubkey::new_unique(), 255, usdc_mint, vault_usdc_key);
+    
+    // 1. Vault USDC账户不是TradingVault中记录的账户（即使owner是Vault PDA）
+    let key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = token_account_data(usdc_mint, vault_pda, 0);
+    let fake_vault = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &token_program_id, false, 0);
+    assert_eq!(
+        validate_vault_token_account(&fake_vault, &vault).unwrap_err(),
+        err(TradingError::InvalidVaultTokenAccount)
+    );
+    
+    // 2. 用户USDC账户不归用户所有
+    let key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = token_account_data(usdc_mint, Pubkey::new_unique(), 0);
+    let foreign_user = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &token_program_id, false, 0);
+    assert_eq!(
+        validate_token_account(&foreign_user, &user, &usdc_mint).unwrap_err(),
+        err(TradingError::InvalidTokenAccountOwner)
+    );
+    
+    // 3. 用户账户mint错误
+    let key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = token_account_data(Pubkey::new_unique(), user, 0);
+    let wrong_mint = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &token_program_id, false, 0);
+    assert_eq!(
+        validate_token_account(&wrong_mint, &user, &usdc_mint).unwrap_err(),
+        err(TradingError::InvalidTokenMint)
+    );
+    
+    // 4. 伪造的Token账户（不由spl-token持有）
+    let key = Pubkey::new_unique();
+    let attacker_program = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = token_account_data(usdc_mint, user, 1_000_000);
+    let not_token = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &attacker_program, false, 0);
+    assert_eq!(
+        validate_token_account(&not_token, &user, &usdc_mint).unwrap_err(),
+        err(TradingError::InvalidTokenAccount)
+    );
+    
+    // 5. Insurance Fund / Fee Treasury不是Vault中登记的账户（即使owner是Vault PDA，如Vault USDC账户）
+    let mut vault = vault;
+    let insurance_fund_key = Pubkey::new_unique();
+    let fee_treasury_key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = token_account_data(usdc_mint, vault_pda, 0);
+    let vault_usdc = AccountInfo::new(&vault_usdc_key, false, true, &mut lamports, &mut data, &token_program_id, false, 0);
+    
+    // 未设置时一律拒绝
+    assert_eq!(
+        validate_insurance_fund_account(&vault_usdc, &vault).unwrap_err(),
+        err(TradingError::InvalidInsuranceFundAccount)
+    );
+    
+    vault.insurance_fund_account = insurance_fund_key;
+    vault.fee_treasury_account = fee_treasury_key;
+    assert_eq!(
+        validate_insurance_fund_account(&vault_usdc, &vault).unwrap_err(),
+        err(TradingError::InvalidInsuranceFundAccount)
+    );
+    assert_eq!(
+        validate_fee_treasury_account(&vault_usdc, &vault).unwrap_err(),
+        err(TradingError::InvalidFeeTreasuryAccount)
+    );
+    
+    // 6. 正确的账户
+    assert!(validate_vault_token_account(&vault_usdc, &vault).is_ok());
+    
+    let mut lamports = 1;
+    let mut data = token_account_data(usdc_mint, vault_pda, 0);
+    let insurance_fund = AccountInfo::new(&insurance_fund_key, false, true, &mut lamports, &mut data, &token_program_id, false, 0);
+    assert!(validate_insurance_fund_account(&insurance_fund, &vault).is_ok());
+    
+    let mut lamports = 1;
+    let mut data = token_account_data(usdc_mint, vault_pda, 0);
+    let fee_treasury = AccountInfo::new(&fee_treasury_key, false, true, &mut lamports, &mut data, &token_program_id, false, 0);
+    assert!(validate_fee_treasury_account(&fee_treasury, &vault).is_ok());
+    
+    println!("✅ Spoofed token accounts rejected");
+}
+
+#[test]
+fn test_rejects_spoofed_usdc_mint() {
+    let key = Pubkey::new_unique();
+    let attacker_program = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = vec![0u8; spl_token::state::Mint::LEN];
+    let fake_mint = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &attacker_program, false, 0);
+    
+    assert_eq!(validate_mint_account(&fake_mint), Err(err(TradingError::InvalidTokenMint)));
+    
+    // 由spl-token持有但未初始化
+    let token_program_id = spl_token::id();
+    let mut lamports = 1;
+    let mut data = vec![0u8; spl_token::state::Mint::LEN];
+    let uninitialized = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &token_program_id, false, 0);
+    
+    assert_eq!(validate_mint_account(&uninitialized), Err(err(TradingError::InvalidTokenMint)));
+}
+
+#[test]
+fn test_rejects_spoofed_market_config() {
+    use trading_program::state::MarketConfig;
+    
+    let program_id = Pubkey::new_unique();
+    let (btc_pda, bump) = find_market_pda(&program_id, "BTC-PERP");
+    let config_data = MarketConfig::new("BTC-PERP".to_string(), 100, RiskParams::DEFAULT, 100_000, 1_000, bump)
+        .try_to_vec()
+        .unwrap();
+    
+    // BTC配置冒充ETH市场
+    let mut lamports = 1;
+    let mut data = config_data.clone();
+    let account = AccountInfo::new(&btc_pda, false, false, &mut lamports, &mut data, &program_id, false, 0);
+    assert_eq!(
+        load_market_config(&program_id, &account, "ETH-PERP").unwrap_err(),
+        err(TradingError::InvalidMarketConfigAccount)
+    );
+    
+    // 正确的配置
+    let loaded = load_market_config(&program_id, &account, "BTC-PERP").unwrap();
+    assert_eq!(loaded.max_leverage, 100);
+    
+    // 不由Program持有
+    let attacker_program = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = config_data;
+    let foreign = AccountInfo::new(&btc_pda, false, false, &mut lamports, &mut data, &attacker_program, false, 0);
+    assert_eq!(
+        load_market_config(&program_id, &foreign, "BTC-PERP").unwrap_err(),
+        err(TradingError::InvalidMarketConfigAccount)
+    );
+}
@@ -0,0 +1,268 @@
+//! ADL测试：坏账由反方向盈利持仓按破产价格减仓覆盖
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_sdk::signature::{Keypair, Signer};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{CrossMarginAccount, MarginMode, Side, UserPosition},
+    validation::{find_cross_margin_pda, find_position_pda},
+};
+
+/// AutoDeleverage：targets为按排名传入的目标持仓（测试账户ID），others为其余反方向持仓
+fn auto_deleverage_ix(env: &TestEnv, relayer: &Keypair, targets: &[MarginMode], others: &[MarginMode]) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let mut accounts = vec![
+        AccountMeta::new_readonly(relayer.pubkey(), true),
+        AccountMeta::new(env.vault_pda, false),
+        AccountMeta::new(env.vault_usdc, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(env.market_pda, false),
+        AccountMeta::new_readonly(env.oracle, false),
+        AccountMeta::new(env.funding_pda, false),
+    ];
+    
+    for &margin_mode in targets {
+        let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, test_account_id(margin_mode));
+        accounts.push(AccountMeta::new(position_pda(env, margin_mode), false));
+        accounts.push(AccountMeta::new(user, false));
+        accounts.push(AccountMeta::new(env.user_usdc, false));
+        accounts.push(AccountMeta::new(cross_margin_pda, false));
+    }
+    
+    for &margin_mode in others {
+        accounts.push(AccountMeta::new_readonly(position_pda(env, margin_mode), false));
+    }
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts,
+        data: TradingInstruction::AutoDeleverage {
+            market: "BTC-PERP".to_string(),
+            target_count: targets.len() as u8,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+async fn load_cross_margin(env: &mut TestEnv, account_id: &str) -> CrossMarginAccount {
+    let user = env.context.payer.pubkey();
+    let (pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+    let account = env.context.banks_client.get_account(pda).await.unwrap().unwrap();
+    CrossMarginAccount::deserialize(&mut &account.data[..]).unwrap()
+}
+
+/// 空头：逐仓1 BTC 20x（IM $5,000）+ 全仓1 BTC 50x（IM $2,000），价格跌至$99,000后
+/// 全仓排名更高（盈利率50% × 50 > 20% × 20）；本市场多头穿仓，破产价格$99,500
+async fn open_ranked_shorts(env: &mut TestEnv, isolated_collateral_e6: i64, adl_outstanding_e6: i64) {
+    if isolated_collateral_e6 > 0 {
+        let ix = deposit_ix(env, "test_isolated", isolated_collateral_e6);
+        send(env, ix).await.unwrap();
+    }
+    let ix = lock_margin_order_ix(env, env.oracle, Side::Sell, 1_000_000, ORACLE_PRICE_E6);
+    send(env, ix).await.unwrap();
+    
+    let ix = deposit_ix(env, "test_cross", 10_000_000_000);
+    send(env, ix).await.unwrap();
+    let ix = lock_margin_with_mode_ix(env, env.oracle, Side::Sell, 1_000_000, ORACLE_PRICE_E6, 50, MarginMode::Cross);
+    send(env, ix).await.unwrap();
+    
+    let now = current_time(env).await;
+    write_oracle(env, 99_000_000_000, 10_000_000, now);
+    write_funding(env, |state| {
+        state.last_updated_at = now;
+        state.long_bankruptcy_price_e6 = 99_500_000_000;
+        state.long_adl_outstanding_e6 = adl_outstanding_e6;
+        state.short_positions = 2;
+    });
+}
+
+#[tokio::test]
+async fn test_auto_deleverage_covers_bad_debt() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    let mut env = setup_with(|vault| {
+        vault.relayer = relayer_key;
+        vault.bad_debt_e6 = 300_000_000;
+    })
+    .await;
+    
+    // 开空1 BTC @ $100,000，IM = $5,000
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,000，空头盈利
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_000_000_000, 10_000_000, now);
+    
+    // 本市场多头穿仓：破产价格$99,500，待ADL覆盖$300
+    write_funding(&mut env, |state| {
+        state.last_updated_at = now;
+        state.long_bankruptcy_price_e6 = 99_500_000_000;
+        state.long_adl_outstanding_e6 = 300_000_000;
+        state.short_positions = 1;
+    });
+    
+    // 每BTC让渡$500 → 减仓0.6 BTC覆盖$300坏账
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Isolated], &[]);
+    send_with_signers(&mut env, ix, &[&relayer]).await.unwrap();
+    
+    let user = env.context.payer.pubkey();
+    let (position_pda, _) = find_position_pda(&env.program_id, &user, "test_isolated", "BTC-PERP");
+    let account = env.context.banks_client.get_account(position_pda).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.size_e6, 400_000);
+    assert_eq!(position.locked_usdc_e6, 2_000_000_000);
+    assert_eq!(position.realized_pnl_e6, 300_000_000);
+    
+    // 返还释放的IM $3,000 + 破产价格下的盈利$300
+    assert_eq!(token_balance(&env, env.vault_usdc).await, 1_700_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.bad_debt_e6, 0);
+    assert_eq!(vault.total_locked_usdc_e6, 2_000_000_000);
+    assert_eq!(vault.total_positions, 1);
+    
+    let funding = load_funding(&mut env).await;
+    assert_eq!(funding.long_adl_outstanding_e6, 0);
+    assert_eq!(funding.pending_adl(), None);
+}
+
+#[tokio::test]
+async fn test_auto_deleverage_requires_bad_debt() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    // Vault有坏账，但本市场没有记录穿仓
+    let mut env = setup_with(|vault| {
+        vault.relayer = relayer_key;
+        vault.bad_debt_e6 = 300_000_000;
+    })
+    .await;
+    
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Isolated], &[]);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&relayer]).await,
+        TradingError::AdlNotRequired,
+    );
+}
+
+#[tokio::test]
+async fn test_liquidation_records_bankruptcy_for_adl() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 开多1 BTC @ $100,000，20x，IM = $5,000，破产价格$95,000
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$89,000：穿仓$6,000，保证金亏损$5,000并入Insurance Fund后仍缺$1,000
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 89_000_000_000, 10_000_000, now);
+    
+    let ix = liquidate_ix(&env, MarginMode::Isolated, insurance_fund, 89_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.bad_debt_e6, 1_000_000_000);
+    
+    // 本市场记录多头穿仓的破产价格与待ADL金额
+    let funding = load_funding(&mut env).await;
+    assert_eq!(funding.long_bankruptcy_price_e6, 95_000_000_000);
+    assert_eq!(funding.long_adl_outstanding_e6, 1_000_000_000);
+    assert_eq!(funding.short_adl_outstanding_e6, 0);
+    assert_eq!(funding.pending_adl(), Some((Side::Buy, 95_000_000_000, 1_000_000_000)));
+}
+
+#[tokio::test]
+async fn test_auto_deleverage_verifies_ranking() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    let mut env = setup_with(|vault| {
+        vault.relayer = relayer_key;
+        vault.bad_debt_e6 = 300_000_000;
+    })
+    .await;
+    open_ranked_shorts(&mut env, 0, 300_000_000).await;
+    
+    // 漏传其余反方向持仓：无法证明没有跳过排名更高的持仓
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Isolated], &[]);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&relayer]).await,
+        TradingError::AdlRankingViolation,
+    );
+    
+    // 跳过排名更高的全仓持仓
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Isolated], &[MarginMode::Cross]);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&relayer]).await,
+        TradingError::AdlRankingViolation,
+    );
+    
+    // 同一持仓重复传入
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Cross], &[MarginMode::Cross]);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&relayer]).await,
+        TradingError::InvalidAdlTarget,
+    );
+    
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Cross], &[MarginMode::Isolated]);
+    send_with_signers(&mut env, ix, &[&relayer]).await.unwrap();
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.bad_debt_e6, 0);
+}
+
+#[tokio::test]
+async fn test_auto_deleverage_pays_into_collateral() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    let mut env = setup_with(|vault| {
+        vault.relayer = relayer_key;
+        vault.bad_debt_e6 = 800_000_000;
+    })
+    .await;
+    open_ranked_shorts(&mut env, 10_000_000_000, 800_000_000).await;
+    
+    let cross_before = load_cross_margin(&mut env, "test_cross").await;
+    let isolated_before = load_cross_margin(&mut env, "test_isolated").await;
+    let vault_before = load_vault(&mut env).await;
+    let user_balance = token_balance(&env, env.user_usdc).await;
+    
+    // 全仓1 BTC全部减仓覆盖$500，逐仓减仓0.6 BTC覆盖剩余$300
+    let ix = auto_deleverage_ix(&env, &relayer, &[MarginMode::Cross, MarginMode::Isolated], &[]);
+    send_with_signers(&mut env, ix, &[&relayer]).await.unwrap();
+    
+    // 全仓：盈利$500计入抵押品，释放IM $2,000，持仓关闭并从账户移除
+    let cross_position = env.context.banks_client.get_account(position_pda(&env, MarginMode::Cross)).await.unwrap();
+    assert!(cross_position.is_none());
+    let cross = load_cross_margin(&mut env, "test_cross").await;
+    assert_eq!(cross.collateral_e6, cross_before.collateral_e6 + 500_000_000);
+    assert_eq!(cross.locked_im_e6, cross_before.locked_im_e6 - 2_000_000_000);
+    assert!(cross.positions.is_empty());
+    
+    // 逐仓：释放的IM $3,000 + 盈利$300计入账户抵押品，不转给用户
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Isolated)).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.size_e6, 400_000);
+    assert_eq!(position.locked_usdc_e6, 2_000_000_000);
+    let isolated = load_cross_margin(&mut env, "test_isolated").await;
+    assert_eq!(isolated.collateral_e6, isolated_before.collateral_e6 + 3_300_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, user_balance);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.bad_debt_e6, 0);
+    assert_eq!(vault.total_locked_usdc_e6, vault_before.total_locked_usdc_e6 + 800_000_000);
+    assert_eq!(vault.total_positions, vault_before.total_positions - 1);
+    
+    let funding = load_funding(&mut env).await;
+    assert_eq!(funding.short_positions, 1);
+    assert_eq!(funding.pending_adl(), None);
+}
@@ -0,0 +1,103 @@
+//! 管理员转移测试：ProposeAuthority + 新管理员签名AcceptAuthority
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::signature::{Keypair, Signer};
+use trading_program::{error::TradingError, instruction::TradingInstruction, state::TradingVault};
+
+fn propose_authority_ix(env: &TestEnv, admin: Pubkey, new_authority: Pubkey) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new(admin, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: TradingInstruction::ProposeAuthority { new_authority }.try_to_vec().unwrap(),
+    }
+}
+
+fn accept_authority_ix(env: &TestEnv, new_authority: Pubkey) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(new_authority, true),
+        ],
+        data: TradingInstruction::AcceptAuthority.try_to_vec().unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_two_step_authority_transfer() {
+    let mut env = setup().await;
+    make_payer_admin(&mut env).await;
+    let admin = env.context.payer.pubkey();
+    let new_admin = Keypair::new();
+    let other = Keypair::new();
+    
+    // 没有待接受的管理员
+    let ix = accept_authority_ix(&env, new_admin.pubkey());
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&new_admin]).await,
+        TradingError::InvalidAuthority,
+    );
+    
+    let ix = propose_authority_ix(&env, admin, new_admin.pubkey());
+    send(&mut env, ix).await.unwrap();
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.authority, admin);
+    assert_eq!(vault.pending_authority, new_admin.pubkey());
+    
+    // 只有被提议的公钥可以接受
+    let ix = accept_authority_ix(&env, other.pubkey());
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&other]).await,
+        TradingError::InvalidAuthority,
+    );
+    
+    // 换新blockhash，避免与上面失败的交易签名相同
+    env.context.get_new_latest_blockhash().await.unwrap();
+    let ix = accept_authority_ix(&env, new_admin.pubkey());
+    send_with_signers(&mut env, ix, &[&new_admin]).await.unwrap();
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.authority, new_admin.pubkey());
+    assert_eq!(vault.pending_authority, Pubkey::default());
+    
+    // 原管理员失去权限
+    let ix = propose_authority_ix(&env, admin, admin);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidAuthority);
+}
+
+#[tokio::test]
+async fn test_propose_authority_resizes_old_vault() {
+    // 旧Vault的272字节之后没有guardian
+    let mut env = setup_with(|vault| vault.guardian = Pubkey::default()).await;
+    write_vault_with_payer_admin(&mut env, 272).await;
+    let admin = env.context.payer.pubkey();
+    let new_admin = Keypair::new();
+    
+    // 不需要先SetGuardian
+    let ix = propose_authority_ix(&env, admin, new_admin.pubkey());
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(env.vault_pda).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), TradingVault::SIZE);
+    
+    let vault = TradingVault::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(vault.pending_authority, new_admin.pubkey());
+    assert_eq!(vault.guardian, Pubkey::default());
+    
+    let ix = accept_authority_ix(&env, new_admin.pubkey());
+    send_with_signers(&mut env, ix, &[&new_admin]).await.unwrap();
+    assert_eq!(load_vault(&mut env).await.authority, new_admin.pubkey());
+}
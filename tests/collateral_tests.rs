@@ -0,0 +1,225 @@
+//! 账户抵押品测试：Deposit/Withdraw存取，逐仓开仓从抵押品划转IM，平仓返还计入抵押品
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    signature::Signer,
+};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{CrossMarginAccount, MarginMode, Side},
+    validation::{find_cross_margin_pda, find_hedge_position_pda},
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+async fn load_collateral(env: &mut TestEnv, account_id: &str) -> CrossMarginAccount {
+    let user = env.context.payer.pubkey();
+    let (pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+    let account = env.context.banks_client.get_account(pda).await.unwrap().unwrap();
+    CrossMarginAccount::deserialize(&mut &account.data[..]).unwrap()
+}
+
+#[tokio::test]
+async fn test_deposit_and_withdraw() {
+    let mut env = setup().await;
+    
+    let ix = deposit_ix(&env, "test_isolated", 2_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let collateral = load_collateral(&mut env, "test_isolated").await;
+    assert_eq!(collateral.collateral_e6, 2_000_000_000);
+    assert_eq!(token_balance(&env, env.vault_usdc).await, 2_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 2_000_000_000);
+    
+    // 超出可用抵押品
+    let ix = withdraw_ix(&env, "test_isolated", 2_000_000_001);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InsufficientBalance);
+    
+    let ix = withdraw_ix(&env, "test_isolated", 2_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE);
+    assert_eq!(load_vault(&mut env).await.total_locked_usdc_e6, 0);
+}
+
+#[tokio::test]
+async fn test_isolated_lock_margin_draws_from_collateral() {
+    let mut env = setup().await;
+    
+    let ix = deposit_ix(&env, "test_isolated", 2_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    // 开多1 BTC，IM = $5,000：$2,000来自抵押品，$3,000由钱包补足
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    assert_eq!(load_collateral(&mut env, "test_isolated").await.collateral_e6, 0);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+    
+    // 平仓：释放的IM计入抵押品，不转回钱包
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    assert_eq!(load_collateral(&mut env, "test_isolated").await.collateral_e6, 5_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+    
+    // 再次开仓完全由抵押品支付
+    // （换新blockhash，避免与上面开仓的交易签名相同）
+    env.context.get_new_latest_blockhash().await.unwrap();
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    assert_eq!(load_collateral(&mut env, "test_isolated").await.collateral_e6, 0);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 5_000_000_000);
+}
+
+#[tokio::test]
+async fn test_prefunded_collateral_pda_does_not_block_account() {
+    let mut env = setup().await;
+    
+    // 他人向账户抵押品PDA预先转入lamports（仍归System Program所有）
+    let user = env.context.payer.pubkey();
+    let (pda, _) = find_cross_margin_pda(&env.program_id, &user, "test_isolated");
+    env.context.set_account(
+        &pda,
+        &AccountSharedData::from(Account {
+            lamports: 1_000_000,
+            owner: system_program::id(),
+            ..Account::default()
+        }),
+    );
+    
+    // 逐仓开仓不把它当作已创建的抵押品账户
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+    
+    // Deposit补足租金后allocate + assign
+    let ix = deposit_ix(&env, "test_isolated", 2_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(pda).await.unwrap().unwrap();
+    assert_eq!(account.owner, env.program_id);
+    assert_eq!(load_collateral(&mut env, "test_isolated").await.collateral_e6, 2_000_000_000);
+}
+
+#[tokio::test]
+async fn test_prefunded_position_pda_does_not_block_open() {
+    let mut env = setup().await;
+    
+    // 他人向持仓PDA预先转入lamports（仍归System Program所有）
+    let pda = position_pda(&env, MarginMode::Isolated);
+    env.context.set_account(
+        &pda,
+        &AccountSharedData::from(Account {
+            lamports: 1_000_000,
+            owner: system_program::id(),
+            ..Account::default()
+        }),
+    );
+    
+    // 开仓补足租金后allocate + assign
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(pda).await.unwrap().unwrap();
+    assert_eq!(account.owner, env.program_id);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+}
+
+#[tokio::test]
+async fn test_withdraw_keeps_cross_positions_healthy() {
+    let mut env = setup().await;
+    
+    // 全仓开多1 BTC @ $100,000，100x，IM = $1,000；另存入$1,000可用抵押品
+    let ix = lock_margin_with_mode_ix(&env, env.oracle, Side::Buy, 1_000_000, ORACLE_PRICE_E6, 100, MarginMode::Cross);
+    send(&mut env, ix).await.unwrap();
+    let ix = deposit_ix(&env, "test_cross", 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,500：未实现亏损$500
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_500_000_000, 10_000_000, now);
+    
+    let positions = [
+        AccountMeta::new_readonly(position_pda(&env, MarginMode::Cross), false),
+        AccountMeta::new_readonly(env.market_pda, false),
+        AccountMeta::new_readonly(env.oracle, false),
+    ];
+    
+    // 未传入全仓持仓
+    let ix = withdraw_ix(&env, "test_cross", 500_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::CrossPositionsMismatch);
+    
+    // 提取全部可用抵押品后 $1,000 - $500 < IM $1,000
+    let mut ix = withdraw_ix(&env, "test_cross", 1_000_000_000);
+    ix.accounts.extend(positions.clone());
+    assert_trading_error(send(&mut env, ix).await, TradingError::InsufficientBalance);
+    
+    // 扣除未实现亏损后最多提取$500
+    let mut ix = withdraw_ix(&env, "test_cross", 500_000_000);
+    ix.accounts.extend(positions);
+    send(&mut env, ix).await.unwrap();
+    
+    let collateral = load_collateral(&mut env, "test_cross").await;
+    assert_eq!(collateral.collateral_e6, 1_500_000_000);
+    assert_eq!(collateral.locked_im_e6, 1_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 1_500_000_000);
+}
+
+#[tokio::test]
+async fn test_isolated_lock_margin_excludes_cross_unrealized_loss() {
+    let mut env = setup().await;
+    
+    // 全仓开多1 BTC @ $100,000，100x，IM = $1,000；另存入$1,000可用抵押品
+    let ix = lock_margin_with_mode_ix(&env, env.oracle, Side::Buy, 1_000_000, ORACLE_PRICE_E6, 100, MarginMode::Cross);
+    send(&mut env, ix).await.unwrap();
+    let ix = deposit_ix(&env, "test_cross", 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,500：全仓未实现亏损$500
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_500_000_000, 10_000_000, now);
+    
+    // 同一账户逐仓开空1 BTC @ $99,500（双向持仓），100x，IM = $995
+    let user = env.context.payer.pubkey();
+    let (short_pda, _) = find_hedge_position_pda(&env.program_id, &user, "test_cross", "BTC-PERP", Side::Sell);
+    let mut ix = lock_margin_with_mode_ix(&env, env.oracle, Side::Sell, 1_000_000, 99_500_000_000, 100, MarginMode::Cross);
+    ix.accounts[0] = AccountMeta::new(short_pda, false);
+    ix.data = TradingInstruction::LockMargin {
+        account_id: "test_cross".to_string(),
+        market: "BTC-PERP".to_string(),
+        side: Side::Sell,
+        size_e6: 1_000_000,
+        entry_price_e6: 99_500_000_000,
+        leverage: 100,
+        margin_mode: MarginMode::Isolated,
+    }
+    .try_to_vec()
+    .unwrap();
+    
+    // 未传入全仓持仓
+    assert_trading_error(send(&mut env, ix.clone()).await, TradingError::CrossPositionsMismatch);
+    
+    // 可用抵押品$1,000扣除亏损$500后只划转$500，其余$495由钱包补足
+    ix.accounts.extend([
+        AccountMeta::new_readonly(position_pda(&env, MarginMode::Cross), false),
+        AccountMeta::new_readonly(env.market_pda, false),
+        AccountMeta::new_readonly(env.oracle, false),
+    ]);
+    send(&mut env, ix).await.unwrap();
+    
+    let collateral = load_collateral(&mut env, "test_cross").await;
+    assert_eq!(collateral.collateral_e6, 1_500_000_000);
+    assert_eq!(collateral.locked_im_e6, 1_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 2_495_000_000);
+}
@@ -0,0 +1,597 @@
+//! program-test测试公共环境：直接写入Vault、MarketConfig、FundingState、Token账户和mock Pyth价格账户
+
+#![allow(dead_code)]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use solana_program_test::*;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    oracle::*,
+    processor,
+    state::{FundingState, MarginMode, MarketConfig, MarketStatus, RiskParams, Side, TradingMode, TradingVault},
+    validation::*,
+};
+
+pub const ORACLE_PRICE_E6: i64 = 100_000_000_000; // $100,000
+
+/// 构造Pyth v2价格账户数据（expo = -8）
+pub fn mock_pyth_price_data(price_e6: i64, conf_e6: u64, publish_time: i64) -> Vec<u8> {
+    let mut data = vec![0u8; 3312];
+    data[PYTH_MAGIC_OFFSET..PYTH_MAGIC_OFFSET + 4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+    data[PYTH_VERSION_OFFSET..PYTH_VERSION_OFFSET + 4].copy_from_slice(&PYTH_VERSION_2.to_le_bytes());
+    data[PYTH_ACCOUNT_TYPE_OFFSET..PYTH_ACCOUNT_TYPE_OFFSET + 4]
+        .copy_from_slice(&PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes());
+    data[PYTH_EXPO_OFFSET..PYTH_EXPO_OFFSET + 4].copy_from_slice(&(-8i32).to_le_bytes());
+    data[PYTH_TIMESTAMP_OFFSET..PYTH_TIMESTAMP_OFFSET + 8].copy_from_slice(&publish_time.to_le_bytes());
+    data[PYTH_AGG_PRICE_OFFSET..PYTH_AGG_PRICE_OFFSET + 8].copy_from_slice(&(price_e6 * 100).to_le_bytes());
+    data[PYTH_AGG_CONF_OFFSET..PYTH_AGG_CONF_OFFSET + 8].copy_from_slice(&(conf_e6 * 100).to_le_bytes());
+    data[PYTH_AGG_STATUS_OFFSET..PYTH_AGG_STATUS_OFFSET + 4]
+        .copy_from_slice(&PYTH_STATUS_TRADING.to_le_bytes());
+    data
+}
+
+pub fn program_account(data: Vec<u8>, owner: Pubkey) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+pub fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    let mut data = vec![0u8; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint,
+            owner,
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        },
+        &mut data,
+    )
+    .unwrap();
+    program_account(data, spl_token::id())
+}
+
+pub struct TestEnv {
+    pub context: ProgramTestContext,
+    pub program_id: Pubkey,
+    pub oracle: Pubkey,
+    pub oracle_owner: Pubkey,
+    pub vault_pda: Pubkey,
+    pub vault_usdc: Pubkey,
+    pub user_usdc: Pubkey,
+    pub fee_treasury: Pubkey,
+    pub market_pda: Pubkey,
+    pub funding_pda: Pubkey,
+    pub keeper: Keypair,
+}
+
+/// 创建测试环境：Vault、BTC-PERP市场（1% band, 60s, 1% conf）、mock预言机
+pub async fn setup() -> TestEnv {
+    setup_with(|_| {}).await
+}
+
+/// 同TradingVault::new，但不读取Clock sysvar
+/// 
+/// program-test的syscall stub是进程级的，程序调用之外读取Clock会panic。
+fn new_vault(authority: Pubkey, bump: u8, usdc_mint: Pubkey, vault_usdc_account: Pubkey) -> TradingVault {
+    TradingVault {
+        discriminator: TradingVault::DISCRIMINATOR,
+        version: TradingVault::VERSION,
+        bump,
+        reserved_align: [0; 6],
+        authority,
+        total_locked_usdc_e6: 0,
+        total_positions: 0,
+        insurance_fund_e6: 0,
+        fee_treasury_e6: 0,
+        created_at: 0,
+        updated_at: 0,
+        total_trades: 0,
+        total_liquidations: 0,
+        total_smart_hedges: 0,
+        cumulative_volume_e6: 0,
+        cumulative_fees_e6: 0,
+        cumulative_pnl_e6: 0,
+        relayer: authority,
+        usdc_mint,
+        vault_usdc_account,
+        bad_debt_e6: 0,
+        maker_fee_bp: 0,
+        taker_fee_bp: 0,
+        keeper_fee_bp: 0,
+        trading_mode: TradingMode::Active,
+        position_nonce: 0,
+        paused: false,
+        guardian: authority,
+        pending_authority: Pubkey::default(),
+        insurance_fund_account: Pubkey::default(),
+        fee_treasury_account: Pubkey::default(),
+        reserved: [0; 2],
+    }
+}
+
+/// 同MarketConfig::new（100x，tick 0.1，lot 0.001），但不读取Clock sysvar
+fn new_market_config(market: &str, bump: u8) -> MarketConfig {
+    MarketConfig {
+        discriminator: MarketConfig::DISCRIMINATOR,
+        version: MarketConfig::VERSION,
+        bump,
+        trading_mode: TradingMode::Active,
+        reserved_align: [0; 5],
+        market: market.to_string(),
+        status: MarketStatus::Active,
+        max_leverage: 100,
+        risk_params: RiskParams::DEFAULT,
+        tick_size_e6: 100_000,
+        lot_size_e6: 1_000,
+        created_at: 0,
+        updated_at: 0,
+        risk_brackets: Vec::new(),
+        oracle: Pubkey::default(),
+        max_price_deviation_bp: 0,
+        max_oracle_staleness_secs: 0,
+        max_oracle_confidence_bp: 0,
+        partial_liquidation_target_bp: 0,
+    }
+}
+
+/// 创建测试环境，写入前可修改Trading Vault（如手续费率、Relayer）
+pub async fn setup_with(configure_vault: impl FnOnce(&mut TradingVault)) -> TestEnv {
+    let program_id = Pubkey::new_unique();
+    let mut program_test = ProgramTest::new(
+        "trading_program",
+        program_id,
+        processor!(processor::process_instruction),
+    );
+    
+    let usdc_mint = Pubkey::new_unique();
+    let vault_usdc = Pubkey::new_unique();
+    let user_usdc = Pubkey::new_unique();
+    let fee_treasury = Pubkey::new_unique();
+    let oracle = Pubkey::new_unique();
+    let oracle_owner = Pubkey::new_unique();
+    let (vault_pda, vault_bump) = find_vault_pda(&program_id);
+    let (market_pda, market_bump) = find_market_pda(&program_id, "BTC-PERP");
+    let (funding_pda, _) = find_funding_pda(&program_id, "BTC-PERP");
+    let keeper = Keypair::new();
+    
+    // USDC Mint
+    let mut mint_data = vec![0u8; Mint::LEN];
+    Mint::pack(
+        Mint {
+            mint_authority: COption::None,
+            supply: 1_000_000_000_000,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+        &mut mint_data,
+    )
+    .unwrap();
+    program_test.add_account(usdc_mint, program_account(mint_data, spl_token::id()));
+    
+    // Trading Vault
+    let mut vault = new_vault(Pubkey::new_unique(), vault_bump, usdc_mint, vault_usdc);
+    vault.fee_treasury_account = fee_treasury;
+    configure_vault(&mut vault);
+    let mut vault_data = vault.try_to_vec().unwrap();
+    vault_data.resize(TradingVault::SIZE, 0);
+    program_test.add_account(vault_pda, program_account(vault_data, program_id));
+    program_test.add_account(vault_usdc, token_account(usdc_mint, vault_pda, 0));
+    program_test.add_account(fee_treasury, token_account(usdc_mint, vault_pda, 0));
+    
+    // Market Config（带预言机）
+    let mut config = new_market_config("BTC-PERP", market_bump);
+    config.oracle = oracle;
+    config.max_price_deviation_bp = 100;
+    config.max_oracle_staleness_secs = 60;
+    config.max_oracle_confidence_bp = 100;
+    let mut config_data = config.try_to_vec().unwrap();
+    config_data.resize(MarketConfig::MAX_SIZE, 0);
+    program_test.add_account(market_pda, program_account(config_data, program_id));
+    
+    let mut context = program_test.start_with_context().await;
+    
+    // 用户USDC账户（payer作为用户）
+    let user = context.payer.pubkey();
+    context.set_account(
+        &user_usdc,
+        &AccountSharedData::from(token_account(usdc_mint, user, 1_000_000_000_000)),
+    );
+    
+    let mut env = TestEnv {
+        context,
+        program_id,
+        oracle,
+        oracle_owner,
+        vault_pda,
+        vault_usdc,
+        user_usdc,
+        fee_treasury,
+        market_pda,
+        funding_pda,
+        keeper,
+    };
+    
+    let now = current_time(&env).await;
+    write_oracle(&mut env, ORACLE_PRICE_E6, 10_000_000, now);
+    write_funding(&mut env, |state| state.last_updated_at = now);
+    env
+}
+
+pub async fn current_time(env: &TestEnv) -> i64 {
+    let mut banks_client = env.context.banks_client.clone();
+    banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+}
+
+/// 写入mock预言机账户
+pub fn write_oracle(env: &mut TestEnv, price_e6: i64, conf_e6: u64, publish_time: i64) {
+    let data = mock_pyth_price_data(price_e6, conf_e6, publish_time);
+    env.context.set_account(
+        &env.oracle,
+        &AccountSharedData::from(program_account(data, env.oracle_owner)),
+    );
+}
+
+/// 写入Funding State（1小时周期，单周期费率上限0.1%）
+pub fn write_funding(env: &mut TestEnv, configure: impl FnOnce(&mut FundingState)) {
+    let (_, bump) = find_funding_pda(&env.program_id, "BTC-PERP");
+    // 不走FundingState::new：程序调用之外读取Clock会panic
+    let mut state = FundingState {
+        discriminator: FundingState::DISCRIMINATOR,
+        version: FundingState::VERSION,
+        bump,
+        reserved_align: [0; 6],
+        market: "BTC-PERP".to_string(),
+        keeper: env.keeper.pubkey(),
+        cumulative_funding_index: 0,
+        last_funding_rate_e9: 0,
+        last_mark_price_e6: 0,
+        funding_interval_secs: 3600,
+        max_funding_rate_e9: 1_000_000,
+        created_at: 0,
+        last_updated_at: 0,
+        long_bankruptcy_price_e6: 0,
+        long_adl_outstanding_e6: 0,
+        short_bankruptcy_price_e6: 0,
+        short_adl_outstanding_e6: 0,
+        long_positions: 0,
+        short_positions: 0,
+        reserved: [0; 24],
+    };
+    configure(&mut state);
+    
+    let mut data = state.try_to_vec().unwrap();
+    data.resize(FundingState::MAX_SIZE, 0);
+    env.context.set_account(
+        &env.funding_pda,
+        &AccountSharedData::from(program_account(data, env.program_id)),
+    );
+}
+
+/// 读取Funding State
+pub async fn load_funding(env: &mut TestEnv) -> FundingState {
+    let account = env.context.banks_client.get_account(env.funding_pda).await.unwrap().unwrap();
+    FundingState::deserialize(&mut &account.data[..]).unwrap()
+}
+
+pub fn lock_margin_ix(env: &TestEnv, price_feed: Pubkey, entry_price_e6: i64) -> Instruction {
+    lock_margin_order_ix(env, price_feed, Side::Buy, 1_000_000, entry_price_e6)
+}
+
+/// LockMargin（20x逐仓，指定方向和数量）
+pub fn lock_margin_order_ix(
+    env: &TestEnv,
+    price_feed: Pubkey,
+    side: Side,
+    size_e6: i64,
+    entry_price_e6: i64,
+) -> Instruction {
+    lock_margin_with_mode_ix(env, price_feed, side, size_e6, entry_price_e6, 20, MarginMode::Isolated)
+}
+
+/// 测试使用的账户ID：逐仓 "test_isolated"，全仓 "test_cross"
+pub fn test_account_id(margin_mode: MarginMode) -> &'static str {
+    match margin_mode {
+        MarginMode::Isolated => "test_isolated",
+        MarginMode::Cross => "test_cross",
+    }
+}
+
+/// LockMargin（指定杠杆和保证金模式）
+pub fn lock_margin_with_mode_ix(
+    env: &TestEnv,
+    price_feed: Pubkey,
+    side: Side,
+    size_e6: i64,
+    entry_price_e6: i64,
+    leverage: u32,
+    margin_mode: MarginMode,
+) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let account_id = test_account_id(margin_mode);
+    let (position_pda, _) = find_position_pda(&env.program_id, &user, account_id, "BTC-PERP");
+    let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(position_pda, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new(env.user_usdc, false),
+            AccountMeta::new(env.vault_usdc, false),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(env.market_pda, false),
+            AccountMeta::new_readonly(price_feed, false),
+            AccountMeta::new(env.fee_treasury, false),
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new(cross_margin_pda, false),
+        ],
+        data: TradingInstruction::LockMargin {
+            account_id: account_id.to_string(),
+            market: "BTC-PERP".to_string(),
+            side,
+            size_e6,
+            entry_price_e6,
+            leverage,
+            margin_mode,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// 测试账户在BTC-PERP的单向持仓PDA
+pub fn position_pda(env: &TestEnv, margin_mode: MarginMode) -> Pubkey {
+    let user = env.context.payer.pubkey();
+    find_position_pda(&env.program_id, &user, test_account_id(margin_mode), "BTC-PERP").0
+}
+
+/// UnlockMargin（按预言机价格平仓，position可传入双向持仓PDA）
+pub fn unlock_ix(
+    env: &TestEnv,
+    margin_mode: MarginMode,
+    position: Pubkey,
+    insurance_fund: Pubkey,
+    close_size_e6: i64,
+) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let account_id = test_account_id(margin_mode);
+    let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(position, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new(env.user_usdc, false),
+            AccountMeta::new(env.vault_usdc, false),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(insurance_fund, false),
+            AccountMeta::new_readonly(env.market_pda, false),
+            AccountMeta::new_readonly(env.oracle, false),
+            AccountMeta::new(env.fee_treasury, false),
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new(cross_margin_pda, false),
+        ],
+        data: TradingInstruction::UnlockMargin {
+            account_id: account_id.to_string(),
+            market: "BTC-PERP".to_string(),
+            close_size_e6,
+            exit_price_e6: ORACLE_PRICE_E6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Liquidate（payer作为清算人强平自己的单向持仓，全仓持仓附带Cross Margin Account）
+pub fn liquidate_ix(
+    env: &TestEnv,
+    margin_mode: MarginMode,
+    insurance_fund: Pubkey,
+    liquidation_price_e6: i64,
+) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let account_id = test_account_id(margin_mode);
+    
+    let mut accounts = vec![
+        AccountMeta::new(position_pda(env, margin_mode), false),
+        AccountMeta::new_readonly(user, false),
+        AccountMeta::new(user, true),
+        AccountMeta::new(env.user_usdc, false),
+        AccountMeta::new(env.user_usdc, false),
+        AccountMeta::new(env.vault_usdc, false),
+        AccountMeta::new(insurance_fund, false),
+        AccountMeta::new(env.fee_treasury, false),
+        AccountMeta::new(env.vault_pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(env.market_pda, false),
+        AccountMeta::new_readonly(env.oracle, false),
+        AccountMeta::new(env.funding_pda, false),
+    ];
+    
+    if margin_mode == MarginMode::Cross {
+        let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+        accounts.push(AccountMeta::new(cross_margin_pda, false));
+    }
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts,
+        data: TradingInstruction::Liquidate {
+            account_id: account_id.to_string(),
+            market: "BTC-PERP".to_string(),
+            liquidation_price_e6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Deposit（存入账户抵押品）
+pub fn deposit_ix(env: &TestEnv, account_id: &str, amount_e6: i64) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(cross_margin_pda, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new(env.user_usdc, false),
+            AccountMeta::new(env.vault_usdc, false),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: TradingInstruction::Deposit {
+            account_id: account_id.to_string(),
+            amount_e6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Withdraw（提取账户抵押品）
+pub fn withdraw_ix(env: &TestEnv, account_id: &str, amount_e6: i64) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, account_id);
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(cross_margin_pda, false),
+            AccountMeta::new_readonly(user, true),
+            AccountMeta::new(env.user_usdc, false),
+            AccountMeta::new(env.vault_usdc, false),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: TradingInstruction::Withdraw {
+            account_id: account_id.to_string(),
+            amount_e6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+pub async fn send(env: &mut TestEnv, ix: Instruction) -> Result<(), BanksClientError> {
+    send_with_signers(env, ix, &[]).await
+}
+
+/// 发送交易（payer之外的额外签名者）
+pub async fn send_with_signers(
+    env: &mut TestEnv,
+    ix: Instruction,
+    extra_signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    // 用context记录的blockhash：get_new_latest_blockhash之后保证与之前的交易不同
+    let blockhash = env.context.last_blockhash;
+    let mut signers: Vec<&Keypair> = vec![&env.context.payer];
+    signers.extend_from_slice(extra_signers);
+    
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.context.payer.pubkey()),
+        &signers,
+        blockhash,
+    );
+    env.context.banks_client.process_transaction(tx).await
+}
+
+/// 创建USDC token账户（如Keeper收奖励账户）
+pub async fn create_token_account(env: &mut TestEnv, owner: Pubkey) -> Pubkey {
+    let usdc_mint = load_vault(env).await.usdc_mint;
+    let account = Pubkey::new_unique();
+    env.context.set_account(
+        &account,
+        &AccountSharedData::from(token_account(usdc_mint, owner, 0)),
+    );
+    account
+}
+
+/// 创建Insurance Fund账户（owner = Vault PDA）并登记到Vault
+pub async fn create_insurance_fund(env: &mut TestEnv) -> Pubkey {
+    let vault_pda = env.vault_pda;
+    let insurance_fund = create_token_account(env, vault_pda).await;
+    
+    let account = env.context.banks_client.get_account(vault_pda).await.unwrap().unwrap();
+    let mut vault = load_vault(env).await;
+    vault.insurance_fund_account = insurance_fund;
+    
+    let mut data = vault.try_to_vec().unwrap();
+    data.resize(account.data.len(), 0);
+    env.context.set_account(&vault_pda, &AccountSharedData::from(program_account(data, env.program_id)));
+    insurance_fund
+}
+
+/// 将payer设为管理员，并按指定长度写入Vault（小于TradingVault::SIZE时模拟旧版本账户）
+pub async fn write_vault_with_payer_admin(env: &mut TestEnv, len: usize) {
+    let mut vault = load_vault(env).await;
+    vault.authority = env.context.payer.pubkey();
+    
+    let mut data = vault.try_to_vec().unwrap();
+    data.resize(len, 0);
+    env.context.set_account(
+        &env.vault_pda,
+        &AccountSharedData::from(program_account(data, env.program_id)),
+    );
+}
+
+/// 将payer设为管理员
+pub async fn make_payer_admin(env: &mut TestEnv) {
+    write_vault_with_payer_admin(env, TradingVault::SIZE).await;
+}
+
+/// 读取SPL Token账户余额
+pub async fn token_balance(env: &TestEnv, account: Pubkey) -> u64 {
+    let mut banks_client = env.context.banks_client.clone();
+    let account = banks_client.get_account(account).await.unwrap().unwrap();
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+/// 读取Trading Vault
+pub async fn load_vault(env: &mut TestEnv) -> TradingVault {
+    let mut data = env.context.banks_client.get_account(env.vault_pda).await.unwrap().unwrap().data;
+    
+    // 旧版本Vault补0读取（同load_trading_vault）
+    data.resize(TradingVault::SIZE, 0);
+    TradingVault::deserialize(&mut &data[..]).unwrap()
+}
+
+pub fn assert_trading_error(result: Result<(), BanksClientError>, expected: TradingError) {
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(expected as u32))
+    );
+}
@@ -0,0 +1,211 @@
+//! 条件单测试：止损单在预言机价格越过触发价格后由Keeper执行平仓并获得奖励
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    signature::Signer,
+};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{MarginMode, Side, TriggerDirection, UserPosition},
+    validation::{find_conditional_order_pda, find_cross_margin_pda},
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+fn order_pda(env: &TestEnv, order_id: u64) -> Pubkey {
+    find_conditional_order_pda(&env.program_id, &position_pda(env, MarginMode::Isolated), order_id).0
+}
+
+fn create_order_ix(env: &TestEnv, order_id: u64, trigger_price_e6: i64, trigger_direction: TriggerDirection) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(order_pda(env, order_id), false),
+            AccountMeta::new(env.context.payer.pubkey(), true),
+            AccountMeta::new_readonly(position_pda(env, MarginMode::Isolated), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: TradingInstruction::CreateConditionalOrder {
+            account_id: "test_isolated".to_string(),
+            market: "BTC-PERP".to_string(),
+            order_id,
+            trigger_price_e6,
+            trigger_direction,
+            size_e6: 1_000_000,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+fn cancel_order_ix(env: &TestEnv, order_id: u64) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(order_pda(env, order_id), false),
+            AccountMeta::new(env.context.payer.pubkey(), true),
+        ],
+        data: TradingInstruction::CancelConditionalOrder { order_id }.try_to_vec().unwrap(),
+    }
+}
+
+fn execute_order_ix(env: &TestEnv, order_id: u64, keeper_usdc: Pubkey, insurance_fund: Pubkey) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, "test_isolated");
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(order_pda(env, order_id), false),
+            AccountMeta::new_readonly(env.keeper.pubkey(), true),
+            AccountMeta::new(keeper_usdc, false),
+            AccountMeta::new(position_pda(env, MarginMode::Isolated), false),
+            AccountMeta::new(user, false),
+            AccountMeta::new(env.user_usdc, false),
+            AccountMeta::new(env.vault_usdc, false),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(insurance_fund, false),
+            AccountMeta::new_readonly(env.market_pda, false),
+            AccountMeta::new_readonly(env.oracle, false),
+            AccountMeta::new(env.fee_treasury, false),
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new(cross_margin_pda, false),
+        ],
+        data: TradingInstruction::ExecuteConditionalOrder.try_to_vec().unwrap(),
+    }
+}
+
+async fn account_exists(env: &TestEnv, pubkey: Pubkey) -> bool {
+    let mut banks_client = env.context.banks_client.clone();
+    banks_client.get_account(pubkey).await.unwrap().is_some()
+}
+
+#[tokio::test]
+async fn test_keeper_executes_stop_loss() {
+    let mut env = setup_with(|vault| vault.keeper_fee_bp = 10).await;
+    let keeper = env.keeper.pubkey();
+    let keeper_usdc = create_token_account(&mut env, keeper).await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 开多1 BTC @ $100,000，IM = $5,000；止损 $99,500
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    let ix = create_order_ix(&env, 1, 99_500_000_000, TriggerDirection::Below);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格未越过触发价格
+    let ix = execute_order_ix(&env, 1, keeper_usdc, insurance_fund);
+    let keeper_signer = env.keeper.insecure_clone();
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&keeper_signer]).await,
+        TradingError::ConditionalOrderNotTriggered,
+    );
+    
+    // 价格跌至$99,400：按预言机价格平仓，亏损$600，Keeper奖励 10bp × $99,400 = $99.4
+    // （换新blockhash，避免与上面失败的交易签名相同）
+    env.context.get_new_latest_blockhash().await.unwrap();
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_400_000_000, 10_000_000, now);
+    
+    let ix = execute_order_ix(&env, 1, keeper_usdc, insurance_fund);
+    send_with_signers(&mut env, ix, &[&keeper_signer]).await.unwrap();
+    
+    assert!(!account_exists(&env, position_pda(&env, MarginMode::Isolated)).await);
+    assert!(!account_exists(&env, order_pda(&env, 1)).await);
+    assert_eq!(token_balance(&env, keeper_usdc).await, 99_400_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 699_400_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 0);
+    assert_eq!(vault.total_positions, 0);
+}
+
+#[tokio::test]
+async fn test_cancel_conditional_order() {
+    let mut env = setup().await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 止盈 $101,000
+    let ix = create_order_ix(&env, 7, 101_000_000_000, TriggerDirection::Above);
+    send(&mut env, ix).await.unwrap();
+    assert!(account_exists(&env, order_pda(&env, 7)).await);
+    
+    // 同一编号不能重复创建
+    let ix = create_order_ix(&env, 7, 102_000_000_000, TriggerDirection::Above);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidConditionalOrder);
+    
+    let ix = cancel_order_ix(&env, 7);
+    send(&mut env, ix).await.unwrap();
+    assert!(!account_exists(&env, order_pda(&env, 7)).await);
+}
+
+#[tokio::test]
+async fn test_prefunded_conditional_order_pda_does_not_block_create() {
+    let mut env = setup().await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 他人向条件单PDA预先转入lamports（仍归System Program所有）
+    let pda = order_pda(&env, 7);
+    env.context.set_account(
+        &pda,
+        &AccountSharedData::from(Account {
+            lamports: 1_000_000,
+            owner: system_program::id(),
+            ..Account::default()
+        }),
+    );
+    
+    let ix = create_order_ix(&env, 7, 101_000_000_000, TriggerDirection::Above);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(pda).await.unwrap().unwrap();
+    assert_eq!(account.owner, env.program_id);
+}
+
+#[tokio::test]
+async fn test_flip_invalidates_conditional_order() {
+    let mut env = setup().await;
+    let keeper = env.keeper.pubkey();
+    let keeper_usdc = create_token_account(&mut env, keeper).await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 多头止损挂单后同一秒内反手为空头1 BTC
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    let ix = create_order_ix(&env, 1, 99_500_000_000, TriggerDirection::Below);
+    send(&mut env, ix).await.unwrap();
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 2_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Isolated)).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.side, Side::Sell);
+    assert_eq!(position.position_nonce, 2);
+    assert_eq!(load_vault(&mut env).await.position_nonce, 2);
+    
+    // 价格越过触发价格，但原多头的止损单不能平掉新空头
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_400_000_000, 10_000_000, now);
+    
+    let ix = execute_order_ix(&env, 1, keeper_usdc, insurance_fund);
+    let keeper_signer = env.keeper.insecure_clone();
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&keeper_signer]).await,
+        TradingError::InvalidConditionalOrder,
+    );
+}
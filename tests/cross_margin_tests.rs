@@ -0,0 +1,325 @@
+//! 全仓测试：持仓IM从Cross Margin Account抵押品划转，按账户权益判断强平
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+use solana_sdk::{account::AccountSharedData, signature::Signer};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{CrossMarginAccount, MarginMode, Side},
+    validation::{find_cross_margin_pda, find_hedge_position_pda},
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+fn cross_margin_pda(env: &TestEnv) -> Pubkey {
+    let user = env.context.payer.pubkey();
+    find_cross_margin_pda(&env.program_id, &user, "test_cross").0
+}
+
+/// 100x全仓下单
+fn cross_order_ix(env: &TestEnv, side: Side, size_e6: i64, price_e6: i64) -> Instruction {
+    lock_margin_with_mode_ix(env, env.oracle, side, size_e6, price_e6, 100, MarginMode::Cross)
+}
+
+/// 全仓双向持仓（账户0替换为方向PDA）
+fn cross_hedge_order_ix(env: &TestEnv, side: Side, size_e6: i64) -> (Pubkey, Instruction) {
+    let user = env.context.payer.pubkey();
+    let (pda, _) = find_hedge_position_pda(&env.program_id, &user, "test_cross", "BTC-PERP", side);
+    let mut ix = cross_order_ix(env, side, size_e6, ORACLE_PRICE_E6);
+    ix.accounts[0] = AccountMeta::new(pda, false);
+    (pda, ix)
+}
+
+/// 其他全仓持仓账户：[持仓PDA, Market Config PDA, Price Feed]
+fn other_cross_position(env: &TestEnv, position: Pubkey) -> [AccountMeta; 3] {
+    [
+        AccountMeta::new_readonly(position, false),
+        AccountMeta::new_readonly(env.market_pda, false),
+        AccountMeta::new_readonly(env.oracle, false),
+    ]
+}
+
+async fn load_cross_margin(env: &mut TestEnv) -> CrossMarginAccount {
+    let account = env.context.banks_client.get_account(cross_margin_pda(env)).await.unwrap().unwrap();
+    CrossMarginAccount::deserialize(&mut &account.data[..]).unwrap()
+}
+
+/// 模拟引入账户抵押品之前开立的全仓持仓：IM锁定在持仓自身，Cross Margin Account不存在
+async fn open_legacy_cross_position(env: &mut TestEnv) {
+    let ix = cross_order_ix(env, Side::Buy, 1_000_000, ORACLE_PRICE_E6);
+    send(env, ix).await.unwrap();
+    
+    let pda = cross_margin_pda(env);
+    env.context.set_account(&pda, &AccountSharedData::default());
+}
+
+#[tokio::test]
+async fn test_cross_positions_share_collateral() {
+    let mut env = setup().await;
+    
+    // 开多1 BTC @ $100,000，100x，IM = $1,000 由用户补足
+    let ix = cross_order_ix(&env, Side::Buy, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 1_000_000_000);
+    
+    // 减仓0.5 BTC：释放的IM留在账户中作为可用抵押品
+    let ix = cross_order_ix(&env, Side::Sell, 500_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let cross = load_cross_margin(&mut env).await;
+    assert_eq!(cross.collateral_e6, 1_000_000_000);
+    assert_eq!(cross.locked_im_e6, 500_000_000);
+    assert_eq!(cross.positions, vec![position_pda(&env, MarginMode::Cross)]);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 1_000_000_000);
+    
+    // 再加仓0.5 BTC：IM从可用抵押品划转，用户无需转账
+    let ix = cross_order_ix(&env, Side::Buy, 500_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let cross = load_cross_margin(&mut env).await;
+    assert_eq!(cross.free_collateral_e6(), 0);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 1_000_000_000);
+    
+    // 全部平仓：释放的IM留在账户抵押品中，由Withdraw提取
+    let ix = cross_order_ix(&env, Side::Sell, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let cross = load_cross_margin(&mut env).await;
+    assert_eq!(cross.collateral_e6, 1_000_000_000);
+    assert_eq!(cross.locked_im_e6, 0);
+    assert!(cross.positions.is_empty());
+    
+    let ix = withdraw_ix(&env, "test_cross", 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 0);
+    assert_eq!(vault.total_positions, 0);
+}
+
+#[tokio::test]
+async fn test_lock_margin_rejects_margin_mode_mismatch() {
+    let mut env = setup().await;
+    
+    let ix = cross_order_ix(&env, Side::Buy, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 同一持仓按逐仓加仓
+    let mut ix = cross_order_ix(&env, Side::Buy, 1_000_000, ORACLE_PRICE_E6);
+    ix.data = TradingInstruction::LockMargin {
+        account_id: "test_cross".to_string(),
+        market: "BTC-PERP".to_string(),
+        side: Side::Buy,
+        size_e6: 1_000_000,
+        entry_price_e6: ORACLE_PRICE_E6,
+        leverage: 100,
+        margin_mode: MarginMode::Isolated,
+    }
+    .try_to_vec()
+    .unwrap();
+    assert_trading_error(send(&mut env, ix).await, TradingError::MarginModeMismatch);
+}
+
+#[tokio::test]
+async fn test_cross_liquidation_uses_account_equity() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 持仓0.5 BTC（IM $500，MM $250），账户另有$500可用抵押品
+    let ix = cross_order_ix(&env, Side::Buy, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    let ix = cross_order_ix(&env, Side::Sell, 500_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,000：持仓权益为0，但账户权益$500 >= MM $250
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_000_000_000, 10_000_000, now);
+    
+    let ix = liquidate_ix(&env, MarginMode::Cross, insurance_fund, 99_000_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::PositionNotLiquidatable);
+}
+
+#[tokio::test]
+async fn test_cross_liquidation_below_account_mm() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 持仓1 BTC（IM $1,000，MM $500），没有其他抵押品
+    let ix = cross_order_ix(&env, Side::Buy, 1_000_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,000：账户权益$0 < MM $500
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_000_000_000, 10_000_000, now);
+    
+    let ix = liquidate_ix(&env, MarginMode::Cross, insurance_fund, 99_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Cross)).await.unwrap();
+    assert!(account.is_none());
+    
+    let cross = load_cross_margin(&mut env).await;
+    assert_eq!(cross.collateral_e6, 0);
+    assert_eq!(cross.locked_im_e6, 0);
+    assert!(cross.positions.is_empty());
+    
+    // 保证金亏损全部进入Insurance Fund
+    assert_eq!(token_balance(&env, insurance_fund).await, 1_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 0);
+    assert_eq!(vault.insurance_fund_e6, 1_000_000_000);
+    assert_eq!(vault.total_liquidations, 1);
+}
+
+#[tokio::test]
+async fn test_legacy_cross_position_closes_as_isolated() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 1 BTC @ $100,000，100x，IM = $1,000
+    open_legacy_cross_position(&mut env).await;
+    
+    // 没有账户抵押品：释放的IM直接返还钱包
+    let ix = unlock_ix(&env, MarginMode::Cross, position_pda(&env, MarginMode::Cross), insurance_fund, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Cross)).await.unwrap();
+    assert!(account.is_none());
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 0);
+    assert_eq!(vault.total_positions, 0);
+}
+
+#[tokio::test]
+async fn test_legacy_cross_position_liquidates_as_isolated() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 1 BTC @ $100,000，100x，IM = $1,000，MM = $500
+    open_legacy_cross_position(&mut env).await;
+    
+    // 价格跌至$99,000：持仓权益$0 < MM $500，按逐仓强平
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_000_000_000, 10_000_000, now);
+    
+    let ix = liquidate_ix(&env, MarginMode::Cross, insurance_fund, 99_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Cross)).await.unwrap();
+    assert!(account.is_none());
+    assert_eq!(token_balance(&env, insurance_fund).await, 1_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 0);
+    assert_eq!(vault.insurance_fund_e6, 1_000_000_000);
+    assert_eq!(vault.total_liquidations, 1);
+}
+
+#[tokio::test]
+async fn test_cross_health_marks_other_positions_to_oracle() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 多空各1 BTC @ $100,000，100x，IM各$1,000，MM各$500；账户抵押品$2,000
+    let (long_pda, ix) = cross_hedge_order_ix(&env, Side::Buy, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    let (short_pda, mut ix) = cross_hedge_order_ix(&env, Side::Sell, 1_000_000);
+    ix.accounts.extend(other_cross_position(&env, long_pda));
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$98,900：多头亏损$1,100，空头记录的盈亏仍为0（未UpdatePosition）
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 98_900_000_000, 10_000_000, now);
+    
+    // 其他持仓缺少Market Config和预言机
+    let mut ix = liquidate_ix(&env, MarginMode::Cross, insurance_fund, 98_900_000_000);
+    ix.accounts[0] = AccountMeta::new(long_pda, false);
+    ix.accounts.push(AccountMeta::new_readonly(short_pda, false));
+    assert_trading_error(send(&mut env, ix).await, TradingError::CrossPositionsMismatch);
+    
+    // 空头按预言机价格盈利$1,100：账户权益$2,000 >= MM $1,000
+    let mut ix = liquidate_ix(&env, MarginMode::Cross, insurance_fund, 98_900_000_000);
+    ix.accounts[0] = AccountMeta::new(long_pda, false);
+    ix.accounts.extend(other_cross_position(&env, short_pda));
+    assert_trading_error(send(&mut env, ix).await, TradingError::PositionNotLiquidatable);
+}
+
+#[tokio::test]
+async fn test_cross_liquidation_credits_profitable_position() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    // 多2 BTC、空1 BTC @ $100,000，100x：IM $3,000，MM $1,500；账户抵押品$3,000
+    let (long_pda, ix) = cross_hedge_order_ix(&env, Side::Buy, 2_000_000);
+    send(&mut env, ix).await.unwrap();
+    let (short_pda, mut ix) = cross_hedge_order_ix(&env, Side::Sell, 1_000_000);
+    ix.accounts.extend(other_cross_position(&env, long_pda));
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$98,400：多头亏损$3,200，空头盈利$1,600，账户权益$1,400 < MM $1,500
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 98_400_000_000, 10_000_000, now);
+    
+    // 强平盈利的空头
+    let mut ix = liquidate_ix(&env, MarginMode::Cross, insurance_fund, 98_400_000_000);
+    ix.accounts[0] = AccountMeta::new(short_pda, false);
+    ix.accounts.extend(other_cross_position(&env, long_pda));
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(short_pda).await.unwrap();
+    assert!(account.is_none());
+    
+    // 清算费$5（IM的0.5%），盈利$1,600计入抵押品
+    let cross = load_cross_margin(&mut env).await;
+    assert_eq!(cross.collateral_e6, 4_595_000_000);
+    assert_eq!(cross.locked_im_e6, 2_000_000_000);
+    assert_eq!(cross.positions, vec![long_pda]);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 4_595_000_000);
+    assert_eq!(vault.insurance_fund_e6, 0);
+    assert_eq!(vault.total_liquidations, 1);
+}
+
+#[tokio::test]
+async fn test_cross_open_covers_unrealized_loss() {
+    let mut env = setup().await;
+    
+    // 多1 BTC @ $100,000，100x，IM $1,000
+    let (long_pda, ix) = cross_hedge_order_ix(&env, Side::Buy, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,500：多头未实现亏损$500
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_500_000_000, 10_000_000, now);
+    
+    // 开空1 BTC @ $99,500（IM $995）需要传入其他全仓持仓
+    let user = env.context.payer.pubkey();
+    let (short_pda, _) = find_hedge_position_pda(&env.program_id, &user, "test_cross", "BTC-PERP", Side::Sell);
+    let mut ix = cross_order_ix(&env, Side::Sell, 1_000_000, 99_500_000_000);
+    ix.accounts[0] = AccountMeta::new(short_pda, false);
+    assert_trading_error(send(&mut env, ix.clone()).await, TradingError::CrossPositionsMismatch);
+    
+    // 抵押品$1,000扣除亏损$500后只剩$500，用户补足至占用IM $1,995
+    ix.accounts.extend(other_cross_position(&env, long_pda));
+    send(&mut env, ix).await.unwrap();
+    
+    let cross = load_cross_margin(&mut env).await;
+    assert_eq!(cross.collateral_e6, 2_495_000_000);
+    assert_eq!(cross.locked_im_e6, 1_995_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 2_495_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 2_495_000_000);
+}
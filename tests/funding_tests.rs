@@ -0,0 +1,111 @@
+//! 资金费测试：Keeper按预言机价格累计funding index，持仓在UpdatePosition时结算到locked_usdc
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_sdk::signature::{Keypair, Signer};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::UserPosition,
+    validation::find_position_pda,
+};
+
+fn update_funding_ix(env: &TestEnv, keeper: &Keypair, funding_rate_e9: i64) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new_readonly(keeper.pubkey(), true),
+            AccountMeta::new_readonly(env.market_pda, false),
+            AccountMeta::new_readonly(env.oracle, false),
+            AccountMeta::new_readonly(env.vault_pda, false),
+        ],
+        data: TradingInstruction::UpdateFunding {
+            market: "BTC-PERP".to_string(),
+            funding_rate_e9,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_update_funding_requires_keeper() {
+    let mut env = setup().await;
+    
+    let impostor = Keypair::new();
+    let ix = update_funding_ix(&env, &impostor, 100_000);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&impostor]).await,
+        TradingError::UnauthorizedKeeper,
+    );
+    
+    // 超过单周期上限0.1%
+    let keeper = env.keeper.insecure_clone();
+    let ix = update_funding_ix(&env, &keeper, 1_000_001);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&keeper]).await,
+        TradingError::InvalidFundingRate,
+    );
+}
+
+#[tokio::test]
+async fn test_funding_settled_into_locked_margin() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    let mut env = setup_with(|vault| vault.relayer = relayer_key).await;
+    
+    // 开多1 BTC，IM = $5,000
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 距上次更新一个完整周期，费率0.01%
+    let now = current_time(&env).await;
+    write_funding(&mut env, |state| state.last_updated_at = now - 3600);
+    
+    let keeper = env.keeper.insecure_clone();
+    let ix = update_funding_ix(&env, &keeper, 100_000);
+    send_with_signers(&mut env, ix, &[&keeper]).await.unwrap();
+    
+    // $100,000 × 0.01% = $10 / BTC（e12）
+    let funding = load_funding(&mut env).await;
+    assert_eq!(funding.cumulative_funding_index, 10_000_000_000_000);
+    assert_eq!(funding.last_funding_rate_e9, 100_000);
+    assert_eq!(funding.last_mark_price_e6, ORACLE_PRICE_E6);
+    
+    let user = env.context.payer.pubkey();
+    let (position_pda, _) = find_position_pda(&env.program_id, &user, "test_isolated", "BTC-PERP");
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(position_pda, false),
+            AccountMeta::new_readonly(relayer.pubkey(), true),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new_readonly(env.market_pda, false),
+        ],
+        data: TradingInstruction::UpdatePosition {
+            account_id: "test_isolated".to_string(),
+            market: "BTC-PERP".to_string(),
+            wallet: user,
+            mark_price_e6: ORACLE_PRICE_E6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    };
+    send_with_signers(&mut env, ix, &[&relayer]).await.unwrap();
+    
+    // 多头支付$10
+    let account = env.context.banks_client.get_account(position_pda).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.locked_usdc_e6, 4_990_000_000);
+    assert_eq!(position.cumulative_funding_e6, -10_000_000);
+    assert_eq!(position.last_funding_index, funding.cumulative_funding_index);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 4_990_000_000);
+    assert_eq!(vault.bad_debt_e6, 0);
+}
@@ -0,0 +1,88 @@
+//! 双向持仓测试：Hedge Mode下同一市场同时持有多空，UnlockMargin按方向平仓
+
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+use solana_sdk::signature::Signer;
+use trading_program::{
+    error::TradingError,
+    state::{MarginMode, Side, UserPosition},
+    validation::{find_hedge_position_pda, find_position_pda},
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+fn hedge_position_pda(env: &TestEnv, side: Side) -> Pubkey {
+    let user = env.context.payer.pubkey();
+    find_hedge_position_pda(&env.program_id, &user, "test_isolated", "BTC-PERP", side).0
+}
+
+/// 账户0替换为双向持仓PDA
+fn hedge_order_ix(env: &TestEnv, position: Pubkey, side: Side, size_e6: i64) -> Instruction {
+    let mut ix = lock_margin_order_ix(env, env.oracle, side, size_e6, ORACLE_PRICE_E6);
+    ix.accounts[0] = AccountMeta::new(position, false);
+    ix
+}
+
+async fn load_position(env: &mut TestEnv, pda: Pubkey) -> Option<UserPosition> {
+    let account = env.context.banks_client.get_account(pda).await.unwrap()?;
+    Some(UserPosition::deserialize(&mut &account.data[..]).unwrap())
+}
+
+#[tokio::test]
+async fn test_hedge_mode_holds_long_and_short() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    let long_pda = hedge_position_pda(&env, Side::Buy);
+    let short_pda = hedge_position_pda(&env, Side::Sell);
+    
+    // 多空各1 BTC @ $100,000，IM各$5,000，互不对冲
+    let ix = hedge_order_ix(&env, long_pda, Side::Buy, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    let ix = hedge_order_ix(&env, short_pda, Side::Sell, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let long = load_position(&mut env, long_pda).await.unwrap();
+    assert!(long.hedge_mode);
+    assert_eq!(long.side, Side::Buy);
+    assert_eq!(long.size_e6, 1_000_000);
+    assert_eq!(long.locked_usdc_e6, 5_000_000_000);
+    
+    let short = load_position(&mut env, short_pda).await.unwrap();
+    assert!(short.hedge_mode);
+    assert_eq!(short.side, Side::Sell);
+    assert_eq!(short.size_e6, 1_000_000);
+    assert_eq!(short.locked_usdc_e6, 5_000_000_000);
+    
+    // 单向持仓PDA未被使用
+    let user = env.context.payer.pubkey();
+    let (one_way_pda, _) = find_position_pda(&env.program_id, &user, "test_isolated", "BTC-PERP");
+    assert!(load_position(&mut env, one_way_pda).await.is_none());
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 10_000_000_000);
+    
+    // 平掉空头，多头保留
+    let ix = unlock_ix(&env, MarginMode::Isolated, short_pda, insurance_fund, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    assert!(load_position(&mut env, short_pda).await.is_none());
+    assert_eq!(load_position(&mut env, long_pda).await.unwrap().size_e6, 1_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 5_000_000_000);
+}
+
+#[tokio::test]
+async fn test_hedge_position_pda_must_match_order_side() {
+    let mut env = setup().await;
+    
+    // 卖单传入多头PDA
+    let long_pda = hedge_position_pda(&env, Side::Buy);
+    let ix = hedge_order_ix(&env, long_pda, Side::Sell, 1_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidPositionAccount);
+}
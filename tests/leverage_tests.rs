@@ -0,0 +1,202 @@
+//! 杠杆调整测试：AdjustLeverage重新计算IM和强平价格，加仓杠杆必须与持仓一致
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_sdk::signature::Signer;
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{CrossMarginAccount, MarginMode, Side, UserPosition},
+    validation::find_cross_margin_pda,
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+/// AddMargin/AdjustLeverage共用的账户（逐仓测试持仓）
+fn margin_accounts(env: &TestEnv) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(position_pda(env, MarginMode::Isolated), false),
+        AccountMeta::new_readonly(env.context.payer.pubkey(), true),
+        AccountMeta::new(env.user_usdc, false),
+        AccountMeta::new(env.vault_usdc, false),
+        AccountMeta::new(env.vault_pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(env.market_pda, false),
+        AccountMeta::new_readonly(env.oracle, false),
+        AccountMeta::new_readonly(env.funding_pda, false),
+    ]
+}
+
+fn adjust_leverage_ix(env: &TestEnv, leverage: u32) -> Instruction {
+    let user = env.context.payer.pubkey();
+    let (cross_margin_pda, _) = find_cross_margin_pda(&env.program_id, &user, "test_isolated");
+    let mut accounts = margin_accounts(env);
+    accounts.push(AccountMeta::new(cross_margin_pda, false));
+    
+    Instruction {
+        program_id: env.program_id,
+        accounts,
+        data: TradingInstruction::AdjustLeverage {
+            account_id: "test_isolated".to_string(),
+            market: "BTC-PERP".to_string(),
+            leverage,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+fn add_margin_ix(env: &TestEnv, amount_e6: i64) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: margin_accounts(env),
+        data: TradingInstruction::AddMargin {
+            account_id: "test_isolated".to_string(),
+            market: "BTC-PERP".to_string(),
+            amount_e6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+async fn load_collateral(env: &mut TestEnv) -> CrossMarginAccount {
+    let user = env.context.payer.pubkey();
+    let (pda, _) = find_cross_margin_pda(&env.program_id, &user, "test_isolated");
+    let account = env.context.banks_client.get_account(pda).await.unwrap().unwrap();
+    CrossMarginAccount::deserialize(&mut &account.data[..]).unwrap()
+}
+
+async fn load_position(env: &mut TestEnv) -> UserPosition {
+    let account = env.context.banks_client.get_account(position_pda(env, MarginMode::Isolated)).await.unwrap().unwrap();
+    UserPosition::deserialize(&mut &account.data[..]).unwrap()
+}
+
+#[tokio::test]
+async fn test_lock_margin_rejects_leverage_mismatch() {
+    let mut env = setup().await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 20x持仓按10x加仓
+    let ix = lock_margin_with_mode_ix(&env, env.oracle, Side::Buy, 1_000_000, ORACLE_PRICE_E6, 10, MarginMode::Isolated);
+    assert_trading_error(send(&mut env, ix).await, TradingError::LeverageMismatch);
+}
+
+#[tokio::test]
+async fn test_adjust_leverage_recomputes_margin() {
+    let mut env = setup().await;
+    
+    // 开多1 BTC @ $100,000，20x，IM = $5,000，MM = $500
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 降至10x：IM = $10,000，补足$5,000，强平价格 = 100,000 − (10,000 − 500)
+    let ix = adjust_leverage_ix(&env, 10);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.leverage, 10);
+    assert_eq!(position.locked_usdc_e6, 10_000_000_000);
+    assert_eq!(position.mm_e6, 500_000_000);
+    assert_eq!(position.liquidation_price_e6, 90_500_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 10_000_000_000);
+    
+    // 升至50x：IM = $2,000，释放$8,000
+    let ix = adjust_leverage_ix(&env, 50);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.leverage, 50);
+    assert_eq!(position.locked_usdc_e6, 2_000_000_000);
+    assert_eq!(position.liquidation_price_e6, 98_500_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 2_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 2_000_000_000);
+    
+    // 调整后可按新杠杆加仓
+    let ix = lock_margin_with_mode_ix(&env, env.oracle, Side::Buy, 1_000_000, ORACLE_PRICE_E6, 50, MarginMode::Isolated);
+    send(&mut env, ix).await.unwrap();
+    assert_eq!(load_position(&mut env).await.locked_usdc_e6, 4_000_000_000);
+}
+
+#[tokio::test]
+async fn test_adjust_leverage_rejects_release_below_mark_im() {
+    let mut env = setup().await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,000：未实现亏损$1,000，50x下需保留 $1,980 + $1,000
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_000_000_000, 10_000_000, now);
+    
+    let ix = adjust_leverage_ix(&env, 50);
+    assert_trading_error(send(&mut env, ix).await, TradingError::MarginRatioTooLow);
+}
+
+#[tokio::test]
+async fn test_adjust_leverage_keeps_added_margin() {
+    let mut env = setup().await;
+    
+    // 开多1 BTC @ $100,000，20x，IM = $5,000，追加$1,000
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    let ix = add_margin_ix(&env, 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    // 降至10x：IM差额$5,000，追加的$1,000保留
+    let ix = adjust_leverage_ix(&env, 10);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.locked_usdc_e6, 11_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 11_000_000_000);
+    
+    // 升至50x：只释放IM差额$8,000
+    let ix = adjust_leverage_ix(&env, 50);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.locked_usdc_e6, 3_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 3_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 3_000_000_000);
+}
+
+#[tokio::test]
+async fn test_adjust_leverage_routes_through_collateral() {
+    let mut env = setup().await;
+    
+    // 账户已Deposit $20,000：开仓IM $5,000从抵押品划转
+    let ix = deposit_ix(&env, "test_isolated", 20_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    let collateral_before = load_collateral(&mut env).await.collateral_e6;
+    
+    // 降至10x：差额$5,000从抵押品划转，不动用钱包
+    let ix = adjust_leverage_ix(&env, 10);
+    send(&mut env, ix).await.unwrap();
+    
+    assert_eq!(load_position(&mut env).await.locked_usdc_e6, 10_000_000_000);
+    assert_eq!(load_collateral(&mut env).await.collateral_e6, collateral_before - 5_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 20_000_000_000);
+    
+    // 升至50x：释放的$8,000计入抵押品
+    let ix = adjust_leverage_ix(&env, 50);
+    send(&mut env, ix).await.unwrap();
+    
+    assert_eq!(load_position(&mut env).await.locked_usdc_e6, 2_000_000_000);
+    assert_eq!(load_collateral(&mut env).await.collateral_e6, collateral_before + 3_000_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 20_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 20_000_000_000);
+}
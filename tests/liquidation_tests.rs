@@ -1,25 +1,35 @@
 //! Liquidation（强平）集成测试
 
 use trading_program::utils::*;
-use trading_program::state::UserPosition;
+use trading_program::state::{LiquidationStatus, RiskParams};
 
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    /// 按默认风控参数判断是否可强平（保证金率 < 100%）
+    fn is_liquidatable(margin_ratio_bp: u32) -> bool {
+        RiskParams::DEFAULT.liquidation_status(margin_ratio_bp) == LiquidationStatus::Liquidatable
+    }
+    
+    /// 按默认清算费率（50bp）计算清算手续费
+    fn default_liquidation_fee(locked_usdc_e6: i64) -> i64 {
+        calculate_fee_bp(locked_usdc_e6, RiskParams::DEFAULT.liquidation_fee_bp)
+    }
+    
     #[test]
     fn test_liquidation_fee_calculation() {
         // 测试清算手续费计算（0.5% = 50 bps）
         
         let locked_usdc = 10_000_000; // $10
-        let fee = calculate_liquidation_fee(locked_usdc);
+        let fee = default_liquidation_fee(locked_usdc);
         
         // Fee = 10 × 0.5% = $0.05
         assert_eq!(fee, 50_000);
         
         // 大额测试
         let locked_usdc_large = 1_000_000_000; // $1,000
-        let fee_large = calculate_liquidation_fee(locked_usdc_large);
+        let fee_large = default_liquidation_fee(locked_usdc_large);
         assert_eq!(fee_large, 5_000_000); // $5
         
         println!("✅ Liquidation fee calculation test passed");
@@ -64,11 +74,11 @@ mod tests {
         assert!(is_liquidatable(margin_ratio_bp_2));
         
         // 验证清算费
-        let liquidation_fee = calculate_liquidation_fee(im);
+        let liquidation_fee = default_liquidation_fee(im);
         assert_eq!(liquidation_fee, 50_000); // $0.05
         
         // 用户应得返还
-        let user_return = equity_2 - liquidation_fee as i64;
+        let user_return = equity_2 - liquidation_fee;
         assert_eq!(user_return, 4_450_000); // $4.45
         
         println!("✅ Liquidation loss scenario test passed");
@@ -97,7 +107,8 @@ mod tests {
         assert_eq!(user_return, 0);
         
         // 清算人收费
-        let liquidation_fee = calculate_liquidation_fee(im);
+        let liquidation_fee = default_liquidation_fee(im);
+        assert_eq!(liquidation_fee, 50_000); // $0.05
         
         // 损失进Insurance Fund
         let loss_to_insurance = if equity < 0 { -equity } else { 0 };
@@ -106,6 +117,30 @@ mod tests {
         println!("✅ Liquidation total loss scenario test passed");
     }
     
+    #[test]
+    fn test_liquidation_shortfall_waterfall() {
+        // 场景: 穿仓$2，保证金$10已全部扣除
+        let im = 10_000_000; // $10
+        let unrealized_pnl = -12_000_000; // -$12
+        let equity = im + unrealized_pnl; // -$2
+        let shortfall = -equity;
+        
+        // Insurance Fund充足：全部由Insurance Fund承担
+        let (insurance, bad_debt) = split_shortfall(shortfall, 5_000_000);
+        assert_eq!(insurance, 2_000_000);
+        assert_eq!(bad_debt, 0);
+        
+        // Insurance Fund只有$0.5：剩余$1.5记为坏账
+        let (insurance, bad_debt) = split_shortfall(shortfall, 500_000);
+        assert_eq!(insurance, 500_000);
+        assert_eq!(bad_debt, 1_500_000);
+        
+        // 每一级之和 = 总亏损
+        assert_eq!(im + insurance + bad_debt, -unrealized_pnl);
+        
+        println!("✅ Liquidation shortfall waterfall test passed");
+    }
+    
     #[test]
     fn test_liquidation_edge_cases() {
         // 边界测试
@@ -1,7 +1,7 @@
 //! Lock Margin和Unlock Margin集成测试
 
 use trading_program::utils::*;
-use trading_program::state::Side;
+use trading_program::state::{LiquidationStatus, RiskParams, Side};
 
 #[test]
 fn test_calculate_initial_margin() {
@@ -85,7 +85,7 @@ fn test_calculate_liquidation_fee() {
     // 测试清算手续费计算 (0.5%)
     
     let locked_usdc = 5_000_000; // $5
-    let fee = calculate_liquidation_fee(locked_usdc);
+    let fee = calculate_fee_bp(locked_usdc, RiskParams::DEFAULT.liquidation_fee_bp);
     
     // Fee = 5 × 0.5% = $0.025
     assert_eq!(fee, 25_000); // $0.025 (e6)
@@ -108,14 +108,15 @@ fn test_validate_leverage() {
 
 #[test]
 fn test_is_liquidatable() {
-    // 测试强平判断
+    // 测试强平判断（默认风控参数：保证金率 < 100%）
+    let risk_params = RiskParams::DEFAULT;
     
-    assert!(!is_liquidatable(15000)); // 150% - 不可强平
-    assert!(!is_liquidatable(11000)); // 110% - 不可强平
-    assert!(!is_liquidatable(10000)); // 100% - 不可强平（刚好）
-    assert!(is_liquidatable(9999)); // 99.99% - 可强平 ✅
-    assert!(is_liquidatable(5000)); // 50% - 可强平 ✅
-    assert!(is_liquidatable(0)); // 0% - 可强平 ✅
+    assert_ne!(risk_params.liquidation_status(15000), LiquidationStatus::Liquidatable); // 150% - 不可强平
+    assert_ne!(risk_params.liquidation_status(11000), LiquidationStatus::Liquidatable); // 110% - 不可强平
+    assert_ne!(risk_params.liquidation_status(10000), LiquidationStatus::Liquidatable); // 100% - 不可强平（刚好）
+    assert_eq!(risk_params.liquidation_status(9999), LiquidationStatus::Liquidatable); // 99.99% - 可强平 ✅
+    assert_eq!(risk_params.liquidation_status(5000), LiquidationStatus::Liquidatable); // 50% - 可强平 ✅
+    assert_eq!(risk_params.liquidation_status(0), LiquidationStatus::Liquidatable); // 0% - 可强平 ✅
     
     println!("✅ 强平判断逻辑正确");
 }
@@ -0,0 +1,112 @@
+//! 逐仓保证金调整测试：AddMargin/RemoveMargin更新锁定保证金和强平价格
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_sdk::signature::Signer;
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{MarginMode, UserPosition},
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+fn adjust_margin_ix(env: &TestEnv, data: TradingInstruction) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(position_pda(env, MarginMode::Isolated), false),
+            AccountMeta::new_readonly(env.context.payer.pubkey(), true),
+            AccountMeta::new(env.user_usdc, false),
+            AccountMeta::new(env.vault_usdc, false),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.market_pda, false),
+            AccountMeta::new_readonly(env.oracle, false),
+            AccountMeta::new_readonly(env.funding_pda, false),
+        ],
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+fn add_margin_ix(env: &TestEnv, amount_e6: i64) -> Instruction {
+    adjust_margin_ix(env, TradingInstruction::AddMargin {
+        account_id: "test_isolated".to_string(),
+        market: "BTC-PERP".to_string(),
+        amount_e6,
+    })
+}
+
+fn remove_margin_ix(env: &TestEnv, amount_e6: i64) -> Instruction {
+    adjust_margin_ix(env, TradingInstruction::RemoveMargin {
+        account_id: "test_isolated".to_string(),
+        market: "BTC-PERP".to_string(),
+        amount_e6,
+    })
+}
+
+async fn load_position(env: &mut TestEnv) -> UserPosition {
+    let account = env.context.banks_client.get_account(position_pda(env, MarginMode::Isolated)).await.unwrap().unwrap();
+    UserPosition::deserialize(&mut &account.data[..]).unwrap()
+}
+
+#[tokio::test]
+async fn test_add_and_remove_margin() {
+    let mut env = setup().await;
+    
+    // 开多1 BTC @ $100,000，IM = $5,000，MM = $500
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 追加$1,000：强平价格 = 100,000 − (6,000 − 500) = $94,500
+    let ix = add_margin_ix(&env, 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.locked_usdc_e6, 6_000_000_000);
+    assert_eq!(position.liquidation_price_e6, 94_500_000_000);
+    assert_eq!(position.margin_ratio_bp, 120_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 6_000_000_000);
+    
+    // 按标记价格IM = $5,000，最多减少$1,000
+    let ix = remove_margin_ix(&env, 1_000_000_001);
+    assert_trading_error(send(&mut env, ix).await, TradingError::MarginRatioTooLow);
+    
+    let ix = remove_margin_ix(&env, 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.locked_usdc_e6, 5_000_000_000);
+    assert_eq!(position.liquidation_price_e6, 95_500_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 5_000_000_000);
+}
+
+#[tokio::test]
+async fn test_remove_margin_counts_unrealized_loss() {
+    let mut env = setup().await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    let ix = add_margin_ix(&env, 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$99,500：未实现亏损$500，IM $4,975 → 最多减少$525
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 99_500_000_000, 10_000_000, now);
+    
+    let ix = remove_margin_ix(&env, 526_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::MarginRatioTooLow);
+    
+    let ix = remove_margin_ix(&env, 525_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let position = load_position(&mut env).await;
+    assert_eq!(position.locked_usdc_e6, 5_475_000_000);
+    assert_eq!(position.mark_price_e6, 99_500_000_000);
+}
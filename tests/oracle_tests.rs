@@ -0,0 +1,77 @@
+//! 预言机价格校验测试：在program-test中写入本地mock Pyth价格账户
+
+mod common;
+
+use common::*;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{account::AccountSharedData, signature::Signer};
+use trading_program::{error::TradingError, validation::find_position_pda};
+
+#[tokio::test]
+async fn test_lock_margin_within_oracle_band() {
+    let mut env = setup().await;
+    
+    // 偏离0.5%（band 1%）
+    let ix = lock_margin_ix(&env, env.oracle, 100_500_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let user = env.context.payer.pubkey();
+    let (position_pda, _) = find_position_pda(&env.program_id, &user, "test_isolated", "BTC-PERP");
+    let position = env.context.banks_client.get_account(position_pda).await.unwrap();
+    assert!(position.is_some());
+}
+
+#[tokio::test]
+async fn test_lock_margin_rejects_price_outside_band() {
+    let mut env = setup().await;
+    
+    // 偏离5%（band 1%）
+    let ix = lock_margin_ix(&env, env.oracle, 105_000_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::OraclePriceDeviation);
+    
+    let ix = lock_margin_ix(&env, env.oracle, 95_000_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::OraclePriceDeviation);
+}
+
+#[tokio::test]
+async fn test_lock_margin_rejects_spoofed_oracle() {
+    let mut env = setup().await;
+    
+    // 攻击者自建的价格账户（布局合法，但不是市场配置的预言机）
+    let fake_oracle = Pubkey::new_unique();
+    let now = current_time(&env).await;
+    env.context.set_account(
+        &fake_oracle,
+        &AccountSharedData::from(program_account(
+            mock_pyth_price_data(200_000_000_000, 0, now),
+            env.oracle_owner,
+        )),
+    );
+    
+    let ix = lock_margin_ix(&env, fake_oracle, 200_000_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidOracleAccount);
+}
+
+#[tokio::test]
+async fn test_lock_margin_rejects_stale_oracle() {
+    let mut env = setup().await;
+    
+    // 发布时间超过60秒
+    let now = current_time(&env).await;
+    write_oracle(&mut env, ORACLE_PRICE_E6, 10_000_000, now - 120);
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    assert_trading_error(send(&mut env, ix).await, TradingError::OraclePriceStale);
+}
+
+#[tokio::test]
+async fn test_lock_margin_rejects_wide_confidence() {
+    let mut env = setup().await;
+    
+    // 置信区间2%（上限1%）
+    let now = current_time(&env).await;
+    write_oracle(&mut env, ORACLE_PRICE_E6, 2_000_000_000, now);
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    assert_trading_error(send(&mut env, ix).await, TradingError::OracleConfidenceTooWide);
+}
@@ -0,0 +1,57 @@
+//! 部分强平测试：只平掉使保证金率恢复到目标值所需的数量，被平部分的亏损进Insurance Fund
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_sdk::account::AccountSharedData;
+use trading_program::state::{MarginMode, MarketConfig, UserPosition};
+
+/// 设置市场部分强平目标保证金率
+async fn set_partial_liquidation_target(env: &mut TestEnv, target_bp: u32) {
+    let account = env.context.banks_client.get_account(env.market_pda).await.unwrap().unwrap();
+    let mut config = MarketConfig::deserialize(&mut &account.data[..]).unwrap();
+    config.partial_liquidation_target_bp = target_bp;
+    
+    let mut data = config.try_to_vec().unwrap();
+    data.resize(MarketConfig::MAX_SIZE, 0);
+    env.context.set_account(
+        &env.market_pda,
+        &AccountSharedData::from(program_account(data, env.program_id)),
+    );
+}
+
+#[tokio::test]
+async fn test_partial_liquidation_moves_loss_to_insurance_fund() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    set_partial_liquidation_target(&mut env, 15000).await;
+    
+    // 开多1 BTC @ $100,000，20x，IM = $5,000，MM = $500
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 价格跌至$95,400：亏损$4,600，权益$400 < MM $500
+    let now = current_time(&env).await;
+    write_oracle(&mut env, 95_400_000_000, 10_000_000, now);
+    
+    // 平掉0.483 BTC使保证金率回到150%：实现亏损$2,221.8，清算费 50bp × $2,415 = $12.075
+    let ix = liquidate_ix(&env, MarginMode::Isolated, insurance_fund, 95_400_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Isolated)).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.size_e6, 517_000);
+    assert_eq!(position.locked_usdc_e6, 2_766_125_000);
+    
+    // 被平部分的亏损转入Insurance Fund
+    assert_eq!(token_balance(&env, insurance_fund).await, 2_221_800_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.insurance_fund_e6, 2_221_800_000);
+    assert_eq!(vault.total_locked_usdc_e6, 2_766_125_000);
+    assert_eq!(vault.total_liquidations, 1);
+    
+    // Vault中剩余的USDC与剩余持仓锁定的保证金一致
+    assert_eq!(token_balance(&env, env.vault_usdc).await, 2_766_125_000);
+}
@@ -0,0 +1,157 @@
+//! 紧急暂停测试：Guardian暂停后拒绝资金相关指令，只有管理员可以恢复
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::signature::{Keypair, Signer};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::TradingVault,
+};
+
+fn set_guardian_ix(env: &TestEnv, guardian: Pubkey) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new(env.context.payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: TradingInstruction::SetGuardian { guardian }.try_to_vec().unwrap(),
+    }
+}
+
+fn pause_ix(env: &TestEnv, guardian: &Keypair) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(guardian.pubkey(), true),
+        ],
+        data: TradingInstruction::Pause.try_to_vec().unwrap(),
+    }
+}
+
+fn unpause_ix(env: &TestEnv, admin: &Keypair) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(admin.pubkey(), true),
+        ],
+        data: TradingInstruction::Unpause.try_to_vec().unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_guardian_pause_and_admin_unpause() {
+    let mut env = setup().await;
+    make_payer_admin(&mut env).await;
+    let guardian = env.keeper.insecure_clone();
+    
+    let ix = set_guardian_ix(&env, guardian.pubkey());
+    send(&mut env, ix).await.unwrap();
+    
+    let ix = pause_ix(&env, &guardian);
+    send_with_signers(&mut env, ix, &[&guardian]).await.unwrap();
+    assert!(load_vault(&mut env).await.paused);
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    assert_trading_error(send(&mut env, ix).await, TradingError::ProgramPaused);
+    
+    let ix = deposit_ix(&env, "test_isolated", 1_000_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::ProgramPaused);
+    
+    // Guardian不能恢复
+    let ix = unpause_ix(&env, &guardian);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&guardian]).await,
+        TradingError::InvalidAuthority,
+    );
+    
+    let admin = env.context.payer.insecure_clone();
+    let ix = unpause_ix(&env, &admin);
+    send(&mut env, ix).await.unwrap();
+    assert!(!load_vault(&mut env).await.paused);
+    
+    // 换新blockhash，避免与上面失败的交易签名相同
+    env.context.get_new_latest_blockhash().await.unwrap();
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_pause_requires_guardian() {
+    let mut env = setup().await;
+    
+    // 默认Guardian为管理员，其他签名者不能暂停
+    let other = Keypair::new();
+    let ix = pause_ix(&env, &other);
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&other]).await,
+        TradingError::UnauthorizedGuardian,
+    );
+}
+
+#[tokio::test]
+async fn test_set_guardian_resizes_old_vault() {
+    let mut env = setup().await;
+    write_vault_with_payer_admin(&mut env, 272).await;
+    
+    let guardian = Pubkey::new_unique();
+    let ix = set_guardian_ix(&env, guardian);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(env.vault_pda).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), TradingVault::SIZE);
+    
+    let vault = TradingVault::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(vault.guardian, guardian);
+    assert!(!vault.paused);
+}
+
+#[tokio::test]
+async fn test_old_vault_usable_before_resize() {
+    // 旧Vault的272字节之后没有guardian
+    let mut env = setup_with(|vault| vault.guardian = Pubkey::default()).await;
+    write_vault_with_payer_admin(&mut env, 272).await;
+    
+    // 部署新程序后、SetGuardian之前，旧Vault上不涉及新增字段的指令照常执行
+    let ix = deposit_ix(&env, "test_isolated", 1_000_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let mut data = env.context.banks_client.get_account(env.vault_pda).await.unwrap().unwrap().data;
+    assert_eq!(data.len(), 272);
+    
+    data.resize(TradingVault::SIZE, 0);
+    let vault = TradingVault::deserialize(&mut &data[..]).unwrap();
+    assert_eq!(vault.total_locked_usdc_e6, 1_000_000_000);
+    assert_eq!(vault.guardian, Pubkey::default());
+}
+
+#[tokio::test]
+async fn test_pause_old_vault_requires_resize() {
+    let mut env = setup_with(|vault| vault.guardian = Pubkey::default()).await;
+    write_vault_with_payer_admin(&mut env, 272).await;
+    let admin = env.context.payer.insecure_clone();
+    
+    // 旧Vault没有Guardian，暂停前必须先SetGuardian扩容
+    let ix = pause_ix(&env, &admin);
+    assert_trading_error(send(&mut env, ix).await, TradingError::VaultResizeRequired);
+    
+    let ix = set_guardian_ix(&env, admin.pubkey());
+    send(&mut env, ix).await.unwrap();
+    
+    // 换新blockhash，避免与上面失败的交易签名相同
+    env.context.get_new_latest_blockhash().await.unwrap();
+    let ix = pause_ix(&env, &admin);
+    send(&mut env, ix).await.unwrap();
+    assert!(load_vault(&mut env).await.paused);
+}
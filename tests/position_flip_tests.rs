@@ -0,0 +1,72 @@
+//! 反向下单测试：LockMargin与现有持仓对冲，实现盈亏后剩余部分反向开仓
+
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use trading_program::state::{MarginMode, Side, UserPosition};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+#[tokio::test]
+async fn test_lock_margin_opposite_side_flips_position() {
+    let mut env = setup().await;
+    
+    // 开多1 BTC @ $100,000，IM = $5,000
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 卖出3 BTC @ $100,500：平多1 BTC（盈利$500），反向开空2 BTC（IM = $10,050）
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 3_000_000, 100_500_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Isolated)).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.side, Side::Sell);
+    assert_eq!(position.size_e6, 2_000_000);
+    assert_eq!(position.entry_price_e6, 100_500_000_000);
+    assert_eq!(position.locked_usdc_e6, 10_050_000_000);
+    assert_eq!(position.realized_pnl_e6, 500_000_000);
+    
+    // 净额结算：补足 $10,050 − ($5,000 + $500) = $4,550
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000 - 4_550_000_000);
+    // Vault支付对冲盈利$500
+    assert_eq!(token_balance(&env, env.vault_usdc).await, 9_550_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 10_050_000_000);
+    assert_eq!(vault.cumulative_pnl_e6, 500_000_000);
+    assert_eq!(vault.total_positions, 1);
+    assert_eq!(vault.total_trades, 2);
+}
+
+#[tokio::test]
+async fn test_lock_margin_opposite_side_closes_position() {
+    let mut env = setup().await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 卖出0.5 BTC @ $99,500：部分对冲，亏损$250，释放IM $2,500
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 500_000, 99_500_000_000);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Isolated)).await.unwrap().unwrap();
+    let position = UserPosition::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(position.side, Side::Buy);
+    assert_eq!(position.size_e6, 500_000);
+    assert_eq!(position.locked_usdc_e6, 2_500_000_000);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 5_000_000_000 + 2_250_000_000);
+    
+    // 卖出剩余0.5 BTC：持仓关闭
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 500_000, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(position_pda(&env, MarginMode::Isolated)).await.unwrap();
+    assert!(account.is_none());
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - 250_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_locked_usdc_e6, 0);
+    assert_eq!(vault.total_positions, 0);
+}
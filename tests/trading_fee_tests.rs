@@ -0,0 +1,67 @@
+//! 交易手续费测试：开仓按成交名义价值收取Maker/Taker手续费并更新Vault统计
+
+mod common;
+
+use common::*;
+use solana_program::instruction::AccountMeta;
+use solana_sdk::signature::{Keypair, Signer};
+
+const NOTIONAL_E6: i64 = 100_000_000_000; // 1 × $100,000
+const USER_BALANCE: u64 = 1_000_000_000_000;
+const IM_E6: u64 = 5_000_000_000; // 20x
+
+#[tokio::test]
+async fn test_lock_margin_charges_taker_fee() {
+    let mut env = setup_with(|vault| {
+        vault.maker_fee_bp = 2;
+        vault.taker_fee_bp = 5;
+    })
+    .await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 5bp × $100,000 = $50
+    let fee_e6 = 50_000_000;
+    assert_eq!(token_balance(&env, env.fee_treasury).await, fee_e6);
+    assert_eq!(token_balance(&env, env.vault_usdc).await, IM_E6);
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE - IM_E6 - fee_e6);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_trades, 1);
+    assert_eq!(vault.cumulative_volume_e6, NOTIONAL_E6);
+    assert_eq!(vault.cumulative_fees_e6, fee_e6 as i64);
+    assert_eq!(vault.fee_treasury_e6, fee_e6 as i64);
+}
+
+#[tokio::test]
+async fn test_lock_margin_maker_fee_requires_relayer() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    let mut env = setup_with(|vault| {
+        vault.relayer = relayer_key;
+        vault.maker_fee_bp = 2;
+        vault.taker_fee_bp = 5;
+    })
+    .await;
+    
+    // 非Relayer签名不能享受Maker费率
+    let impostor = Keypair::new();
+    let mut ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    ix.accounts.push(AccountMeta::new_readonly(impostor.pubkey(), true));
+    send_with_signers(&mut env, ix, &[&impostor]).await.unwrap();
+    
+    assert_eq!(token_balance(&env, env.fee_treasury).await, 50_000_000);
+    
+    // Relayer联合签名 → 2bp × $100,000 = $20
+    let mut ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    ix.accounts.push(AccountMeta::new_readonly(relayer.pubkey(), true));
+    send_with_signers(&mut env, ix, &[&relayer]).await.unwrap();
+    
+    assert_eq!(token_balance(&env, env.fee_treasury).await, 70_000_000);
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.total_trades, 2);
+    assert_eq!(vault.cumulative_volume_e6, NOTIONAL_E6 * 2);
+    assert_eq!(vault.cumulative_fees_e6, 70_000_000);
+}
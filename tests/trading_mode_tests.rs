@@ -0,0 +1,159 @@
+//! 交易模式测试：ReduceOnly拒绝LockMargin但允许平仓，Halted拒绝全部交易指令
+
+mod common;
+
+use borsh::BorshSerialize;
+use common::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_sdk::signature::{Keypair, Signer};
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{MarginMode, Side, TradingMode},
+};
+
+const USER_BALANCE: u64 = 1_000_000_000_000;
+
+fn set_trading_mode_ix(env: &TestEnv, mode: TradingMode) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new_readonly(env.context.payer.pubkey(), true),
+        ],
+        data: TradingInstruction::SetTradingMode { mode }.try_to_vec().unwrap(),
+    }
+}
+
+fn set_market_trading_mode_ix(env: &TestEnv, mode: TradingMode) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.market_pda, false),
+            AccountMeta::new_readonly(env.context.payer.pubkey(), true),
+            AccountMeta::new_readonly(env.vault_pda, false),
+        ],
+        data: TradingInstruction::SetMarketTradingMode {
+            market: "BTC-PERP".to_string(),
+            mode,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_reduce_only_refuses_lock_margin_and_allows_close() {
+    let mut env = setup().await;
+    make_payer_admin(&mut env).await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    let position = position_pda(&env, MarginMode::Isolated);
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    let ix = set_trading_mode_ix(&env, TradingMode::ReduceOnly);
+    send(&mut env, ix).await.unwrap();
+    assert_eq!(load_vault(&mut env).await.trading_mode, TradingMode::ReduceOnly);
+    
+    // 反向下单也会被拒绝，减仓使用UnlockMargin
+    let ix = lock_margin_order_ix(&env, env.oracle, Side::Sell, 1_000_000, ORACLE_PRICE_E6);
+    assert_trading_error(send(&mut env, ix).await, TradingError::TradingReduceOnly);
+    
+    let ix = unlock_ix(&env, MarginMode::Isolated, position, insurance_fund, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+    assert_eq!(token_balance(&env, env.user_usdc).await, USER_BALANCE);
+}
+
+#[tokio::test]
+async fn test_market_halted_refuses_close() {
+    let mut env = setup().await;
+    make_payer_admin(&mut env).await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    let position = position_pda(&env, MarginMode::Isolated);
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 市场Halted：全局Active时同样生效
+    let ix = set_market_trading_mode_ix(&env, TradingMode::Halted);
+    send(&mut env, ix).await.unwrap();
+    
+    let ix = unlock_ix(&env, MarginMode::Isolated, position, insurance_fund, 1_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::TradingHalted);
+    
+    // 恢复后可以平仓（换新blockhash，避免与上面失败的交易签名相同）
+    let ix = set_market_trading_mode_ix(&env, TradingMode::Active);
+    send(&mut env, ix).await.unwrap();
+    env.context.get_new_latest_blockhash().await.unwrap();
+    
+    let ix = unlock_ix(&env, MarginMode::Isolated, position, insurance_fund, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_halted_checks_both_modes_for_updates() {
+    let relayer = Keypair::new();
+    let relayer_key = relayer.pubkey();
+    let mut env = setup_with(|vault| vault.relayer = relayer_key).await;
+    make_payer_admin(&mut env).await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // 全局Halted：UpdateFunding同样被拒绝
+    let ix = set_trading_mode_ix(&env, TradingMode::Halted);
+    send(&mut env, ix).await.unwrap();
+    
+    let keeper = env.keeper.insecure_clone();
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new_readonly(keeper.pubkey(), true),
+            AccountMeta::new_readonly(env.market_pda, false),
+            AccountMeta::new_readonly(env.oracle, false),
+            AccountMeta::new_readonly(env.vault_pda, false),
+        ],
+        data: TradingInstruction::UpdateFunding {
+            market: "BTC-PERP".to_string(),
+            funding_rate_e9: 0,
+        }
+        .try_to_vec()
+        .unwrap(),
+    };
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&keeper]).await,
+        TradingError::TradingHalted,
+    );
+    
+    // 全局恢复、市场Halted：UpdatePosition同样被拒绝
+    let ix = set_trading_mode_ix(&env, TradingMode::Active);
+    send(&mut env, ix).await.unwrap();
+    let ix = set_market_trading_mode_ix(&env, TradingMode::Halted);
+    send(&mut env, ix).await.unwrap();
+    
+    let user = env.context.payer.pubkey();
+    let ix = Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(position_pda(&env, MarginMode::Isolated), false),
+            AccountMeta::new_readonly(relayer.pubkey(), true),
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new(env.funding_pda, false),
+            AccountMeta::new_readonly(env.market_pda, false),
+        ],
+        data: TradingInstruction::UpdatePosition {
+            account_id: "test_isolated".to_string(),
+            market: "BTC-PERP".to_string(),
+            wallet: user,
+            mark_price_e6: ORACLE_PRICE_E6,
+        }
+        .try_to_vec()
+        .unwrap(),
+    };
+    assert_trading_error(
+        send_with_signers(&mut env, ix, &[&relayer]).await,
+        TradingError::TradingHalted,
+    );
+}
@@ -0,0 +1,114 @@
+//! 金库账户测试：Insurance Fund和Fee Treasury只接受Vault中登记的账户
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::signature::Signer;
+use trading_program::{
+    error::TradingError,
+    instruction::TradingInstruction,
+    state::{MarginMode, TradingVault},
+};
+
+fn set_treasury_accounts_ix(env: &TestEnv, insurance_fund: Pubkey, fee_treasury: Pubkey) -> Instruction {
+    Instruction {
+        program_id: env.program_id,
+        accounts: vec![
+            AccountMeta::new(env.vault_pda, false),
+            AccountMeta::new(env.context.payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(insurance_fund, false),
+            AccountMeta::new_readonly(fee_treasury, false),
+        ],
+        data: TradingInstruction::SetTreasuryAccounts.try_to_vec().unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_set_treasury_accounts() {
+    let mut env = setup().await;
+    make_payer_admin(&mut env).await;
+    let vault_pda = env.vault_pda;
+    let payer = env.context.payer.pubkey();
+    let insurance_fund = create_token_account(&mut env, vault_pda).await;
+    let user_owned = create_token_account(&mut env, payer).await;
+    
+    // 不归Vault PDA所有
+    let ix = set_treasury_accounts_ix(&env, user_owned, env.fee_treasury);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidInsuranceFundAccount);
+    
+    // 不能复用Vault USDC账户
+    let ix = set_treasury_accounts_ix(&env, env.vault_usdc, env.fee_treasury);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidInsuranceFundAccount);
+    
+    let ix = set_treasury_accounts_ix(&env, insurance_fund, env.vault_usdc);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidFeeTreasuryAccount);
+    
+    let ix = set_treasury_accounts_ix(&env, insurance_fund, env.fee_treasury);
+    send(&mut env, ix).await.unwrap();
+    
+    let vault = load_vault(&mut env).await;
+    assert_eq!(vault.insurance_fund_account, insurance_fund);
+    assert_eq!(vault.fee_treasury_account, env.fee_treasury);
+}
+
+#[tokio::test]
+async fn test_rejects_unregistered_insurance_fund() {
+    let mut env = setup().await;
+    let insurance_fund = create_insurance_fund(&mut env).await;
+    
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+    
+    // owner为Vault PDA但未登记的账户（包括Vault USDC账户）
+    let vault_pda = env.vault_pda;
+    let spoofed = create_token_account(&mut env, vault_pda).await;
+    let position = position_pda(&env, MarginMode::Isolated);
+    
+    let ix = unlock_ix(&env, MarginMode::Isolated, position, spoofed, 1_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidInsuranceFundAccount);
+    
+    let ix = unlock_ix(&env, MarginMode::Isolated, position, env.vault_usdc, 1_000_000);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidInsuranceFundAccount);
+    
+    let ix = unlock_ix(&env, MarginMode::Isolated, position, insurance_fund, 1_000_000);
+    send(&mut env, ix).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_set_treasury_accounts_resizes_old_vault() {
+    // 旧Vault的272字节之后没有guardian和金库账户
+    let mut env = setup_with(|vault| {
+        vault.guardian = Pubkey::default();
+        vault.fee_treasury_account = Pubkey::default();
+    })
+    .await;
+    write_vault_with_payer_admin(&mut env, 272).await;
+    
+    // 登记之前不接受任何Fee Treasury
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    assert_trading_error(send(&mut env, ix).await, TradingError::InvalidFeeTreasuryAccount);
+    
+    let vault_pda = env.vault_pda;
+    let insurance_fund = create_token_account(&mut env, vault_pda).await;
+    let ix = set_treasury_accounts_ix(&env, insurance_fund, env.fee_treasury);
+    send(&mut env, ix).await.unwrap();
+    
+    let account = env.context.banks_client.get_account(env.vault_pda).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), TradingVault::SIZE);
+    
+    let vault = TradingVault::deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(vault.insurance_fund_account, insurance_fund);
+    assert_eq!(vault.fee_treasury_account, env.fee_treasury);
+    
+    // 换新blockhash，避免与上面失败的交易签名相同
+    env.context.get_new_latest_blockhash().await.unwrap();
+    let ix = lock_margin_ix(&env, env.oracle, ORACLE_PRICE_E6);
+    send(&mut env, ix).await.unwrap();
+}